@@ -1,12 +1,50 @@
-use std::io::Error;
+use std::{collections::HashSet, io::Error};
 
-use super::{block_header::BlockHeader, transaction::Transaction};
+use super::{
+    block_header::BlockHeader,
+    transaction::Transaction,
+    transaction_validation::{check_transaction_sanity, legacy_sigops, TransactionError, COIN},
+};
 use crate::{
-    helpers::auxiliar_functions::u8_to_hex_string,
-    merkle_tree::merkle_tree_calculator::{calculate_merkle_tree, MerkleTreeError},
+    configuration::chain_params::{chain_params, ChainParams},
+    helpers::auxiliar_functions::serialize_var_int,
+    merkle_tree::merkle_tree_calculator::{
+        calculate_merkle_tree, calculate_merkle_tree_with_mutation, merkle_node, MerkleTreeError,
+    },
+    script::{
+        interpreter::encode_script_num,
+        opcodes::{OP_0, OP_1},
+        script_parser::push_data,
+    },
 };
 use bitcoin_hashes::{sha256d, Hash};
-const GENESIS_BLOCK_HASH: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Start of the coinbase output script that commits to the witness data of the block (BIP141)
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// Maximum weight of a block (BIP141)
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+
+/// Maximum cost of the signature operations of a block
+pub const MAX_BLOCK_SIGOPS_COST: u32 = 80_000;
+
+/// Weight of a byte that is not witness data
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+/// #ENUM BlockError
+/// Represents the reasons a block breaks the consensus rules
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    NoTransactions,
+    FirstTransactionNotCoinbase,
+    MultipleCoinbases,
+    DuplicateTransactions,
+    BadWeight,
+    BadSigops,
+    BadCoinbaseHeight,
+    BadCoinbaseAmount,
+    InvalidTransaction(TransactionError),
+}
 
 #[derive(Clone, Debug)]
 pub struct Block {
@@ -26,9 +64,148 @@ impl Block {
         }
     }
 
+    /// Serializes the block in the format used by the block message
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut payload = self.header.serialize();
+
+        payload.extend_from_slice(&serialize_var_int(self.txns.len() as u64));
+
+        for tx in &self.txns {
+            payload.extend_from_slice(&tx.serialize());
+        }
+
+        payload
+    }
+
+    /// Returns a copy of the block without the witness data of its transactions
+    pub fn without_witness(&self) -> Block {
+        Block {
+            header: self.header.clone(),
+            txn_count: self.txn_count,
+            txns: self.txns.iter().map(|tx| tx.without_witness()).collect(),
+        }
+    }
+
+    /// Returns the witness commitment of the coinbase, the one of its last output that has it
+    pub fn witness_commitment(&self) -> Option<&[u8]> {
+        let coinbase = self.txns.first()?;
+
+        coinbase
+            .outputs
+            .iter()
+            .rev()
+            .find(|output| {
+                output.script_pubkey.len() >= 38
+                    && output.script_pubkey.starts_with(&WITNESS_COMMITMENT_HEADER)
+            })
+            .map(|output| &output.script_pubkey[6..38])
+    }
+
+    /// Merkle root of the wtxids of the block. The coinbase counts as a hash of zeros
+    pub fn witness_merkle_root(&self) -> Result<sha256d::Hash, MerkleTreeError> {
+        let wtxids = self
+            .txns
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| {
+                if index == 0 {
+                    sha256d::Hash::from_inner([0; 32])
+                } else {
+                    tx.compute_wtxid()
+                }
+            })
+            .collect();
+
+        calculate_merkle_tree(wtxids)
+    }
+
+    /// Checks the commitment of the coinbase to the witness data of the block.
+    /// A block without commitment cannot have witness data
+    pub fn has_valid_witness_commitment(&self) -> bool {
+        let commitment = match self.witness_commitment() {
+            Some(commitment) => commitment,
+            None => return !self.txns.iter().any(|tx| tx.has_witness()),
+        };
+
+        // The witness of the coinbase is a single reserved value of 32 bytes
+        let reserved_value = match self.txns[0].inputs.first() {
+            Some(input) if input.witness.len() == 1 && input.witness[0].len() == 32 => {
+                &input.witness[0]
+            }
+            _ => return false,
+        };
+
+        match self.witness_merkle_root() {
+            Ok(root) => {
+                let mut data = root.to_vec();
+                data.extend_from_slice(reserved_value);
+                sha256d::Hash::hash(&data).to_vec() == commitment
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Weight of the block: the bytes without witness data count four times, the witness data once
+    pub fn weight(&self) -> usize {
+        let stripped_size = self.without_witness().serialize().len();
+        stripped_size * (WITNESS_SCALE_FACTOR - 1) + self.serialize().len()
+    }
+
+    /// Checks of the block that do not depend on the chain: the first transaction is the only
+    /// coinbase, every transaction is well formed and appears once, and the weight and the
+    /// signature operations of the scripts are within the limits
+    pub fn check_block(&self) -> Result<(), BlockError> {
+        match self.txns.first() {
+            None => return Err(BlockError::NoTransactions),
+            Some(coinbase) if !coinbase.is_coinbase() => {
+                return Err(BlockError::FirstTransactionNotCoinbase)
+            }
+            Some(_) => {}
+        }
+        if self.txns[1..].iter().any(|tx| tx.is_coinbase()) {
+            return Err(BlockError::MultipleCoinbases);
+        }
+
+        let mut txids = HashSet::new();
+        let mut sigops = 0;
+        for tx in &self.txns {
+            check_transaction_sanity(tx).map_err(BlockError::InvalidTransaction)?;
+            if !txids.insert(tx.compute_txid()) {
+                return Err(BlockError::DuplicateTransactions);
+            }
+            sigops += legacy_sigops(tx);
+        }
+
+        if self.weight() > MAX_BLOCK_WEIGHT {
+            return Err(BlockError::BadWeight);
+        }
+        if sigops * WITNESS_SCALE_FACTOR as u32 > MAX_BLOCK_SIGOPS_COST {
+            return Err(BlockError::BadSigops);
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the script of the coinbase starts with the push of the height (BIP34)
+    pub fn has_coinbase_height(&self, height: u32) -> bool {
+        let expected = match height {
+            0 => vec![OP_0],
+            1..=16 => vec![OP_1 + height as u8 - 1],
+            _ => push_data(&encode_script_num(height as i64)),
+        };
+
+        match self
+            .txns
+            .first()
+            .and_then(|coinbase| coinbase.inputs.first())
+        {
+            Some(input) => input.script.starts_with(&expected),
+            None => false,
+        }
+    }
+
     fn is_genesis_block(&self) -> bool {
-        let hash_string = u8_to_hex_string(&self.txns[0].hash);
-        hash_string == GENESIS_BLOCK_HASH
+        self.header.calculate_hash() == chain_params().genesis_hash()
     }
 
     pub fn is_valid(&self) -> Result<bool, MerkleTreeError> {
@@ -41,12 +218,23 @@ impl Block {
             return Ok(true);
         };
 
+        if self.check_block().is_err() {
+            return Ok(false);
+        }
+
+        if !self.has_valid_witness_commitment() {
+            return Ok(false);
+        }
+
         if self.is_genesis_block() {
             // Special case for genesis block
             Ok(self.txns[0].hash.to_vec() == self.header.merkle_root)
         } else {
-            match self.merkle_root() {
-                Ok(computed_root) => Ok(computed_root.to_vec() == self.header.merkle_root),
+            // A mutated block has the hash of the honest one, so only the block is rejected
+            match self.merkle_root_with_mutation() {
+                Ok((computed_root, mutated)) => {
+                    Ok(!mutated && computed_root.to_vec() == self.header.merkle_root)
+                }
                 Err(_) => Ok(false),
             }
         }
@@ -59,15 +247,15 @@ impl Block {
             return false;
         }
 
-        let merkle_root_result = self.merkle_root();
+        let merkle_root_result = self.merkle_root_with_mutation();
 
-        if merkle_root_result.is_err() {
+        if !matches!(merkle_root_result, Ok((_, false))) {
             return false;
         }
 
         // Si se pudo obtener la prueba de Merkle y la raíz del árbol de Merkle, verifica si son válidas
         let proof = proof_result.unwrap();
-        let merkle_root = merkle_root_result.unwrap();
+        let (merkle_root, _) = merkle_root_result.unwrap();
         self.verify_merkle_proof(proof, tx_hash, merkle_root)
     }
 
@@ -88,6 +276,7 @@ impl Block {
         hash == merkle_root
     }
 
+    #[allow(dead_code)]
     pub fn merkle_root(&self) -> Result<sha256d::Hash, MerkleTreeError> {
         let tx_hashes = self.txns.iter().map(|tx| tx.hash).collect::<Vec<_>>();
         let merkle_tree_result = calculate_merkle_tree(tx_hashes)?;
@@ -95,6 +284,12 @@ impl Block {
         Ok(merkle_tree_result)
     }
 
+    /// Merkle root of the txids and whether the list of transactions is mutated (CVE-2012-2459)
+    pub fn merkle_root_with_mutation(&self) -> Result<(sha256d::Hash, bool), MerkleTreeError> {
+        let tx_hashes = self.txns.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        calculate_merkle_tree_with_mutation(tx_hashes)
+    }
+
     pub fn merkle_proof(
         &self,
         tx_hash: sha256d::Hash,
@@ -141,11 +336,21 @@ impl Block {
     // Function to compute a new hash from a pair of hashes.
     // This is a helper function used in the construction of the Merkle tree.
     pub fn compute_node(&self, left: sha256d::Hash, right: sha256d::Hash) -> sha256d::Hash {
-        let concatenated = [left.into_inner().as_ref(), right.into_inner().as_ref()].concat();
-        sha256d::Hash::hash(&sha256d::Hash::hash(&concatenated).into_inner())
+        merkle_node(left, right)
     }
 }
 
+/// Amount of new bitcoins the coinbase of the block at the height can claim.
+/// It starts at 50 and halves every halving interval
+pub fn block_subsidy(height: u32, params: &ChainParams) -> u64 {
+    let halvings = height / params.subsidy_halving_interval;
+    if halvings >= 64 {
+        return 0;
+    }
+
+    (50 * COIN) >> halvings
+}
+
 #[allow(dead_code)]
 pub fn get_valid_blocks(blocks: Vec<Block>) -> Result<Vec<Block>, Error> {
     let mut validated_blocks: Vec<Block> = vec![];
@@ -162,3 +367,157 @@ pub fn get_valid_blocks(blocks: Vec<Block>) -> Result<Vec<Block>, Error> {
 
     Ok(validated_blocks)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::transaction::TransactionOutput;
+    use crate::helpers::test_helpers;
+
+    fn transaction(previous_output: u8, witness: Vec<Vec<u8>>) -> Transaction {
+        let mut tx = test_helpers::transaction(
+            vec![test_helpers::input([previous_output; 36], vec![0x51])],
+            vec![test_helpers::output(5000, vec![0x51])],
+        );
+        tx.inputs[0].witness = witness;
+        tx
+    }
+
+    fn segwit_block() -> Block {
+        let coinbase = transaction(0, vec![vec![0; 32]]);
+        let spend = transaction(1, vec![vec![1, 2, 3]]);
+        let mut block = Block::new(
+            BlockHeader::new(1, vec![0; 32], vec![0; 32], 0, 0x207fffff, 0),
+            2,
+            vec![coinbase, spend],
+        );
+
+        let root = block.witness_merkle_root().unwrap();
+        let mut data = root.to_vec();
+        data.extend_from_slice(&[0; 32]);
+        let mut script_pubkey = WITNESS_COMMITMENT_HEADER.to_vec();
+        script_pubkey.extend_from_slice(&sha256d::Hash::hash(&data));
+
+        block.txns[0].outputs.push(TransactionOutput {
+            value: 0,
+            script_pubkey,
+        });
+        block
+    }
+
+    #[test]
+    pub fn test_witness_commitment_of_the_coinbase() {
+        let block = segwit_block();
+        assert!(block.has_valid_witness_commitment());
+
+        let mut changed_witness = block.clone();
+        changed_witness.txns[1].inputs[0].witness = vec![vec![4]];
+        assert!(!changed_witness.has_valid_witness_commitment());
+
+        let mut without_reserved_value = block;
+        without_reserved_value.txns[0].inputs[0].witness = vec![];
+        assert!(!without_reserved_value.has_valid_witness_commitment());
+    }
+
+    #[test]
+    pub fn test_witness_data_needs_a_commitment() {
+        let mut block = segwit_block();
+        block.txns[0].outputs.pop();
+        assert!(!block.has_valid_witness_commitment());
+
+        assert!(block.without_witness().has_valid_witness_commitment());
+    }
+
+    fn coinbase(script: Vec<u8>) -> Transaction {
+        let mut tx = transaction(0, vec![]);
+        tx.inputs[0].previous_output[32..].copy_from_slice(&[0xff; 4]);
+        tx.inputs[0].script = script;
+        tx.hash = tx.compute_txid();
+        tx
+    }
+
+    fn block_with(txns: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader::new(1, vec![0; 32], vec![0; 32], 0, 0x207fffff, 0),
+            txns.len(),
+            txns,
+        )
+    }
+
+    #[test]
+    pub fn test_first_transaction_must_be_the_only_coinbase() {
+        let spend = transaction(1, vec![]);
+        let coinbase = coinbase(vec![0x51, 0x51]);
+
+        assert_eq!(
+            block_with(vec![]).check_block(),
+            Err(BlockError::NoTransactions)
+        );
+        assert_eq!(
+            block_with(vec![spend.clone(), coinbase.clone()]).check_block(),
+            Err(BlockError::FirstTransactionNotCoinbase)
+        );
+        assert_eq!(
+            block_with(vec![coinbase.clone(), coinbase.clone()]).check_block(),
+            Err(BlockError::MultipleCoinbases)
+        );
+        assert_eq!(
+            block_with(vec![coinbase.clone(), spend.clone(), spend.clone()]).check_block(),
+            Err(BlockError::DuplicateTransactions)
+        );
+        assert!(block_with(vec![coinbase, spend]).check_block().is_ok());
+    }
+
+    #[test]
+    pub fn test_block_with_too_many_sigops_is_invalid() {
+        let mut spend = transaction(1, vec![]);
+        spend.outputs[0].script_pubkey = vec![0xac; 20_001];
+        let block = block_with(vec![coinbase(vec![0x51, 0x51]), spend]);
+
+        assert_eq!(block.check_block(), Err(BlockError::BadSigops));
+    }
+
+    #[test]
+    pub fn test_coinbase_starts_with_the_height() {
+        let block = block_with(vec![coinbase(vec![0x03, 0x00, 0x9f, 0x24, 0x51])]);
+        assert!(block.has_coinbase_height(2_400_000));
+        assert!(!block.has_coinbase_height(2_400_001));
+
+        let low_height = block_with(vec![coinbase(vec![0x55, 0x00])]);
+        assert!(low_height.has_coinbase_height(5));
+    }
+
+    #[test]
+    pub fn test_subsidy_halves_every_interval() {
+        let params = ChainParams::new(crate::configuration::chain_params::Network::Mainnet);
+
+        assert_eq!(block_subsidy(0, &params), 50 * COIN);
+        assert_eq!(block_subsidy(209_999, &params), 50 * COIN);
+        assert_eq!(block_subsidy(210_000, &params), 25 * COIN);
+        assert_eq!(block_subsidy(64 * 210_000, &params), 0);
+    }
+
+    #[test]
+    pub fn test_block_with_the_last_transactions_repeated_is_mutated() {
+        let txns = vec![
+            coinbase(vec![0x51, 0x51]),
+            transaction(1, vec![]),
+            transaction(2, vec![]),
+        ];
+        let honest = block_with(txns.clone());
+        let mut mutated_txns = txns;
+        mutated_txns.push(mutated_txns[2].clone());
+        let mutated = block_with(mutated_txns);
+
+        let (honest_root, honest_mutated) = honest.merkle_root_with_mutation().unwrap();
+        let (mutated_root, is_mutated) = mutated.merkle_root_with_mutation().unwrap();
+
+        assert_eq!(honest_root, mutated_root);
+        assert!(!honest_mutated);
+        assert!(is_mutated);
+
+        let tx_hash = honest.txns[1].hash;
+        assert!(honest.is_transaction_valid(tx_hash));
+        assert!(!mutated.is_transaction_valid(tx_hash));
+    }
+}
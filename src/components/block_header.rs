@@ -3,9 +3,15 @@ extern crate chrono;
 extern crate rand;
 
 use bitcoin_hashes::{sha256d, Hash};
-use chrono::Utc;
 
-const MAX_BITS: u32 = 0x1d00ffff;
+use crate::{
+    configuration::chain_params::chain_params, connection::network_time::network_adjusted_time,
+    helpers::uint256::U256,
+};
+
+/// Most a header timestamp can be ahead of the network adjusted time, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 60;
+
 const ZERO_HASH: [u8; 32] = [0; 32];
 
 /// Represents the structure of the block header
@@ -40,7 +46,8 @@ impl BlockHeader {
         }
     }
 
-    fn calculate_hash(&self) -> Vec<u8> {
+    /// Serializes the header in the 80 bytes format used in the protocol
+    pub fn serialize(&self) -> Vec<u8> {
         let mut data = Vec::new();
         data.extend_from_slice(&self.version.to_le_bytes());
         data.extend_from_slice(&self.prev_block_hash);
@@ -48,18 +55,20 @@ impl BlockHeader {
         data.extend_from_slice(&self.timestamp.to_le_bytes());
         data.extend_from_slice(&self.bits.to_le_bytes());
         data.extend_from_slice(&self.nonce.to_le_bytes());
+        data
+    }
 
-        let hash = sha256d::Hash::hash(&data);
+    /// Returns the double sha256 of the serialized header
+    pub fn calculate_hash(&self) -> Vec<u8> {
+        let hash = sha256d::Hash::hash(&self.serialize());
         hash.into_inner().to_vec()
     }
 
-    fn validate_pow(&self) -> bool {
-        // Compactar los bits
-        let target = target_from_compact(self.bits);
-        // hash in little endian
-        let hash = self.calculate_hash();
-        // Big endian comparison gives error if the target is bigger than the hash
-        hash.iter().rev().cmp(target.iter()) != std::cmp::Ordering::Greater
+    /// Returns true if the hash, read as a little endian number, is not above the target of the
+    /// bits. A negative, zero or overflowed target is never met
+    pub fn validate_pow(&self) -> bool {
+        let target = U256::from_compact(self.bits);
+        !target.is_zero() && U256::from_le_bytes(&self.calculate_hash()) <= target
     }
 
     /// validates the property version
@@ -76,12 +85,11 @@ impl BlockHeader {
     }
 
     fn validate_time(&self) -> bool {
-        let current_time = Utc::now().timestamp() as u32;
-        self.timestamp <= current_time
+        self.timestamp as i64 <= network_adjusted_time() + MAX_FUTURE_BLOCK_TIME
     }
 
     fn validate_bits(&self) -> bool {
-        self.bits <= MAX_BITS
+        self.bits <= chain_params().pow_limit_bits
     }
 
     fn validate_nonce(&self) -> bool {
@@ -105,23 +113,6 @@ impl BlockHeader {
     }
 }
 
-// Compact the bits for the comparison with the hash
-fn target_from_compact(bits: u32) -> Vec<u8> {
-    let size = bits >> 24;
-    let word = bits & 0xFFFFFF;
-    let mut target = vec![];
-
-    if size <= 3 {
-        target.resize((3 - size) as usize, 0);
-        target.extend_from_slice(&word.to_le_bytes()[0..size as usize]);
-    } else {
-        target.extend_from_slice(&word.to_le_bytes());
-        target.resize(size as usize, 0);
-    }
-
-    target
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,967 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Error, ErrorKind},
+};
+
+use bitcoin_hashes::Hash;
+
+use super::{
+    block::{block_subsidy, Block, BlockError, MAX_BLOCK_SIGOPS_COST},
+    header_chain::{HeaderChain, MEDIAN_TIME_SPAN},
+    transaction::{Transaction, TransactionInput, TransactionOutput},
+    transaction_validation::{
+        check_transaction, check_transaction_scripts, is_final_transaction, transaction_sigop_cost,
+        TransactionError,
+    },
+};
+use crate::{
+    configuration::chain_params::chain_params,
+    script::{
+        interpreter::{block_script_flags, MAX_SCRIPT_SIZE},
+        opcodes::OP_RETURN,
+    },
+    testnet_protocol::messages::network_message::PayloadReader,
+};
+
+/// Amount of blocks, counting from the tip, that keep their undo data and can be disconnected
+pub const MAX_REORG_DEPTH: usize = 100;
+
+/// #ENUM ChainstateError
+/// Represents the reasons a block can not be connected to the chainstate
+#[derive(Debug, PartialEq)]
+pub enum ChainstateError {
+    NotNextBlock,
+    UnexpectedHeight,
+    NotTip,
+    MissingUndoData,
+    MissingBlock,
+    NotInBestChain,
+    InvalidBlock(BlockError),
+    InvalidTransaction(TransactionError),
+}
+
+/// #TDA OutPoint
+/// Output of a transaction: the txid, in the byte order of the inputs, and the index of the output
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: [u8; 32],
+    pub index: u32,
+}
+
+impl OutPoint {
+    pub fn new(txid: [u8; 32], index: u32) -> Self {
+        OutPoint { txid, index }
+    }
+
+    /// Outpoint spent by the input
+    pub fn from_input(input: &TransactionInput) -> Self {
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&input.previous_output[..32]);
+        let index = u32::from_le_bytes([
+            input.previous_output[32],
+            input.previous_output[33],
+            input.previous_output[34],
+            input.previous_output[35],
+        ]);
+
+        OutPoint { txid, index }
+    }
+}
+
+/// #TDA Coin
+/// Unspent output with the height of the block that created it and whether it was a coinbase
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Coin {
+    pub output: TransactionOutput,
+    pub height: u32,
+    pub is_coinbase: bool,
+}
+
+/// #TDA BlockUndo
+/// Coins spent by the transactions of a block, in the order they were spent.
+/// They are restored when the block is disconnected
+#[derive(Debug, Clone, Default)]
+pub struct BlockUndo {
+    pub spent_coins: Vec<(OutPoint, Coin)>,
+}
+
+impl BlockUndo {
+    /// Splits the spent coins by the transaction that spent them. The coins are in the order the
+    /// inputs were connected, and the inputs without a coin in the set have none
+    pub fn coins_spent_by(&self, txns: &[Transaction]) -> Vec<Vec<&(OutPoint, Coin)>> {
+        let mut spent_coins = self.spent_coins.iter().peekable();
+
+        txns.iter()
+            .map(|tx| match tx.is_coinbase() {
+                true => Vec::new(),
+                false => tx
+                    .inputs
+                    .iter()
+                    .filter_map(|input| {
+                        let outpoint = OutPoint::from_input(input);
+                        spent_coins.next_if(|(spent, _)| *spent == outpoint)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+/// #TDA ChainUpdate
+/// Blocks disconnected, from the old tip down to the fork, and blocks connected after
+/// them when the chainstate moves to another tip
+#[derive(Debug, Clone, Default)]
+pub struct ChainUpdate {
+    pub disconnected: Vec<(Block, BlockUndo)>,
+    pub connected: Vec<Block>,
+}
+
+/// #TDA Chainstate
+/// Set of the unspent outputs of the chain, built connecting the blocks in order.
+/// The tip is the last block connected. The undo data of the last blocks is kept to disconnect them.
+/// When the first block connected is the one after the genesis, the inputs of the blocks are validated.
+/// The timestamps of the connected blocks, and of the genesis with the full history, are kept for the lock times
+#[derive(Debug, Clone, Default)]
+pub struct Chainstate {
+    coins: HashMap<OutPoint, Coin>,
+    tip_hash: Option<Vec<u8>>,
+    tip_height: u32,
+    full_history: bool,
+    undo_data: VecDeque<(Vec<u8>, BlockUndo)>,
+    block_times: Vec<u32>,
+}
+
+#[allow(dead_code)]
+impl Chainstate {
+    pub fn new() -> Self {
+        Chainstate::default()
+    }
+
+    /// Connects the block on top of the tip: removes the outpoints its transactions spend and
+    /// adds their spendable outputs. The first block connected can be at any height.
+    /// Inputs that spend outputs created before the first block connected are not in the set,
+    /// so the transactions, their scripts, the amount claimed by the coinbase and the signature
+    /// operations of the spent outputs are only checked against the set with the full history,
+    /// as are the lock times. If the block is invalid the chainstate does not change
+    pub fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), ChainstateError> {
+        if let Some(tip_hash) = &self.tip_hash {
+            if block.header.prev_block_hash != *tip_hash {
+                return Err(ChainstateError::NotNextBlock);
+            }
+            if height != self.tip_height + 1 {
+                return Err(ChainstateError::UnexpectedHeight);
+            }
+        }
+
+        block.check_block().map_err(ChainstateError::InvalidBlock)?;
+        if height >= chain_params().bip34_height && !block.has_coinbase_height(height) {
+            return Err(ChainstateError::InvalidBlock(BlockError::BadCoinbaseHeight));
+        }
+
+        let full_history = match self.tip_hash {
+            Some(_) => self.full_history,
+            None => height <= 1,
+        };
+        let hash = block.header.calculate_hash();
+        let script_flags = block_script_flags(&hash, height);
+        let mut undo = BlockUndo::default();
+        let mut fees: u64 = 0;
+        let mut sigop_cost: u32 = 0;
+
+        // Lock times are compared with the median time past of the parent since BIP113
+        let lock_time_cutoff = if height >= chain_params().csv_height {
+            self.median_time_past(height - 1).unwrap_or(0)
+        } else {
+            block.header.timestamp
+        };
+
+        for (position, tx) in block.txns.iter().enumerate() {
+            let is_coinbase = tx.is_coinbase();
+            if full_history {
+                let result = if !is_coinbase {
+                    check_transaction(tx, self, height, lock_time_cutoff).and_then(|fee| {
+                        check_transaction_scripts(tx, self, script_flags).map(|_| fee)
+                    })
+                } else if is_final_transaction(tx, height, lock_time_cutoff) {
+                    Ok(0)
+                } else {
+                    Err(TransactionError::NonFinal)
+                };
+
+                match result {
+                    Ok(fee) => fees += fee,
+                    Err(error) => {
+                        self.undo_transactions(&block.txns[..position], &undo);
+                        return Err(ChainstateError::InvalidTransaction(error));
+                    }
+                }
+                sigop_cost += transaction_sigop_cost(tx, self);
+            }
+
+            if !is_coinbase {
+                for input in &tx.inputs {
+                    let outpoint = OutPoint::from_input(input);
+                    if let Some(coin) = self.coins.remove(&outpoint) {
+                        undo.spent_coins.push((outpoint, coin));
+                    }
+                }
+            }
+
+            let txid = tx.compute_txid().into_inner();
+            for (index, output) in tx.outputs.iter().enumerate() {
+                if is_unspendable(&output.script_pubkey) {
+                    continue;
+                }
+                self.coins.insert(
+                    OutPoint::new(txid, index as u32),
+                    Coin {
+                        output: output.clone(),
+                        height,
+                        is_coinbase,
+                    },
+                );
+            }
+        }
+
+        if full_history {
+            let claimed: u64 = block.txns[0]
+                .outputs
+                .iter()
+                .map(|output| output.value)
+                .sum();
+            let error = if claimed > block_subsidy(height, chain_params()) + fees {
+                Some(BlockError::BadCoinbaseAmount)
+            } else if sigop_cost > MAX_BLOCK_SIGOPS_COST {
+                Some(BlockError::BadSigops)
+            } else {
+                None
+            };
+
+            if let Some(error) = error {
+                self.undo_transactions(&block.txns, &undo);
+                return Err(ChainstateError::InvalidBlock(error));
+            }
+        }
+
+        self.undo_data.push_back((hash.clone(), undo));
+        if self.undo_data.len() > MAX_REORG_DEPTH {
+            self.undo_data.pop_front();
+        }
+
+        if self.tip_hash.is_none() && full_history && height == 1 {
+            self.block_times
+                .push(chain_params().genesis_header.timestamp);
+        }
+        self.block_times.push(block.header.timestamp);
+
+        self.tip_hash = Some(hash);
+        self.tip_height = height;
+        self.full_history = full_history;
+
+        Ok(())
+    }
+
+    /// Disconnects the tip block: removes the outputs its transactions created and restores
+    /// the coins they spent. Returns the undo data of the block
+    pub fn disconnect_block(&mut self, block: &Block) -> Result<BlockUndo, ChainstateError> {
+        let hash = block.header.calculate_hash();
+        if self.tip_hash.as_ref() != Some(&hash) {
+            return Err(ChainstateError::NotTip);
+        }
+
+        let undo = match self.undo_data.back() {
+            Some((undo_hash, _)) if *undo_hash == hash => self.undo_data.pop_back().unwrap().1,
+            _ => return Err(ChainstateError::MissingUndoData),
+        };
+
+        self.undo_transactions(&block.txns, &undo);
+        self.block_times.pop();
+
+        self.tip_hash = Some(block.header.prev_block_hash.clone());
+        self.tip_height -= 1;
+
+        Ok(undo)
+    }
+
+    /// Moves the tip to a block of the best chain of the header chain: disconnects the blocks of
+    /// the current branch down to the fork and connects the ones of the best chain up to the new tip.
+    /// Nothing changes unless every block needed is in the blocks and has its undo data.
+    /// If a block of the new branch is invalid, the old branch is connected again and the error is returned
+    pub fn activate_best_chain(
+        &mut self,
+        header_chain: &HeaderChain,
+        blocks: &HashMap<Vec<u8>, Block>,
+        new_tip: &[u8],
+    ) -> Result<ChainUpdate, ChainstateError> {
+        if !header_chain.is_in_best_chain(new_tip) {
+            return Err(ChainstateError::NotInBestChain);
+        }
+        let new_height = header_chain
+            .height_of(new_tip)
+            .ok_or(ChainstateError::NotInBestChain)?;
+
+        let tip_hash = match &self.tip_hash {
+            Some(tip_hash) => tip_hash.clone(),
+            None => {
+                let block = blocks.get(new_tip).ok_or(ChainstateError::MissingBlock)?;
+                self.connect_block(block, new_height)?;
+                return Ok(ChainUpdate {
+                    disconnected: vec![],
+                    connected: vec![block.clone()],
+                });
+            }
+        };
+
+        let fork_height = header_chain
+            .fork_height(&tip_hash)
+            .ok_or(ChainstateError::NotInBestChain)?;
+        let depth = self.tip_height.saturating_sub(fork_height);
+        if depth as usize > self.undo_data.len() {
+            return Err(ChainstateError::MissingUndoData);
+        }
+
+        let mut to_disconnect = vec![];
+        let mut hash = tip_hash;
+        for _ in 0..depth {
+            let block = blocks.get(&hash).ok_or(ChainstateError::MissingBlock)?;
+            hash = block.header.prev_block_hash.clone();
+            to_disconnect.push(block);
+        }
+
+        let mut to_connect = vec![];
+        for height in fork_height + 1..=new_height {
+            let block = header_chain
+                .hash_at(height)
+                .and_then(|hash| blocks.get(hash))
+                .ok_or(ChainstateError::MissingBlock)?;
+            to_connect.push((block, height));
+        }
+
+        let mut update = ChainUpdate::default();
+        let mut result = Ok(());
+        for block in to_disconnect {
+            match self.disconnect_block(block) {
+                Ok(undo) => update.disconnected.push((block.clone(), undo)),
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            for (block, height) in to_connect {
+                if let Err(error) = self.connect_block(block, height) {
+                    result = Err(error);
+                    break;
+                }
+                update.connected.push(block.clone());
+            }
+        }
+
+        if let Err(error) = result {
+            self.restore_branch(&update)?;
+            return Err(error);
+        }
+        Ok(update)
+    }
+
+    /// Undoes a move to another branch that failed partway: disconnects the blocks connected
+    /// and connects again the ones disconnected, so the tip is the one before the move
+    fn restore_branch(&mut self, update: &ChainUpdate) -> Result<(), ChainstateError> {
+        for block in update.connected.iter().rev() {
+            self.disconnect_block(block)?;
+        }
+        for (block, _) in update.disconnected.iter().rev() {
+            self.connect_block(block, self.tip_height + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Undoes the transactions one at a time, from the last one: removes the outputs the
+    /// transaction created and then restores the coins its inputs spent, so an output created and
+    /// spent inside the block is not restored
+    fn undo_transactions(&mut self, txns: &[Transaction], undo: &BlockUndo) {
+        let spent_coins = undo.coins_spent_by(txns);
+
+        for (tx, spent_coins) in txns.iter().zip(spent_coins).rev() {
+            let txid = tx.compute_txid().into_inner();
+            for index in 0..tx.outputs.len() {
+                self.coins.remove(&OutPoint::new(txid, index as u32));
+            }
+
+            for (outpoint, coin) in spent_coins {
+                self.coins.insert(outpoint.clone(), coin.clone());
+            }
+        }
+    }
+
+    /// Returns true if the chainstate was built from the first block of the chain, so every
+    /// output that can be spent is in the set
+    pub fn has_full_history(&self) -> bool {
+        self.full_history
+    }
+
+    /// Returns the median of the timestamps of the connected block at the height and the 10 blocks
+    /// before it, or None if some of them were not connected. The genesis has its own timestamp
+    pub fn median_time_past(&self, height: u32) -> Option<u32> {
+        if height == 0 {
+            return Some(chain_params().genesis_header.timestamp);
+        }
+
+        self.tip_hash.as_ref()?;
+        let first_height = (self.tip_height + 1).checked_sub(self.block_times.len() as u32)?;
+        let start = height.saturating_sub(MEDIAN_TIME_SPAN as u32 - 1);
+        if start < first_height || height > self.tip_height {
+            return None;
+        }
+
+        let mut timestamps = self.block_times
+            [(start - first_height) as usize..=(height - first_height) as usize]
+            .to_vec();
+        timestamps.sort();
+        Some(timestamps[timestamps.len() / 2])
+    }
+
+    /// Returns the coin of the outpoint if it is unspent
+    pub fn get_coin(&self, outpoint: &OutPoint) -> Option<&Coin> {
+        self.coins.get(outpoint)
+    }
+
+    pub fn is_unspent(&self, outpoint: &OutPoint) -> bool {
+        self.coins.contains_key(outpoint)
+    }
+
+    /// Returns the unspent outputs that pay to the script pubkey
+    pub fn coins_of_script(&self, script_pubkey: &[u8]) -> Vec<(OutPoint, Coin)> {
+        self.coins
+            .iter()
+            .filter(|(_, coin)| coin.output.script_pubkey == script_pubkey)
+            .map(|(outpoint, coin)| (outpoint.clone(), coin.clone()))
+            .collect()
+    }
+
+    /// Amount of unspent outputs
+    pub fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coins.is_empty()
+    }
+
+    pub fn tip_hash(&self) -> Option<&Vec<u8>> {
+        self.tip_hash.as_ref()
+    }
+
+    pub fn tip_height(&self) -> u32 {
+        self.tip_height
+    }
+
+    /// Serializes the tip, the coins, the undo data and the block times, so the chainstate can be
+    /// saved and the next run resumes from its tip
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match &self.tip_hash {
+            Some(tip_hash) => {
+                data.push(1);
+                data.extend_from_slice(tip_hash);
+            }
+            None => data.push(0),
+        }
+        data.extend_from_slice(&self.tip_height.to_le_bytes());
+        data.push(self.full_history as u8);
+
+        data.extend_from_slice(&(self.block_times.len() as u32).to_le_bytes());
+        for time in &self.block_times {
+            data.extend_from_slice(&time.to_le_bytes());
+        }
+
+        data.extend_from_slice(&(self.coins.len() as u32).to_le_bytes());
+        for (outpoint, coin) in &self.coins {
+            serialize_coin(&mut data, outpoint, coin);
+        }
+
+        data.extend_from_slice(&(self.undo_data.len() as u32).to_le_bytes());
+        for (hash, undo) in &self.undo_data {
+            data.extend_from_slice(hash);
+            data.extend_from_slice(&(undo.spent_coins.len() as u32).to_le_bytes());
+            for (outpoint, coin) in &undo.spent_coins {
+                serialize_coin(&mut data, outpoint, coin);
+            }
+        }
+
+        data
+    }
+
+    /// Reads a chainstate written by serialize
+    pub fn deserialize(data: &[u8]) -> Result<Chainstate, Error> {
+        let mut reader = PayloadReader::new(data);
+        let mut chainstate = Chainstate::new();
+
+        if reader.read_u8()? == 1 {
+            chainstate.tip_hash = Some(reader.read_bytes(32)?.to_vec());
+        }
+        chainstate.tip_height = reader.read_u32()?;
+        chainstate.full_history = reader.read_u8()? == 1;
+
+        for _ in 0..reader.read_u32()? {
+            chainstate.block_times.push(reader.read_u32()?);
+        }
+
+        for _ in 0..reader.read_u32()? {
+            let (outpoint, coin) = deserialize_coin(&mut reader)?;
+            chainstate.coins.insert(outpoint, coin);
+        }
+
+        for _ in 0..reader.read_u32()? {
+            let hash = reader.read_bytes(32)?.to_vec();
+            let mut undo = BlockUndo::default();
+            for _ in 0..reader.read_u32()? {
+                undo.spent_coins.push(deserialize_coin(&mut reader)?);
+            }
+            chainstate.undo_data.push_back((hash, undo));
+        }
+
+        if reader.read_bytes(1).is_ok() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected data after the chainstate",
+            ));
+        }
+
+        Ok(chainstate)
+    }
+}
+
+fn serialize_coin(data: &mut Vec<u8>, outpoint: &OutPoint, coin: &Coin) {
+    data.extend_from_slice(&outpoint.txid);
+    data.extend_from_slice(&outpoint.index.to_le_bytes());
+    data.extend(coin.output.to_bytes());
+    data.extend_from_slice(&coin.height.to_le_bytes());
+    data.push(coin.is_coinbase as u8);
+}
+
+fn deserialize_coin(reader: &mut PayloadReader) -> Result<(OutPoint, Coin), Error> {
+    let mut txid = [0u8; 32];
+    txid.copy_from_slice(reader.read_bytes(32)?);
+    let outpoint = OutPoint::new(txid, reader.read_u32()?);
+
+    let value = reader.read_u64()?;
+    let script_length = reader.read_var_int()? as usize;
+    let output = TransactionOutput {
+        value,
+        script_pubkey: reader.read_bytes(script_length)?.to_vec(),
+    };
+
+    let coin = Coin {
+        output,
+        height: reader.read_u32()?,
+        is_coinbase: reader.read_u8()? == 1,
+    };
+
+    Ok((outpoint, coin))
+}
+
+/// Returns true if the output can never be spent, so it is not kept in the set
+fn is_unspendable(script_pubkey: &[u8]) -> bool {
+    script_pubkey.first() == Some(&OP_RETURN) || script_pubkey.len() > MAX_SCRIPT_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{block_header::BlockHeader, transaction_validation::COIN};
+    use crate::configuration::chain_params::{ChainParams, Network};
+    use crate::helpers::test_helpers;
+    use crate::script::{interpreter::ScriptError, opcodes::OP_0};
+
+    fn transaction(previous_output: [u8; 36], values: &[u64]) -> Transaction {
+        test_helpers::transaction(
+            vec![test_helpers::input(previous_output, vec![0x51])],
+            values
+                .iter()
+                .map(|value| test_helpers::output(*value, vec![0x51]))
+                .collect(),
+        )
+    }
+
+    fn coinbase(height: u8) -> Transaction {
+        let mut previous_output = [0u8; 36];
+        previous_output[32..].copy_from_slice(&[0xff; 4]);
+        let mut tx = transaction(previous_output, &[5000]);
+        tx.inputs[0].script = vec![0x01, height];
+        tx.hash = tx.compute_txid();
+        tx
+    }
+
+    fn spending(outpoint: &OutPoint, values: &[u64]) -> Transaction {
+        let mut previous_output = [0u8; 36];
+        previous_output[..32].copy_from_slice(&outpoint.txid);
+        previous_output[32..].copy_from_slice(&outpoint.index.to_le_bytes());
+        transaction(previous_output, values)
+    }
+
+    fn block(prev_block_hash: Vec<u8>, txns: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader::new(1, prev_block_hash, vec![0; 32], 0, 0x207fffff, 0),
+            txns.len(),
+            txns,
+        )
+    }
+
+    #[test]
+    pub fn test_connected_blocks_spend_and_create_coins() {
+        let first_coinbase = coinbase(1);
+        let coinbase_outpoint = OutPoint::new(first_coinbase.hash.into_inner(), 0);
+        let first = block(vec![0; 32], vec![first_coinbase]);
+
+        let spend = spending(&coinbase_outpoint, &[3000, 1500]);
+        let second = block(
+            first.header.calculate_hash(),
+            vec![coinbase(2), spend.clone()],
+        );
+
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&first, 1001).unwrap();
+        assert!(chainstate.get_coin(&coinbase_outpoint).unwrap().is_coinbase);
+
+        chainstate.connect_block(&second, 1002).unwrap();
+        assert!(!chainstate.is_unspent(&coinbase_outpoint));
+
+        let change = chainstate
+            .get_coin(&OutPoint::new(spend.hash.into_inner(), 1))
+            .unwrap();
+        assert_eq!(change.output.value, 1500);
+        assert_eq!(change.height, 1002);
+        assert!(!change.is_coinbase);
+        assert_eq!(chainstate.len(), 3);
+        assert_eq!(chainstate.tip_height(), 1002);
+    }
+
+    #[test]
+    pub fn test_block_that_does_not_follow_the_tip_is_rejected() {
+        let first = block(vec![0; 32], vec![coinbase(1)]);
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&first, 1).unwrap();
+
+        let other_parent = block(vec![1; 32], vec![coinbase(2)]);
+        assert_eq!(
+            chainstate.connect_block(&other_parent, 2),
+            Err(ChainstateError::NotNextBlock)
+        );
+
+        let next = block(first.header.calculate_hash(), vec![coinbase(2)]);
+        assert_eq!(
+            chainstate.connect_block(&next, 3),
+            Err(ChainstateError::UnexpectedHeight)
+        );
+        assert_eq!(chainstate.len(), 1);
+    }
+
+    #[test]
+    pub fn test_op_return_outputs_are_not_kept() {
+        let mut tx = coinbase(1);
+        tx.outputs.push(TransactionOutput {
+            value: 0,
+            script_pubkey: vec![OP_RETURN, 0x01, 0x00],
+        });
+        tx.hash = tx.compute_txid();
+        let txid = tx.hash.into_inner();
+
+        let mut chainstate = Chainstate::new();
+        chainstate
+            .connect_block(&block(vec![0; 32], vec![tx]), 1)
+            .unwrap();
+
+        assert!(chainstate.is_unspent(&OutPoint::new(txid, 0)));
+        assert!(!chainstate.is_unspent(&OutPoint::new(txid, 1)));
+    }
+
+    #[test]
+    pub fn test_block_with_an_invalid_transaction_does_not_change_the_set() {
+        let first = block(vec![0; 32], vec![coinbase(1)]);
+        let missing = OutPoint::new([7; 32], 0);
+        let second = block(
+            first.header.calculate_hash(),
+            vec![coinbase(2), spending(&missing, &[3000])],
+        );
+
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&first, 1).unwrap();
+        assert!(chainstate.has_full_history());
+
+        assert_eq!(
+            chainstate.connect_block(&second, 2),
+            Err(ChainstateError::InvalidTransaction(
+                TransactionError::MissingOrSpentInput
+            ))
+        );
+        assert_eq!(chainstate.len(), 1);
+        assert_eq!(chainstate.tip_height(), 1);
+    }
+
+    #[test]
+    pub fn test_block_with_an_input_whose_script_fails_is_rejected() {
+        let mut unspendable = coinbase(1);
+        unspendable.outputs[0].script_pubkey = vec![OP_0];
+        unspendable.hash = unspendable.compute_txid();
+        let outpoint = OutPoint::new(unspendable.hash.into_inner(), 0);
+
+        let mut chainstate = Chainstate::new();
+        let mut tip = block(vec![0; 32], vec![unspendable]);
+        chainstate.connect_block(&tip, 1).unwrap();
+        // The coinbase output matures after 100 blocks
+        for height in 2..=101 {
+            tip = block(tip.header.calculate_hash(), vec![coinbase(height)]);
+            chainstate.connect_block(&tip, height as u32).unwrap();
+        }
+
+        let spend = block(
+            tip.header.calculate_hash(),
+            vec![coinbase(102), spending(&outpoint, &[3000])],
+        );
+        assert_eq!(
+            chainstate.connect_block(&spend, 102),
+            Err(ChainstateError::InvalidTransaction(
+                TransactionError::InvalidScript(ScriptError::EvalFalse)
+            ))
+        );
+        assert!(chainstate.is_unspent(&outpoint));
+        assert_eq!(chainstate.tip_height(), 101);
+    }
+
+    #[test]
+    pub fn test_block_with_a_transaction_that_is_not_final_is_rejected() {
+        let mut locked = coinbase(1);
+        locked.lock_time = 2;
+        locked.inputs[0].sequence = 0;
+        locked.hash = locked.compute_txid();
+
+        let mut chainstate = Chainstate::new();
+        assert_eq!(
+            chainstate.connect_block(&block(vec![0; 32], vec![locked]), 1),
+            Err(ChainstateError::InvalidTransaction(
+                TransactionError::NonFinal
+            ))
+        );
+
+        let mut first = block(vec![0; 32], vec![coinbase(1)]);
+        first.header.timestamp = u32::MAX;
+        chainstate.connect_block(&first, 1).unwrap();
+        // The median of the genesis and the first block is the greatest of them
+        assert_eq!(chainstate.median_time_past(1), Some(u32::MAX));
+        assert_eq!(chainstate.median_time_past(2), None);
+    }
+
+    #[test]
+    pub fn test_coinbase_can_not_claim_more_than_the_subsidy_and_the_fees() {
+        let mut greedy = coinbase(1);
+        greedy.outputs[0].value = 51 * COIN;
+        greedy.hash = greedy.compute_txid();
+
+        let mut chainstate = Chainstate::new();
+        assert_eq!(
+            chainstate.connect_block(&block(vec![0; 32], vec![greedy]), 1),
+            Err(ChainstateError::InvalidBlock(BlockError::BadCoinbaseAmount))
+        );
+        assert!(chainstate.is_empty());
+        assert_eq!(chainstate.tip_hash(), None);
+    }
+
+    #[test]
+    pub fn test_disconnected_block_restores_the_coins_it_spent() {
+        let first_coinbase = coinbase(1);
+        let coinbase_outpoint = OutPoint::new(first_coinbase.hash.into_inner(), 0);
+        let first = block(vec![0; 32], vec![first_coinbase]);
+        let spend = spending(&coinbase_outpoint, &[3000]);
+        let second = block(
+            first.header.calculate_hash(),
+            vec![coinbase(2), spend.clone()],
+        );
+
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&first, 1001).unwrap();
+        chainstate.connect_block(&second, 1002).unwrap();
+        assert_eq!(
+            chainstate.disconnect_block(&first).unwrap_err(),
+            ChainstateError::NotTip
+        );
+
+        let undo = chainstate.disconnect_block(&second).unwrap();
+
+        assert_eq!(undo.spent_coins.len(), 1);
+        assert!(chainstate.is_unspent(&coinbase_outpoint));
+        assert!(!chainstate.is_unspent(&OutPoint::new(spend.hash.into_inner(), 0)));
+        assert_eq!(chainstate.len(), 1);
+        assert_eq!(chainstate.tip_hash(), Some(&first.header.calculate_hash()));
+        assert_eq!(chainstate.tip_height(), 1001);
+    }
+
+    #[test]
+    pub fn test_output_created_and_spent_in_the_block_is_not_restored() {
+        let first_coinbase = coinbase(1);
+        let coinbase_outpoint = OutPoint::new(first_coinbase.hash.into_inner(), 0);
+        let mut tip = block(vec![0; 32], vec![first_coinbase]);
+
+        // The coinbase of the first block matures after 100 blocks
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&tip, 1).unwrap();
+        for height in 2..=101 {
+            tip = block(tip.header.calculate_hash(), vec![coinbase(height)]);
+            chainstate.connect_block(&tip, height as u32).unwrap();
+        }
+        let coins = chainstate.len();
+
+        let create = spending(&coinbase_outpoint, &[3000]);
+        let created_outpoint = OutPoint::new(create.hash.into_inner(), 0);
+        let spend = spending(&created_outpoint, &[2000]);
+        let last = block(
+            tip.header.calculate_hash(),
+            vec![coinbase(102), create.clone(), spend.clone()],
+        );
+        chainstate.connect_block(&last, 102).unwrap();
+        assert!(!chainstate.is_unspent(&created_outpoint));
+
+        chainstate.disconnect_block(&last).unwrap();
+        assert!(chainstate.is_unspent(&coinbase_outpoint));
+        assert!(!chainstate.is_unspent(&created_outpoint));
+        assert!(!chainstate.is_unspent(&OutPoint::new(spend.hash.into_inner(), 0)));
+        assert_eq!(chainstate.len(), coins);
+
+        // A block that fails after spending the output does not restore it either
+        let invalid = block(
+            tip.header.calculate_hash(),
+            vec![
+                coinbase(102),
+                create,
+                spend,
+                spending(&OutPoint::new([7; 32], 0), &[1000]),
+            ],
+        );
+        assert_eq!(
+            chainstate.connect_block(&invalid, 102),
+            Err(ChainstateError::InvalidTransaction(
+                TransactionError::MissingOrSpentInput
+            ))
+        );
+        assert!(chainstate.is_unspent(&coinbase_outpoint));
+        assert!(!chainstate.is_unspent(&created_outpoint));
+        assert_eq!(chainstate.len(), coins);
+    }
+
+    #[test]
+    pub fn test_branch_with_more_work_replaces_the_connected_blocks() {
+        let params = ChainParams::new(Network::Regtest);
+        let genesis = params.genesis_header.clone();
+        let mut header_chain = HeaderChain::new(params);
+        let child = |parent: &BlockHeader, nonce: u32, txns: Vec<Transaction>| {
+            let mut block = block(parent.calculate_hash(), txns);
+            block.header.timestamp = parent.timestamp + 600;
+            block.header.nonce = nonce;
+            block.header = test_helpers::mine(block.header);
+            block
+        };
+
+        let old_coinbase = coinbase(1);
+        let old_outpoint = OutPoint::new(old_coinbase.hash.into_inner(), 0);
+        let old_1 = child(&genesis, 1, vec![old_coinbase]);
+        let old_2 = child(&old_1.header, 1, vec![coinbase(2)]);
+        let new_1 = child(&genesis, 2, vec![coinbase(3)]);
+        let new_2 = child(&new_1.header, 2, vec![coinbase(4)]);
+        let new_3 = child(&new_2.header, 2, vec![coinbase(5)]);
+
+        let mut blocks = HashMap::new();
+        for block in [&old_1, &old_2, &new_1, &new_2, &new_3] {
+            blocks.insert(block.header.calculate_hash(), block.clone());
+        }
+
+        let mut chainstate = Chainstate::new();
+        for block in [&old_1, &old_2] {
+            let hash = header_chain.add_header(block.header.clone()).unwrap();
+            chainstate
+                .activate_best_chain(&header_chain, &blocks, &hash)
+                .unwrap();
+        }
+
+        let mut new_tip = vec![];
+        for block in [&new_1, &new_2, &new_3] {
+            new_tip = header_chain.add_header(block.header.clone()).unwrap();
+        }
+
+        let update = chainstate
+            .activate_best_chain(&header_chain, &blocks, &new_tip)
+            .unwrap();
+
+        assert_eq!(update.disconnected.len(), 2);
+        assert_eq!(
+            update.disconnected[0].0.header.calculate_hash(),
+            old_2.header.calculate_hash()
+        );
+        assert_eq!(update.connected.len(), 3);
+        assert!(!chainstate.is_unspent(&old_outpoint));
+        assert_eq!(chainstate.len(), 3);
+        assert_eq!(chainstate.tip_hash(), Some(&new_tip));
+        assert_eq!(chainstate.tip_height(), 3);
+    }
+
+    #[test]
+    pub fn test_branch_with_an_invalid_block_restores_the_connected_blocks() {
+        let params = ChainParams::new(Network::Regtest);
+        let genesis = params.genesis_header.clone();
+        let mut header_chain = HeaderChain::new(params);
+        let child = |parent: &BlockHeader, nonce: u32, txns: Vec<Transaction>| {
+            let mut block = block(parent.calculate_hash(), txns);
+            block.header.timestamp = parent.timestamp + 600;
+            block.header.nonce = nonce;
+            block.header = test_helpers::mine(block.header);
+            block
+        };
+
+        let old_coinbase = coinbase(1);
+        let old_outpoint = OutPoint::new(old_coinbase.hash.into_inner(), 0);
+        let old_1 = child(&genesis, 1, vec![old_coinbase]);
+        let old_2 = child(&old_1.header, 1, vec![coinbase(2)]);
+        let new_1 = child(&genesis, 2, vec![coinbase(3)]);
+        let missing = OutPoint::new([7; 32], 0);
+        let new_2 = child(
+            &new_1.header,
+            2,
+            vec![coinbase(4), spending(&missing, &[3000])],
+        );
+        let new_3 = child(&new_2.header, 2, vec![coinbase(5)]);
+
+        let mut blocks = HashMap::new();
+        for block in [&old_1, &old_2, &new_1, &new_2, &new_3] {
+            blocks.insert(block.header.calculate_hash(), block.clone());
+        }
+
+        let mut chainstate = Chainstate::new();
+        let mut old_tip = vec![];
+        for block in [&old_1, &old_2] {
+            old_tip = header_chain.add_header(block.header.clone()).unwrap();
+            chainstate
+                .activate_best_chain(&header_chain, &blocks, &old_tip)
+                .unwrap();
+        }
+
+        let mut new_tip = vec![];
+        for block in [&new_1, &new_2, &new_3] {
+            new_tip = header_chain.add_header(block.header.clone()).unwrap();
+        }
+
+        assert_eq!(
+            chainstate
+                .activate_best_chain(&header_chain, &blocks, &new_tip)
+                .unwrap_err(),
+            ChainstateError::InvalidTransaction(TransactionError::MissingOrSpentInput)
+        );
+        assert!(chainstate.is_unspent(&old_outpoint));
+        assert_eq!(chainstate.len(), 2);
+        assert_eq!(chainstate.tip_hash(), Some(&old_tip));
+        assert_eq!(chainstate.tip_height(), 2);
+
+        chainstate.disconnect_block(&old_2).unwrap();
+        chainstate.disconnect_block(&old_1).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use super::{
+    block_header::BlockHeader,
+    header_chain::{HeaderChain, HeaderEntry},
+};
+use crate::{configuration::chain_params::ChainParams, helpers::uint256::U256};
+
+/// Returns the bits the header that follows the parent must have
+pub fn next_work_required(
+    chain: &HeaderChain,
+    parent: &HeaderEntry,
+    header: &BlockHeader,
+    params: &ChainParams,
+) -> u32 {
+    let interval = params.difficulty_adjustment_interval();
+
+    if !(parent.height + 1).is_multiple_of(interval) {
+        if params.allow_min_difficulty_blocks {
+            // Testnet: a block more than 20 minutes after the previous one can use the minimum difficulty
+            if header.timestamp > parent.header.timestamp + params.pow_target_spacing * 2 {
+                return params.pow_limit_bits;
+            }
+
+            // Otherwise it uses the difficulty of the last block that was not a minimum difficulty one
+            let mut current = parent;
+            while !current.height.is_multiple_of(interval)
+                && current.header.bits == params.pow_limit_bits
+            {
+                match chain.get(&current.header.prev_block_hash) {
+                    Some(previous) => current = previous,
+                    None => break,
+                }
+            }
+            return current.header.bits;
+        }
+
+        return parent.header.bits;
+    }
+
+    let first_height = parent.height + 1 - interval;
+    match chain.ancestor(&parent.hash, first_height) {
+        Some(first) => calculate_next_work_required(
+            parent.header.bits,
+            parent.header.timestamp,
+            first.header.timestamp,
+            params,
+        ),
+        None => parent.header.bits,
+    }
+}
+
+/// Calculates the bits of a retarget from the time the last interval of blocks took,
+/// limiting the adjustment to a factor of 4 in each direction
+pub fn calculate_next_work_required(
+    parent_bits: u32,
+    parent_time: u32,
+    first_block_time: u32,
+    params: &ChainParams,
+) -> u32 {
+    if params.no_retargeting {
+        return parent_bits;
+    }
+
+    let target_timespan = params.pow_target_timespan as i64;
+    let actual_timespan = (parent_time as i64 - first_block_time as i64)
+        .clamp(target_timespan / 4, target_timespan * 4);
+
+    let pow_limit = U256::from_compact(params.pow_limit_bits);
+    let new_target = U256::from_compact(parent_bits) * actual_timespan as u64
+        / U256::from_u64(target_timespan as u64);
+
+    if new_target > pow_limit {
+        return pow_limit.to_compact();
+    }
+
+    new_target.to_compact()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::chain_params::Network;
+
+    fn mainnet() -> ChainParams {
+        ChainParams::new(Network::Mainnet)
+    }
+
+    #[test]
+    pub fn test_retarget_of_a_slow_interval() {
+        assert_eq!(
+            calculate_next_work_required(0x1d00ffff, 1262152739, 1261130161, &mainnet()),
+            0x1d00d86a
+        );
+    }
+
+    #[test]
+    pub fn test_retarget_is_limited_by_the_pow_limit() {
+        assert_eq!(
+            calculate_next_work_required(0x1d00ffff, 1233061996, 1231006505, &mainnet()),
+            0x1d00ffff
+        );
+    }
+
+    #[test]
+    pub fn test_retarget_is_clamped_to_a_quarter_of_the_timespan() {
+        assert_eq!(
+            calculate_next_work_required(0x1c05a3f4, 1279297671, 1279008237, &mainnet()),
+            0x1c0168fd
+        );
+    }
+
+    #[test]
+    pub fn test_retarget_is_clamped_to_four_times_the_timespan() {
+        assert_eq!(
+            calculate_next_work_required(0x1c387f6f, 1269211443, 1263163443, &mainnet()),
+            0x1d00e1fd
+        );
+    }
+}
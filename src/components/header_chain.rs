@@ -0,0 +1,552 @@
+use std::collections::HashMap;
+
+use super::{block_header::BlockHeader, difficulty::next_work_required};
+use crate::{
+    components::block_header::MAX_FUTURE_BLOCK_TIME,
+    configuration::chain_params::ChainParams,
+    connection::network_time::network_adjusted_time,
+    helpers::uint256::{work_from_compact, U256},
+};
+
+/// Amount of blocks used to calculate the median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// #ENUM HeaderChainError
+/// Represents the reasons a header can not be added to the chain
+#[derive(Debug, PartialEq)]
+pub enum HeaderChainError {
+    UnknownParent,
+    BadDifficulty,
+    HighHash,
+    TimeTooOld,
+    TimeTooNew,
+    CheckpointMismatch,
+    ForkBeforeCheckpoint,
+}
+
+/// Represents a header of the chain with its position and the work accumulated up to it
+#[derive(Debug, Clone)]
+pub struct HeaderEntry {
+    pub header: BlockHeader,
+    pub hash: Vec<u8>,
+    pub height: u32,
+    pub chainwork: U256,
+}
+
+/// #TDA HeaderChain
+/// Tree of every known header indexed by its hash. The branch with the most
+/// accumulated work is the best chain, kept as a list of hashes indexed by height
+#[derive(Debug, Clone)]
+pub struct HeaderChain {
+    params: ChainParams,
+    entries: HashMap<Vec<u8>, HeaderEntry>,
+    best_chain: Vec<Vec<u8>>,
+}
+
+#[allow(dead_code)]
+impl HeaderChain {
+    /// Creates the chain of the network with only its genesis header
+    pub fn new(params: ChainParams) -> Self {
+        let genesis_header = params.genesis_header.clone();
+        let hash = genesis_header.calculate_hash();
+        let entry = HeaderEntry {
+            chainwork: work_from_compact(genesis_header.bits),
+            header: genesis_header,
+            hash: hash.clone(),
+            height: 0,
+        };
+
+        let mut entries = HashMap::new();
+        entries.insert(hash.clone(), entry);
+
+        HeaderChain {
+            params,
+            entries,
+            best_chain: vec![hash],
+        }
+    }
+
+    /// Adds a header whose parent is already in the chain, whose bits match the
+    /// expected difficulty, whose hash meets the target of its bits and whose timestamp
+    /// is after the median time past of the parent and not too far in the future, and
+    /// returns its hash. If the new branch has more work than the best chain, it becomes
+    /// the best chain
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<Vec<u8>, HeaderChainError> {
+        let hash = header.calculate_hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(hash);
+        }
+
+        let parent = self
+            .entries
+            .get(&header.prev_block_hash)
+            .ok_or(HeaderChainError::UnknownParent)?;
+        let height = parent.height + 1;
+
+        if height <= self.last_checkpoint_height() {
+            return Err(HeaderChainError::ForkBeforeCheckpoint);
+        }
+
+        if self
+            .params
+            .checkpoints
+            .iter()
+            .any(|(checkpoint_height, checkpoint_hash)| {
+                *checkpoint_height == height && *checkpoint_hash != hash
+            })
+        {
+            return Err(HeaderChainError::CheckpointMismatch);
+        }
+
+        if header.bits != next_work_required(self, parent, &header, &self.params) {
+            return Err(HeaderChainError::BadDifficulty);
+        }
+
+        // The bits only prove the work if the hash meets their target
+        if !header.validate_pow() {
+            return Err(HeaderChainError::HighHash);
+        }
+
+        if header.timestamp <= self.median_time_past(&parent.hash).unwrap_or(0) {
+            return Err(HeaderChainError::TimeTooOld);
+        }
+
+        if header.timestamp as i64 > network_adjusted_time() + MAX_FUTURE_BLOCK_TIME {
+            return Err(HeaderChainError::TimeTooNew);
+        }
+
+        let entry = HeaderEntry {
+            height,
+            chainwork: parent.chainwork + work_from_compact(header.bits),
+            header,
+            hash: hash.clone(),
+        };
+
+        let is_new_best = entry.chainwork > self.best_tip().chainwork;
+        self.entries.insert(hash.clone(), entry);
+
+        if is_new_best {
+            self.set_best_tip(&hash);
+        }
+
+        Ok(hash)
+    }
+
+    /// Rebuilds the best chain from the new tip back to the fork point
+    fn set_best_tip(&mut self, tip_hash: &[u8]) {
+        let mut branch: Vec<Vec<u8>> = vec![];
+        let mut current = self.entries.get(tip_hash);
+
+        while let Some(entry) = current {
+            if self.best_chain.get(entry.height as usize) == Some(&entry.hash) {
+                break;
+            }
+            branch.push(entry.hash.clone());
+            current = self.entries.get(&entry.header.prev_block_hash);
+        }
+
+        let fork_height = match current {
+            Some(entry) => entry.height as usize + 1,
+            None => 0,
+        };
+
+        self.best_chain.truncate(fork_height);
+        self.best_chain.extend(branch.into_iter().rev());
+    }
+
+    /// Returns the tip of the chain with the most work
+    pub fn best_tip(&self) -> &HeaderEntry {
+        let hash = &self.best_chain[self.best_chain.len() - 1];
+        &self.entries[hash]
+    }
+
+    /// Returns the height of the best tip
+    pub fn best_height(&self) -> u32 {
+        self.best_tip().height
+    }
+
+    /// Returns the height of a known header, being in the best chain or not
+    pub fn height_of(&self, hash: &[u8]) -> Option<u32> {
+        self.entries.get(hash).map(|entry| entry.height)
+    }
+
+    /// Returns the header of the best chain at the given height
+    pub fn header_at(&self, height: u32) -> Option<&BlockHeader> {
+        self.best_chain
+            .get(height as usize)
+            .map(|hash| &self.entries[hash].header)
+    }
+
+    /// Returns the hash of the header of the best chain at the given height
+    pub fn hash_at(&self, height: u32) -> Option<&Vec<u8>> {
+        self.best_chain.get(height as usize)
+    }
+
+    pub fn get(&self, hash: &[u8]) -> Option<&HeaderEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Returns true if the header is part of the best chain
+    pub fn is_in_best_chain(&self, hash: &[u8]) -> bool {
+        match self.entries.get(hash) {
+            Some(entry) => self.best_chain.get(entry.height as usize) == Some(&entry.hash),
+            None => false,
+        }
+    }
+
+    /// Returns the ancestor of a known header at the given height
+    pub fn ancestor(&self, hash: &[u8], height: u32) -> Option<&HeaderEntry> {
+        let mut current = self.entries.get(hash)?;
+        if height > current.height {
+            return None;
+        }
+
+        while current.height > height {
+            if self.is_in_best_chain(&current.hash) {
+                return self.get(self.hash_at(height)?);
+            }
+            current = self.entries.get(&current.header.prev_block_hash)?;
+        }
+
+        Some(current)
+    }
+
+    /// Returns the height of the highest checkpoint already in the best chain.
+    /// No header can fork the chain below it
+    pub fn last_checkpoint_height(&self) -> u32 {
+        self.params
+            .checkpoints
+            .iter()
+            .filter(|(_, hash)| self.is_in_best_chain(hash))
+            .map(|(height, _)| *height)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns true once the best chain has at least the minimum chainwork of the network,
+    /// before that it could be a cheap chain of low difficulty headers
+    pub fn has_minimum_chainwork(&self) -> bool {
+        self.best_tip().chainwork >= self.params.minimum_chainwork
+    }
+
+    /// Returns the hashes of the best chain used to ask a peer for the headers that follow it:
+    /// the last 11 headers one by one, then going back exponentially until the genesis
+    pub fn block_locator(&self) -> Vec<Vec<u8>> {
+        let mut locator: Vec<Vec<u8>> = vec![];
+        let mut height = self.best_height() as i64;
+        let mut step = 1;
+
+        while height > 0 {
+            locator.push(self.best_chain[height as usize].clone());
+            if locator.len() > 10 {
+                step *= 2;
+            }
+            height -= step;
+        }
+
+        locator.push(self.best_chain[0].clone());
+        locator
+    }
+
+    /// Returns the height of the last header shared by the branch of the given header
+    /// and the best chain
+    pub fn fork_height(&self, hash: &[u8]) -> Option<u32> {
+        let mut current = self.entries.get(hash)?;
+
+        while !self.is_in_best_chain(&current.hash) {
+            current = self.entries.get(&current.header.prev_block_hash)?;
+        }
+
+        Some(current.height)
+    }
+
+    /// Returns the median of the timestamps of the header and the 10 headers before it
+    pub fn median_time_past(&self, hash: &[u8]) -> Option<u32> {
+        let mut timestamps: Vec<u32> = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut current = self.entries.get(hash);
+
+        while let Some(entry) = current {
+            if timestamps.len() == MEDIAN_TIME_SPAN {
+                break;
+            }
+            timestamps.push(entry.header.timestamp);
+            current = self.entries.get(&entry.header.prev_block_hash);
+        }
+
+        if timestamps.is_empty() {
+            return None;
+        }
+
+        timestamps.sort();
+        Some(timestamps[timestamps.len() / 2])
+    }
+
+    /// Returns the median time past of the best tip
+    pub fn best_median_time_past(&self) -> u32 {
+        self.median_time_past(&self.best_tip().hash).unwrap_or(0)
+    }
+
+    /// Returns the headers of the best chain, without the genesis header
+    pub fn best_chain_headers(&self) -> Vec<BlockHeader> {
+        self.best_chain
+            .iter()
+            .skip(1)
+            .map(|hash| self.entries[hash].header.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::chain_params::Network;
+    use crate::helpers::test_helpers;
+
+    const EASY_BITS: u32 = 0x207fffff;
+
+    /// Hardest bits of the headers mined in the tests
+    const MINED_BITS: u32 = 0x1f7fffff;
+
+    fn regtest() -> ChainParams {
+        ChainParams::new(Network::Regtest)
+    }
+
+    /// Header after the parent, mined when its bits are easy enough
+    fn child(parent: &BlockHeader, nonce: u32, bits: u32, seconds: u32) -> BlockHeader {
+        let header = BlockHeader::new(
+            1,
+            parent.calculate_hash(),
+            vec![1; 32],
+            parent.timestamp + seconds,
+            bits,
+            nonce,
+        );
+        match U256::from_compact(bits) >= U256::from_compact(MINED_BITS) {
+            true => test_helpers::mine(header),
+            false => header,
+        }
+    }
+
+    #[test]
+    pub fn test_headers_are_linked_to_their_parent() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        let first = child(&genesis, 1, EASY_BITS, 600);
+        let second = child(&first, 1, EASY_BITS, 600);
+
+        chain.add_header(first.clone()).unwrap();
+        let hash = chain.add_header(second.clone()).unwrap();
+
+        assert_eq!(chain.best_height(), 2);
+        assert_eq!(chain.best_tip().hash, hash);
+        assert_eq!(chain.height_of(&first.calculate_hash()), Some(1));
+        assert_eq!(chain.header_at(2).unwrap().calculate_hash(), hash);
+        assert_eq!(chain.best_tip().chainwork, work_from_compact(EASY_BITS) * 3);
+    }
+
+    #[test]
+    pub fn test_header_without_known_parent_is_rejected() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        let orphan = child(&child(&genesis, 1, EASY_BITS, 600), 1, EASY_BITS, 600);
+
+        assert_eq!(
+            chain.add_header(orphan),
+            Err(HeaderChainError::UnknownParent)
+        );
+        assert_eq!(chain.best_height(), 0);
+    }
+
+    #[test]
+    pub fn test_branch_with_more_work_becomes_the_best_chain() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        let first = child(&genesis, 1, EASY_BITS, 600);
+        let second = child(&first, 1, EASY_BITS, 600);
+        chain.add_header(first.clone()).unwrap();
+        chain.add_header(second.clone()).unwrap();
+
+        let fork_1 = child(&genesis, 2, EASY_BITS, 600);
+        let fork_2 = child(&fork_1, 2, EASY_BITS, 600);
+        let fork_3 = child(&fork_2, 2, EASY_BITS, 600);
+        chain.add_header(fork_1.clone()).unwrap();
+        chain.add_header(fork_2).unwrap();
+        assert_eq!(chain.best_tip().hash, second.calculate_hash());
+
+        let fork_hash = chain.add_header(fork_3).unwrap();
+
+        assert_eq!(chain.best_height(), 3);
+        assert_eq!(chain.best_tip().hash, fork_hash);
+        assert!(!chain.is_in_best_chain(&second.calculate_hash()));
+        assert_eq!(chain.height_of(&second.calculate_hash()), Some(2));
+        assert_eq!(chain.hash_at(1), Some(&fork_1.calculate_hash()));
+        assert_eq!(
+            chain.ancestor(&second.calculate_hash(), 1).unwrap().hash,
+            first.calculate_hash()
+        );
+    }
+
+    #[test]
+    pub fn test_median_time_past_of_the_last_eleven_headers() {
+        let mut header = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        for _ in 0..12 {
+            header = child(&header, 1, EASY_BITS, 600);
+            chain.add_header(header.clone()).unwrap();
+        }
+
+        // The tip is 12 * 600 seconds after genesis, the median is 5 headers before it
+        assert_eq!(
+            chain.best_median_time_past(),
+            regtest().genesis_header.timestamp + 7 * 600
+        );
+    }
+
+    #[test]
+    pub fn test_header_not_after_the_median_time_past_is_rejected() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        let first = child(&genesis, 1, EASY_BITS, 600);
+        chain.add_header(first.clone()).unwrap();
+
+        let mut old = child(&first, 1, EASY_BITS, 0);
+        old.timestamp = genesis.timestamp;
+        let old = test_helpers::mine(old);
+
+        assert_eq!(chain.add_header(old), Err(HeaderChainError::TimeTooOld));
+    }
+
+    #[test]
+    pub fn test_header_too_far_in_the_future_is_rejected() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        let mut future = child(&genesis, 1, EASY_BITS, 0);
+        future.timestamp = (network_adjusted_time() + MAX_FUTURE_BLOCK_TIME + 600) as u32;
+        let future = test_helpers::mine(future);
+
+        assert_eq!(chain.add_header(future), Err(HeaderChainError::TimeTooNew));
+    }
+
+    #[test]
+    pub fn test_checkpoints_reject_other_headers_and_forks_below_them() {
+        let genesis = regtest().genesis_header;
+        let first = child(&genesis, 1, EASY_BITS, 600);
+        let second = child(&first, 1, EASY_BITS, 600);
+        let mut params = regtest();
+        params.checkpoints = vec![(1, first.calculate_hash())];
+        let mut chain = HeaderChain::new(params);
+
+        assert_eq!(
+            chain.add_header(child(&genesis, 2, EASY_BITS, 600)),
+            Err(HeaderChainError::CheckpointMismatch)
+        );
+        chain.add_header(first.clone()).unwrap();
+        chain.add_header(second).unwrap();
+
+        assert_eq!(chain.last_checkpoint_height(), 1);
+        assert_eq!(
+            chain.add_header(child(&genesis, 3, EASY_BITS, 600)),
+            Err(HeaderChainError::ForkBeforeCheckpoint)
+        );
+        assert!(chain.add_header(child(&first, 2, EASY_BITS, 600)).is_ok());
+    }
+
+    #[test]
+    pub fn test_minimum_chainwork() {
+        let genesis = regtest().genesis_header;
+        let mut params = regtest();
+        params.minimum_chainwork = work_from_compact(EASY_BITS) * 2;
+        let mut chain = HeaderChain::new(params);
+        assert!(!chain.has_minimum_chainwork());
+
+        chain
+            .add_header(child(&genesis, 1, EASY_BITS, 600))
+            .unwrap();
+
+        assert!(chain.has_minimum_chainwork());
+    }
+
+    #[test]
+    pub fn test_block_locator_goes_back_exponentially_until_genesis() {
+        let mut header = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+        for _ in 0..30 {
+            header = child(&header, 1, EASY_BITS, 600);
+            chain.add_header(header.clone()).unwrap();
+        }
+
+        let heights: Vec<u32> = chain
+            .block_locator()
+            .iter()
+            .map(|hash| chain.height_of(hash).unwrap())
+            .collect();
+
+        assert_eq!(
+            heights,
+            vec![30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 18, 14, 6, 0]
+        );
+    }
+
+    #[test]
+    pub fn test_block_locator_of_the_genesis() {
+        let chain = HeaderChain::new(regtest());
+
+        assert_eq!(chain.block_locator(), vec![regtest().genesis_hash()]);
+    }
+
+    #[test]
+    pub fn test_header_with_unexpected_bits_is_rejected() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+
+        assert_eq!(
+            chain.add_header(child(&genesis, 1, 0x1f00ffff, 600)),
+            Err(HeaderChainError::BadDifficulty)
+        );
+    }
+
+    #[test]
+    pub fn test_header_with_the_expected_bits_and_a_high_hash_is_rejected() {
+        let genesis = regtest().genesis_header;
+        let mut chain = HeaderChain::new(regtest());
+
+        let mut header = child(&genesis, 1, EASY_BITS, 600);
+        while header.validate_pow() {
+            header.nonce += 1;
+        }
+
+        assert_eq!(chain.add_header(header), Err(HeaderChainError::HighHash));
+        assert_eq!(chain.best_height(), 0);
+    }
+
+    #[test]
+    pub fn test_testnet_minimum_difficulty_after_twenty_minutes() {
+        // The difficulties are lowered so the headers can be mined
+        let mut params = ChainParams::new(Network::Testnet3);
+        params.pow_limit_bits = EASY_BITS;
+        params.genesis_header.bits = MINED_BITS;
+        let genesis = params.genesis_header.clone();
+        let mut chain = HeaderChain::new(params);
+
+        let first = child(&genesis, 1, MINED_BITS, 600);
+        let slow = child(&first, 1, EASY_BITS, 1201);
+        let too_easy = child(&slow, 1, EASY_BITS, 600);
+        let walked_back = child(&slow, 1, MINED_BITS, 600);
+
+        assert_eq!(
+            chain.add_header(child(&genesis, 2, EASY_BITS, 600)),
+            Err(HeaderChainError::BadDifficulty)
+        );
+        chain.add_header(first).unwrap();
+        chain.add_header(slow).unwrap();
+        assert_eq!(
+            chain.add_header(too_easy),
+            Err(HeaderChainError::BadDifficulty)
+        );
+        assert!(chain.add_header(walked_back).is_ok());
+    }
+}
@@ -1,7 +1,12 @@
-use crate::helpers::auxiliar_functions::{address_from_script, bytes_to_hex, find_spent_utxo};
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::helpers::auxiliar_functions::{
+    address_from_script, bytes_to_hex, find_spent_utxo, serialize_var_int,
+};
 
 use super::{utxo_struct::Utxo, wallet::Wallet};
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub hash: bitcoin_hashes::sha256d::Hash,
@@ -14,6 +19,7 @@ pub struct Transaction {
     pub txid: Vec<u8>,
 }
 
+#[allow(dead_code)]
 impl Transaction {
     pub fn to_hex(&self) -> String {
         self.to_bytes()
@@ -40,13 +46,13 @@ impl Transaction {
     }
     pub fn get_amount(&self, wallet: &mut Wallet) -> u64 {
         let mut vec_outputs: Vec<Utxo> = Vec::new();
-        for (_index, ouput) in self.outputs.iter().enumerate() {
+        for (index, ouput) in self.outputs.iter().enumerate() {
             let string = address_from_script(&ouput.script_pubkey);
 
             if string.is_some() && string.clone().unwrap() == wallet.address {
                 let new_utxo = Utxo {
                     txid: bytes_to_hex(&self.txid),
-                    index: 1,
+                    index: index as u32,
                     value: ouput.value,
                     pubkey: wallet.get_public_key(),
                 };
@@ -106,25 +112,90 @@ impl Transaction {
         );
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![];
+    /// Returns true if any input has witness data
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    /// Returns a copy of the transaction without witness data
+    pub fn without_witness(&self) -> Transaction {
+        let mut transaction = self.clone();
+        for input in transaction.inputs.iter_mut() {
+            input.witness = vec![];
+        }
+        transaction
+    }
+
+    /// Serializes the transaction in the format used by the tx and block messages.
+    /// Transactions with witness data use the segwit format (BIP144)
+    pub fn serialize(&self) -> Vec<u8> {
+        if !self.has_witness() {
+            return self.serialize_without_witness();
+        }
+
+        let mut payload = Vec::new();
+
+        payload.extend_from_slice(&self.version.to_le_bytes());
+        // Marker and flag of the segwit format
+        payload.extend_from_slice(&[0x00, 0x01]);
+        self.serialize_inputs_and_outputs(&mut payload);
+
+        for input in &self.inputs {
+            payload.extend_from_slice(&serialize_var_int(input.witness.len() as u64));
+            for item in &input.witness {
+                payload.extend_from_slice(&serialize_var_int(item.len() as u64));
+                payload.extend_from_slice(item);
+            }
+        }
+
+        payload.extend_from_slice(&self.lock_time.to_le_bytes());
 
-        // Bitcoin utiliza little-endian para el version y lock_time
-        bytes.extend(&self.version.to_le_bytes());
+        payload
+    }
+
+    /// Serializes the transaction without the witness data, as it is hashed for the txid
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        payload.extend_from_slice(&self.version.to_le_bytes());
+        self.serialize_inputs_and_outputs(&mut payload);
+        payload.extend_from_slice(&self.lock_time.to_le_bytes());
+
+        payload
+    }
 
-        bytes.push(self.inputs.len() as u8);
+    fn serialize_inputs_and_outputs(&self, payload: &mut Vec<u8>) {
+        payload.extend_from_slice(&serialize_var_int(self.inputs.len() as u64));
         for input in &self.inputs {
-            bytes.extend(input.to_bytes());
+            payload.extend(input.to_bytes());
         }
 
-        bytes.push(self.outputs.len() as u8);
+        payload.extend_from_slice(&serialize_var_int(self.outputs.len() as u64));
         for output in &self.outputs {
-            bytes.extend(output.to_bytes());
+            payload.extend(output.to_bytes());
         }
+    }
 
-        bytes.extend(&self.lock_time.to_le_bytes());
-        bytes.extend(&[0x01, 0x00, 0x00, 0x00]);
-        bytes
+    /// Hash of the transaction without its witness data, the one used in the merkle root and the outpoints
+    pub fn compute_txid(&self) -> sha256d::Hash {
+        sha256d::Hash::hash(&self.serialize_without_witness())
+    }
+
+    /// Hash of the transaction with its witness data. It is the txid when the transaction has no witness
+    pub fn compute_wtxid(&self) -> sha256d::Hash {
+        sha256d::Hash::hash(&self.serialize())
+    }
+
+    /// Bytes of the transaction as they are sent to the network
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.serialize()
+    }
+
+    /// Returns true if the transaction is a coinbase: a single input that spends the null outpoint
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].previous_output[..32] == [0; 32]
+            && self.inputs[0].previous_output[32..] == [0xff; 4]
     }
 }
 
@@ -139,15 +210,16 @@ pub struct TransactionInput {
     pub previous_output: [u8; 36],
     pub script: Vec<u8>,
     pub sequence: u32,
+    pub witness: Vec<Vec<u8>>,
 }
 
 impl TransactionOutput {
-    fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = vec![];
 
         // Bitcoin utiliza little-endian para el valor
         bytes.extend(&self.value.to_le_bytes());
-        bytes.push(self.script_pubkey.len() as u8);
+        bytes.extend(serialize_var_int(self.script_pubkey.len() as u64));
         bytes.extend(&self.script_pubkey);
 
         bytes
@@ -159,7 +231,7 @@ impl TransactionInput {
         let mut bytes = vec![];
 
         bytes.extend(&self.previous_output);
-        bytes.push(self.script.len() as u8);
+        bytes.extend(serialize_var_int(self.script.len() as u64));
         bytes.extend(&self.script);
         bytes.extend(&self.sequence.to_le_bytes());
 
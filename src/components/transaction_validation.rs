@@ -0,0 +1,447 @@
+use std::collections::HashSet;
+
+use super::{
+    block::WITNESS_SCALE_FACTOR,
+    chainstate::{Chainstate, OutPoint},
+    transaction::Transaction,
+};
+use crate::{
+    configuration::chain_params::chain_params,
+    script::{
+        interpreter::{verify_transaction_scripts, ScriptError},
+        sigops::{count_sigops, p2sh_sigops, witness_sigops},
+    },
+};
+
+/// Amount of satoshis in a bitcoin
+pub const COIN: u64 = 100_000_000;
+
+/// Maximum amount of satoshis that can exist, no value can be above it
+pub const MAX_MONEY: u64 = 21_000_000 * COIN;
+
+/// Amount of blocks that must be on top of a coinbase before its outputs can be spent
+pub const COINBASE_MATURITY: u32 = 100;
+
+/// Lock times below it are heights, the rest are unix times
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Sequence of the inputs that do not use the lock time of the transaction nor a relative lock
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+/// The sequence of the input has no relative lock (BIP68)
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// The relative lock of the sequence is in units of 512 seconds instead of blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// Bits of the sequence with the value of the relative lock
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
+/// Relative time locks are in units of 2^9 seconds
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// #ENUM TransactionError
+/// Represents the reasons a transaction is invalid
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    NoInputs,
+    NoOutputs,
+    OutputValueOutOfRange,
+    TotalOutputOutOfRange,
+    DuplicateInputs,
+    NullPrevout,
+    BadCoinbaseScriptLength,
+    UnexpectedCoinbase,
+    MissingOrSpentInput,
+    PrematureCoinbaseSpend,
+    InputValueOutOfRange,
+    InputsBelowOutputs,
+    NonFinal,
+    SequenceLocksNotMet,
+    InvalidScript(ScriptError),
+}
+
+/// #TDA SequenceLocks
+/// Last height and last median time past at which the relative locks of a transaction
+/// still hold, -1 when there is no lock of that kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceLocks {
+    pub min_height: i64,
+    pub min_time: i64,
+}
+
+/// Returns true if the value is between zero and the maximum amount of money
+pub fn money_range(value: u64) -> bool {
+    value <= MAX_MONEY
+}
+
+/// Checks of the transaction that do not depend on the chain: it has inputs and outputs,
+/// the values are in the money range, no input is repeated and only the coinbase spends the null outpoint
+pub fn check_transaction_sanity(tx: &Transaction) -> Result<(), TransactionError> {
+    if tx.inputs.is_empty() {
+        return Err(TransactionError::NoInputs);
+    }
+    if tx.outputs.is_empty() {
+        return Err(TransactionError::NoOutputs);
+    }
+
+    let mut total_output: u64 = 0;
+    for output in &tx.outputs {
+        if !money_range(output.value) {
+            return Err(TransactionError::OutputValueOutOfRange);
+        }
+        total_output += output.value;
+        if !money_range(total_output) {
+            return Err(TransactionError::TotalOutputOutOfRange);
+        }
+    }
+
+    let mut outpoints = HashSet::new();
+    for input in &tx.inputs {
+        if !outpoints.insert(OutPoint::from_input(input)) {
+            return Err(TransactionError::DuplicateInputs);
+        }
+    }
+
+    if tx.is_coinbase() {
+        if !(2..=100).contains(&tx.inputs[0].script.len()) {
+            return Err(TransactionError::BadCoinbaseScriptLength);
+        }
+    } else if tx
+        .inputs
+        .iter()
+        .any(|input| input.previous_output[..32] == [0; 32])
+    {
+        return Err(TransactionError::NullPrevout);
+    }
+
+    Ok(())
+}
+
+/// Returns true if the lock time of the transaction allows it in a block at the height, with the
+/// time as cutoff for lock times that are unix times. Inputs with the final sequence disable the lock
+pub fn is_final_transaction(tx: &Transaction, height: u32, time: u32) -> bool {
+    if tx.lock_time == 0 {
+        return true;
+    }
+
+    let limit = if tx.lock_time < LOCKTIME_THRESHOLD {
+        height
+    } else {
+        time
+    };
+    if tx.lock_time < limit {
+        return true;
+    }
+
+    tx.inputs
+        .iter()
+        .all(|input| input.sequence == SEQUENCE_FINAL)
+}
+
+/// Calculates the relative locks of the inputs of the transaction from the heights of the coins
+/// they spend (BIP68). Time locks count from the median time past of the block before the coin
+pub fn calculate_sequence_locks(
+    tx: &Transaction,
+    coin_heights: &[u32],
+    chainstate: &Chainstate,
+) -> SequenceLocks {
+    let mut locks = SequenceLocks {
+        min_height: -1,
+        min_time: -1,
+    };
+    if tx.version < 2 {
+        return locks;
+    }
+
+    for (input, coin_height) in tx.inputs.iter().zip(coin_heights) {
+        if input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            continue;
+        }
+
+        let value = (input.sequence & SEQUENCE_LOCKTIME_MASK) as i64;
+        if input.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            // Without the times of the chain the lock can not be proven to be met
+            let coin_time = chainstate
+                .median_time_past(coin_height.saturating_sub(1))
+                .unwrap_or(u32::MAX) as i64;
+            let min_time = coin_time + (value << SEQUENCE_LOCKTIME_GRANULARITY) - 1;
+            locks.min_time = locks.min_time.max(min_time);
+        } else {
+            locks.min_height = locks.min_height.max(*coin_height as i64 + value - 1);
+        }
+    }
+
+    locks
+}
+
+/// Returns true if the relative locks are met in a block at the height whose parent has the median time past
+pub fn evaluate_sequence_locks(locks: &SequenceLocks, height: u32, median_time_past: u32) -> bool {
+    locks.min_height < height as i64 && locks.min_time < median_time_past as i64
+}
+
+/// Checks a transaction, that is not a coinbase, against the chainstate for a block at the
+/// spend height: it is final at the lock time cutoff, every input spends an unspent output, the
+/// coinbase outputs it spends are mature, the relative locks are met once BIP68 is active and the
+/// inputs pay for the outputs. The cutoff is the median time past of the parent of the block
+/// since BIP113, and the time of the block before. Returns the fee of the transaction
+pub fn check_transaction(
+    tx: &Transaction,
+    chainstate: &Chainstate,
+    spend_height: u32,
+    lock_time_cutoff: u32,
+) -> Result<u64, TransactionError> {
+    check_transaction_sanity(tx)?;
+    if tx.is_coinbase() {
+        return Err(TransactionError::UnexpectedCoinbase);
+    }
+    if !is_final_transaction(tx, spend_height, lock_time_cutoff) {
+        return Err(TransactionError::NonFinal);
+    }
+
+    let mut total_input: u64 = 0;
+    let mut coin_heights = vec![];
+    for input in &tx.inputs {
+        let coin = chainstate
+            .get_coin(&OutPoint::from_input(input))
+            .ok_or(TransactionError::MissingOrSpentInput)?;
+        coin_heights.push(coin.height);
+
+        if coin.is_coinbase && spend_height < coin.height + COINBASE_MATURITY {
+            return Err(TransactionError::PrematureCoinbaseSpend);
+        }
+
+        total_input += coin.output.value;
+        if !money_range(coin.output.value) || !money_range(total_input) {
+            return Err(TransactionError::InputValueOutOfRange);
+        }
+    }
+
+    if spend_height >= chain_params().csv_height {
+        let locks = calculate_sequence_locks(tx, &coin_heights, chainstate);
+        if !evaluate_sequence_locks(&locks, spend_height, lock_time_cutoff) {
+            return Err(TransactionError::SequenceLocksNotMet);
+        }
+    }
+
+    let total_output: u64 = tx.outputs.iter().map(|output| output.value).sum();
+    if total_input < total_output {
+        return Err(TransactionError::InputsBelowOutputs);
+    }
+
+    Ok(total_input - total_output)
+}
+
+/// Verifies the script of every input of the transaction, that is not a coinbase, against the
+/// output of the chainstate it spends
+pub fn check_transaction_scripts(
+    tx: &Transaction,
+    chainstate: &Chainstate,
+    flags: u32,
+) -> Result<(), TransactionError> {
+    let spent_outputs = tx
+        .inputs
+        .iter()
+        .map(|input| {
+            chainstate
+                .get_coin(&OutPoint::from_input(input))
+                .map(|coin| coin.output.clone())
+                .ok_or(TransactionError::MissingOrSpentInput)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    verify_transaction_scripts(tx, &spent_outputs, flags).map_err(TransactionError::InvalidScript)
+}
+
+/// Counts the signature operations of the scripts of the transaction, without looking at the outputs it spends
+pub fn legacy_sigops(tx: &Transaction) -> u32 {
+    let input_sigops: u32 = tx
+        .inputs
+        .iter()
+        .map(|input| count_sigops(&input.script, false))
+        .sum();
+    let output_sigops: u32 = tx
+        .outputs
+        .iter()
+        .map(|output| count_sigops(&output.script_pubkey, false))
+        .sum();
+
+    input_sigops + output_sigops
+}
+
+/// Cost of the signature operations of the transaction, counting the redeem scripts and the
+/// witness of the outputs it spends. Witness signature operations cost less than the others
+pub fn transaction_sigop_cost(tx: &Transaction, chainstate: &Chainstate) -> u32 {
+    let scale = WITNESS_SCALE_FACTOR as u32;
+    let mut cost = legacy_sigops(tx) * scale;
+    if tx.is_coinbase() {
+        return cost;
+    }
+
+    for input in &tx.inputs {
+        if let Some(coin) = chainstate.get_coin(&OutPoint::from_input(input)) {
+            let script_pubkey = &coin.output.script_pubkey;
+            cost += p2sh_sigops(&input.script, script_pubkey) * scale;
+            cost += witness_sigops(&input.script, script_pubkey, &input.witness);
+        }
+    }
+
+    cost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{block::Block, block_header::BlockHeader};
+    use crate::helpers::test_helpers;
+    use bitcoin_hashes::Hash;
+
+    fn transaction(previous_outputs: &[[u8; 36]], values: &[u64]) -> Transaction {
+        test_helpers::transaction(
+            previous_outputs
+                .iter()
+                .map(|previous_output| test_helpers::input(*previous_output, vec![0x51, 0x51]))
+                .collect(),
+            values
+                .iter()
+                .map(|value| test_helpers::output(*value, vec![0x51]))
+                .collect(),
+        )
+    }
+
+    fn previous_output(txid: [u8; 32], index: u32) -> [u8; 36] {
+        let mut previous_output = [0u8; 36];
+        previous_output[..32].copy_from_slice(&txid);
+        previous_output[32..].copy_from_slice(&index.to_le_bytes());
+        previous_output
+    }
+
+    /// Chainstate with the outputs of a coinbase of 50 bitcoins at height 1
+    fn chainstate_with_coinbase() -> (Chainstate, [u8; 32]) {
+        let coinbase = transaction(&[previous_output([0; 32], 0xffffffff)], &[50 * COIN]);
+        let txid = coinbase.hash.into_inner();
+        let block = Block::new(
+            BlockHeader::new(1, vec![0; 32], vec![0; 32], 0, 0x207fffff, 0),
+            1,
+            vec![coinbase],
+        );
+
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&block, 1).unwrap();
+        (chainstate, txid)
+    }
+
+    #[test]
+    pub fn test_transaction_sanity() {
+        let input = previous_output([1; 32], 0);
+
+        assert_eq!(
+            check_transaction_sanity(&transaction(&[], &[1])),
+            Err(TransactionError::NoInputs)
+        );
+        assert_eq!(
+            check_transaction_sanity(&transaction(&[input], &[MAX_MONEY + 1])),
+            Err(TransactionError::OutputValueOutOfRange)
+        );
+        assert_eq!(
+            check_transaction_sanity(&transaction(&[input], &[MAX_MONEY, 1])),
+            Err(TransactionError::TotalOutputOutOfRange)
+        );
+        assert_eq!(
+            check_transaction_sanity(&transaction(&[input, input], &[1])),
+            Err(TransactionError::DuplicateInputs)
+        );
+        assert_eq!(
+            check_transaction_sanity(&transaction(&[previous_output([0; 32], 3)], &[1])),
+            Err(TransactionError::NullPrevout)
+        );
+        assert!(check_transaction_sanity(&transaction(&[input], &[1])).is_ok());
+    }
+
+    #[test]
+    pub fn test_fee_of_a_transaction_that_spends_a_mature_coinbase() {
+        let (chainstate, txid) = chainstate_with_coinbase();
+        let tx = transaction(&[previous_output(txid, 0)], &[49 * COIN]);
+
+        assert_eq!(check_transaction(&tx, &chainstate, 101, 0), Ok(COIN));
+    }
+
+    #[test]
+    pub fn test_coinbase_can_not_be_spent_before_maturity() {
+        let (chainstate, txid) = chainstate_with_coinbase();
+        let tx = transaction(&[previous_output(txid, 0)], &[49 * COIN]);
+
+        assert_eq!(
+            check_transaction(&tx, &chainstate, 100, 0),
+            Err(TransactionError::PrematureCoinbaseSpend)
+        );
+    }
+
+    #[test]
+    pub fn test_inputs_must_exist_and_pay_for_the_outputs() {
+        let (chainstate, txid) = chainstate_with_coinbase();
+
+        let missing = transaction(&[previous_output(txid, 1)], &[COIN]);
+        assert_eq!(
+            check_transaction(&missing, &chainstate, 101, 0),
+            Err(TransactionError::MissingOrSpentInput)
+        );
+
+        let too_much = transaction(&[previous_output(txid, 0)], &[51 * COIN]);
+        assert_eq!(
+            check_transaction(&too_much, &chainstate, 101, 0),
+            Err(TransactionError::InputsBelowOutputs)
+        );
+    }
+
+    #[test]
+    pub fn test_lock_time_by_height_and_by_time() {
+        let mut tx = transaction(&[previous_output([1; 32], 0)], &[1]);
+        tx.lock_time = 100;
+        tx.inputs[0].sequence = 0;
+
+        assert!(!is_final_transaction(&tx, 100, 0));
+        assert!(is_final_transaction(&tx, 101, 0));
+
+        tx.lock_time = LOCKTIME_THRESHOLD + 10;
+        assert!(!is_final_transaction(
+            &tx,
+            1_000_000,
+            LOCKTIME_THRESHOLD + 10
+        ));
+        assert!(is_final_transaction(&tx, 0, LOCKTIME_THRESHOLD + 11));
+
+        // Inputs with the final sequence disable the lock
+        tx.inputs[0].sequence = SEQUENCE_FINAL;
+        assert!(is_final_transaction(&tx, 0, 0));
+    }
+
+    #[test]
+    pub fn test_relative_locks_count_from_the_spent_coin() {
+        let (chainstate, txid) = chainstate_with_coinbase();
+        let mut tx = transaction(&[previous_output(txid, 0)], &[COIN]);
+        tx.version = 2;
+
+        tx.inputs[0].sequence = 10;
+        let locks = calculate_sequence_locks(&tx, &[1], &chainstate);
+        assert!(!evaluate_sequence_locks(&locks, 10, 0));
+        assert!(evaluate_sequence_locks(&locks, 11, 0));
+
+        // One unit of 512 seconds from the median time past before the coin, the genesis
+        tx.inputs[0].sequence = SEQUENCE_LOCKTIME_TYPE_FLAG | 1;
+        let genesis_time = chain_params().genesis_header.timestamp;
+        let locks = calculate_sequence_locks(&tx, &[1], &chainstate);
+        assert!(!evaluate_sequence_locks(&locks, 2, genesis_time + 511));
+        assert!(evaluate_sequence_locks(&locks, 2, genesis_time + 512));
+
+        tx.inputs[0].sequence = SEQUENCE_LOCKTIME_DISABLE_FLAG | 10;
+        let locks = calculate_sequence_locks(&tx, &[1], &chainstate);
+        assert!(evaluate_sequence_locks(&locks, 2, 0));
+
+        // The version is unsigned, so the highest ones are not below 2
+        tx.version = 0xffffffff;
+        tx.inputs[0].sequence = 10;
+        let locks = calculate_sequence_locks(&tx, &[1], &chainstate);
+        assert!(!evaluate_sequence_locks(&locks, 10, 0));
+
+        tx.version = 1;
+        let locks = calculate_sequence_locks(&tx, &[1], &chainstate);
+        assert!(evaluate_sequence_locks(&locks, 10, 0));
+    }
+}
@@ -1,14 +1,16 @@
-use std::{fs::File, io, str::FromStr};
+use std::str::FromStr;
 
 use bitcoin_hashes::sha256d;
 
 use secp256k1::Secp256k1;
 
-use crate::helpers::{
-    auxiliar_functions::hex_string_to_reversed_bytes_block_hash, persistance::get_blocks_from_file,
+use crate::{
+    configuration::config_helper::{get_data_dir, is_txindex_enabled},
+    helpers::auxiliar_functions::hex_string_to_reversed_bytes_block_hash,
+    storage::{block_store::BlockStore, tx_index::TxIndex},
 };
 
-use super::{transaction::Transaction, wallet::Wallet};
+use super::{chainstate::Chainstate, transaction::Transaction, wallet::Wallet};
 
 pub struct User {
     pub name: String,
@@ -48,10 +50,16 @@ impl User {
     }
 
     #[allow(dead_code)]
-    pub fn create_transaction(&mut self, recipient: &str, amount: u64, address_string: &str) {
+    pub fn create_transaction(
+        &mut self,
+        recipient: &str,
+        amount: u64,
+        address_string: &str,
+        chainstate: &Chainstate,
+    ) {
         for wallet in self.get_wallets() {
             if wallet.address == address_string {
-                wallet.create_transaction(recipient, amount);
+                wallet.create_transaction(recipient, amount, chainstate);
             }
         }
     }
@@ -90,24 +98,29 @@ impl User {
     }
 }
 
+/// Returns true if the merkle proof of the transaction in the stored block is valid. Without the hash
+/// of the block, it is looked up in the transaction index when it is enabled
 pub fn is_tx_valid_in_block(tx_hash_string: String, block_hash: String) -> bool {
-    let block_hash_bytes = hex_string_to_reversed_bytes_block_hash(&block_hash).unwrap();
     let tx_hash = sha256d::Hash::from_str(&tx_hash_string).unwrap();
+    let block_hash_bytes = if block_hash.is_empty() && is_txindex_enabled() {
+        match TxIndex::open(&get_data_dir())
+            .ok()
+            .and_then(|tx_index| tx_index.get(&tx_hash[..]).cloned())
+        {
+            Some(location) => location.block_hash,
+            None => return false,
+        }
+    } else {
+        hex_string_to_reversed_bytes_block_hash(&block_hash).unwrap()
+    };
 
-    let reader = io::BufReader::new(File::open("logs/blocks_proof_of_inclusion_test.txt").unwrap());
-    let lista_blocks = get_blocks_from_file(reader);
+    let block =
+        BlockStore::open(&get_data_dir()).and_then(|store| store.read_block(&block_hash_bytes));
 
-    for (index, block) in lista_blocks.iter().enumerate() {
-        if block.header.prev_block_hash == block_hash_bytes && index != 0 {
-            if lista_blocks[index - 1].header.prev_block_hash != block_hash_bytes {
-                return lista_blocks[index - 1].is_transaction_valid(tx_hash);
-            } else {
-                return lista_blocks[index - 2].is_transaction_valid(tx_hash);
-            }
-        }
+    match block {
+        Ok(Some(block)) => block.is_transaction_valid(tx_hash),
+        _ => false,
     }
-
-    false
 }
 
 // Tests depend on the current blocks file
@@ -1,16 +1,24 @@
+use std::collections::HashMap;
+
 use bitcoin_hashes::{ripemd160, sha256d, Hash};
 
 use rand::RngCore;
 use secp256k1::{PublicKey, Secp256k1, SecretKey};
 
-use crate::connection::connection_protocol::{fetch_nodes_config, set_tcp_stream_vec};
+use crate::connection::connection_protocol::{connect_outbound_peers, MAX_OUTBOUND_PEERS};
 use crate::helpers::auxiliar_functions::u8_to_hex_string;
 use crate::helpers::auxiliar_functions::{
-    address_from_script, address_to_script_pubkey, bytes_to_hex, find_spent_utxo, hex_to_bytes,
-    hex_to_bytes_rev,
+    address_from_script, address_to_script_pubkey, bytes_to_hex, find_spent_utxo, hex_to_bytes_rev,
+};
+use crate::script::{
+    interpreter::STANDARD_SCRIPT_VERIFY_FLAGS,
+    sighash::{legacy_signature_hash, SIGHASH_ALL},
 };
+use crate::storage::{address_index::AddressIndex, block_store::BlockStore};
 use crate::testnet_protocol::broadcasting::broadcast_transaction;
 
+use super::chainstate::{BlockUndo, Chainstate};
+use super::transaction_validation::{check_transaction, check_transaction_scripts};
 use super::utxo_set::UTXOSet;
 use super::utxo_struct::Utxo;
 use super::{
@@ -109,25 +117,19 @@ impl Wallet {
         self.transactions_history.push(transaction);
     }
 
-    pub fn create_sighash(&self, transaction: &Transaction) -> [u8; 32] {
-        let mut tx_clone = transaction.clone();
-        let bytes = tx_clone.to_bytes();
-        transaction.print_hex();
-        for input in tx_clone.inputs.iter_mut() {
-            input.script = vec![];
-        }
+    /// Hash signed by the input. Before signing, the script of each input is the script pubkey it spends
+    pub fn create_sighash(&self, transaction: &Transaction, input_index: usize) -> [u8; 32] {
+        let script_code = &transaction.inputs[input_index].script;
 
-        let hash1 = sha256d::Hash::hash(&bytes);
-        hash1.into_inner()
+        legacy_signature_hash(transaction, input_index, script_code, SIGHASH_ALL)
     }
 
-    pub fn sign_transaction(&mut self, transaction: &mut Transaction) {
+    /// Signs every input of the transaction with the key of the wallet
+    pub fn sign_transaction(&self, transaction: &mut Transaction) {
         let secp = Secp256k1::signing_only();
 
-        let sighashes: Vec<_> = transaction
-            .inputs
-            .iter()
-            .map(|_| self.create_sighash(transaction))
+        let sighashes: Vec<_> = (0..transaction.inputs.len())
+            .map(|input_index| self.create_sighash(transaction, input_index))
             .collect();
 
         for (input, sighash) in transaction.inputs.iter_mut().zip(sighashes) {
@@ -146,62 +148,36 @@ impl Wallet {
 
             input.script = script_sig.clone();
         }
-        let tx_ser = transaction.to_bytes();
-        transaction.txid = bitcoin_hashes::sha256d::Hash::hash(&tx_ser)
-            .into_inner()
-            .to_vec();
-        transaction.hash = bitcoin_hashes::sha256d::Hash::hash(&tx_ser);
-
-        let hex_string = transaction.to_hex();
-
-        let start_index = hex_string.len() - 8;
-        let string_slice = &hex_string[..start_index];
-        println!("Signed transaction: {:?} \n ", string_slice);
-
-        println!(
-            "Signed transaction on SHA256d: {}",
-            bitcoin_hashes::sha256d::Hash::hash(&hex_to_bytes(string_slice))
-        );
-
-        let string_hash =
-            bitcoin_hashes::sha256d::Hash::hash(&hex_to_bytes(string_slice)).to_string();
-
-        let bytes_hash: Vec<u8> = string_hash
-            .as_bytes()
-            .chunks(2)
-            .map(|chunk| {
-                let byte_string = unsafe { std::str::from_utf8_unchecked(chunk) };
-                u8::from_str_radix(byte_string, 16).unwrap()
-            })
-            .collect();
-
-        let reversed_bytes: Vec<u8> = bytes_hash.into_iter().rev().collect();
-
-        println!(
-            "HASH SIMULANDO PARSEO ---> {}",
-            u8_to_hex_string(&reversed_bytes)
-        );
-
-        let nodes = fetch_nodes_config().unwrap();
-        let tcp_stream_vec = set_tcp_stream_vec(nodes[..].to_vec());
+        transaction.hash = transaction.compute_txid();
+        transaction.txid = transaction.hash.into_inner().to_vec();
+    }
 
-        println!("Antes de broadcastear...");
+    /// Sends the signed transaction to the peers and adds it to the history. If no peer can be
+    /// connected the transaction is not sent
+    fn send_transaction(&mut self, transaction: &Transaction) {
+        let tcp_stream_vec = match connect_outbound_peers(MAX_OUTBOUND_PEERS) {
+            Ok(tcp_stream_vec) => tcp_stream_vec,
+            Err(e) => {
+                println!(
+                    "No se pudo conectar a los peers para enviar la transaccion: {}",
+                    e
+                );
+                return;
+            }
+        };
 
-        broadcast_transaction(hex_to_bytes(string_slice), tcp_stream_vec.unwrap());
+        broadcast_transaction(transaction.to_bytes(), tcp_stream_vec);
 
         self.transactions_history.push(transaction.clone());
     }
 
-    pub fn create_transaction(&mut self, recipient: &str, amount: u64) {
+    /// Creates a transaction that pays the amount to the recipient with the utxos of the wallet.
+    /// It is only broadcasted if it is valid for the next block of the chainstate and its signed
+    /// scripts pass the standard script checks
+    pub fn create_transaction(&mut self, recipient: &str, amount: u64, chainstate: &Chainstate) {
         let mut utxos_to_spent = Vec::new();
         let mut total_to_spend = 0;
 
-        println!("self utxo vueltos : {:?}", self.utxos_vueltos.clone());
-        println!(
-            "CATNIDAD self utxo vueltos : {:?}",
-            self.utxos_vueltos.len()
-        );
-
         for utxo in self.utxos_vueltos.clone() {
             if total_to_spend >= amount {
                 break;
@@ -227,6 +203,7 @@ impl Wallet {
                     previous_output: bytes_arr,
                     script: address_to_script_pubkey(&self.address),
                     sequence: 0xffffffff,
+                    witness: vec![],
                 };
                 new_inputs.push(new_input);
             }
@@ -238,9 +215,6 @@ impl Wallet {
                 script_pubkey: address_to_script_pubkey(recipient),
             });
 
-            println!("TOTAL TO SPEND : {}", total_to_spend);
-            println!("AMOUNT : {}", amount);
-
             let change = total_to_spend - amount;
             if change > 0 {
                 new_outputs.push(TransactionOutput {
@@ -260,7 +234,28 @@ impl Wallet {
                 txid: vec![],              // to be filled later
             };
 
+            let lock_time_cutoff = chainstate
+                .median_time_past(chainstate.tip_height())
+                .unwrap_or(0);
+            if let Err(error) = check_transaction(
+                &transaction,
+                chainstate,
+                chainstate.tip_height() + 1,
+                lock_time_cutoff,
+            ) {
+                println!("La transaccion no es valida: {:?}", error);
+                return;
+            }
+
             self.sign_transaction(&mut transaction);
+            if let Err(error) =
+                check_transaction_scripts(&transaction, chainstate, STANDARD_SCRIPT_VERIFY_FLAGS)
+            {
+                println!("La transaccion firmada no es valida: {:?}", error);
+                return;
+            }
+
+            self.send_transaction(&transaction);
         }
     }
 
@@ -309,6 +304,69 @@ impl Wallet {
     pub fn get_utxos(&mut self) -> Vec<Utxo> {
         self.utxo_set.utxos.clone()
     }
+
+    /// Replaces the utxos of the wallet with the unspent outputs of the chainstate that pay to its address
+    pub fn load_utxos_from_chainstate(&mut self, chainstate: &Chainstate) {
+        let script_pubkey = address_to_script_pubkey(&self.address);
+
+        self.utxo_set.utxos = chainstate
+            .coins_of_script(&script_pubkey)
+            .into_iter()
+            .map(|(outpoint, coin)| Utxo {
+                txid: u8_to_hex_string(&outpoint.txid),
+                index: outpoint.index,
+                value: coin.output.value,
+                pubkey: self.public_key,
+            })
+            .collect();
+        self.calculate_balance();
+    }
+
+    /// Replaces the utxos and the history of the wallet with the ones of its address in the
+    /// address index, so it does not need the blocks to be replayed. The transactions of the
+    /// history are read from their blocks in the block store
+    pub fn load_history_from_index(
+        &mut self,
+        address_index: &AddressIndex,
+        block_store: &BlockStore,
+    ) {
+        let history = address_index.history(&address_to_script_pubkey(&self.address));
+
+        self.utxo_set.utxos = history
+            .unspent()
+            .into_iter()
+            .map(|entry| Utxo {
+                txid: u8_to_hex_string(&entry.outpoint.txid),
+                index: entry.outpoint.index,
+                value: entry.value,
+                pubkey: self.public_key,
+            })
+            .collect();
+
+        let mut blocks: HashMap<Vec<u8>, Block> = HashMap::new();
+        let mut transactions = Vec::new();
+        for (txid, block_hash) in history.transactions() {
+            if !blocks.contains_key(&block_hash) {
+                match block_store.read_block(&block_hash) {
+                    Ok(Some(block)) => {
+                        blocks.insert(block_hash.clone(), block);
+                    }
+                    _ => continue,
+                }
+            }
+
+            if let Some(tx) = blocks[&block_hash]
+                .txns
+                .iter()
+                .find(|tx| tx.hash.into_inner() == txid)
+            {
+                transactions.push(tx.clone());
+            }
+        }
+        self.transactions_history = transactions;
+
+        self.calculate_balance();
+    }
 }
 
 pub fn update_wallet(wallet: &mut Wallet, block: Block) {
@@ -322,13 +380,13 @@ pub fn update_wallet(wallet: &mut Wallet, block: Block) {
             }
         }
 
-        for (_index, ouput) in tx.outputs.iter().enumerate() {
+        for (index, ouput) in tx.outputs.iter().enumerate() {
             let string = address_from_script(&ouput.script_pubkey);
 
             if string.is_some() && string.clone().unwrap() == wallet.address {
                 let new_utxo = Utxo {
                     txid: bytes_to_hex(&tx.txid),
-                    index: 1,
+                    index: index as u32,
                     value: ouput.value,
                     pubkey: wallet.get_public_key(),
                 };
@@ -357,3 +415,32 @@ pub fn update_wallet(wallet: &mut Wallet, block: Block) {
         }
     }
 }
+
+/// Rolls back a block disconnected from the chain, one transaction at a time from the last one:
+/// removes the utxos and the transaction and then restores the utxos of the wallet it spent
+pub fn rollback_wallet(wallet: &mut Wallet, block: &Block, undo: &BlockUndo) {
+    let script_pubkey = address_to_script_pubkey(&wallet.address);
+    let spent_coins = undo.coins_spent_by(&block.txns);
+
+    for (tx, spent_coins) in block.txns.iter().zip(spent_coins).rev() {
+        let txid = u8_to_hex_string(&tx.compute_txid().into_inner());
+        wallet.utxo_set.utxos.retain(|utxo| utxo.txid != txid);
+        wallet.utxos_vueltos.retain(|utxo| utxo.txid != txid);
+        wallet
+            .transactions_history
+            .retain(|transaction| transaction.hash != tx.hash);
+
+        for (outpoint, coin) in spent_coins {
+            if coin.output.script_pubkey == script_pubkey {
+                wallet.utxo_set.add_utxo(Utxo {
+                    txid: u8_to_hex_string(&outpoint.txid),
+                    index: outpoint.index,
+                    value: coin.output.value,
+                    pubkey: wallet.public_key,
+                });
+            }
+        }
+    }
+
+    wallet.calculate_balance();
+}
@@ -0,0 +1,377 @@
+use std::sync::OnceLock;
+
+use super::config_helper::get_configuration;
+use crate::{
+    components::block_header::BlockHeader,
+    helpers::{auxiliar_functions::string_to_reversed_bytes, uint256::U256},
+    script::interpreter::{SCRIPT_VERIFY_P2SH, SCRIPT_VERIFY_WITNESS},
+};
+
+/// Merkle root of the genesis block, the same coinbase is used by every network
+const GENESIS_MERKLE_ROOT: &str =
+    "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+/// Network used when the configuration does not have the network key
+const DEFAULT_NETWORK: Network = Network::Testnet3;
+
+static CHAIN_PARAMS: OnceLock<ChainParams> = OnceLock::new();
+
+/// #ENUM Network
+/// Represents the bitcoin networks the node can connect to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Network {
+    Mainnet,
+    Testnet3,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    /// Parses the value of the network key of the configuration file
+    pub fn from_name(name: &str) -> Option<Network> {
+        match name.trim().to_lowercase().as_str() {
+            "mainnet" | "main" | "bitcoin" => Some(Network::Mainnet),
+            "testnet3" | "testnet" | "test" => Some(Network::Testnet3),
+            "signet" => Some(Network::Signet),
+            "regtest" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+}
+
+/// #TDA ChainParams
+/// Contains every value that changes between the bitcoin networks
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    pub network: Network,
+    pub magic: u32,
+    pub default_port: u16,
+    pub genesis_header: BlockHeader,
+    pub pow_limit_bits: u32,
+    pub pow_target_timespan: u32,
+    pub pow_target_spacing: u32,
+    pub allow_min_difficulty_blocks: bool,
+    pub no_retargeting: bool,
+    pub pubkey_address_prefix: u8,
+    pub script_address_prefix: u8,
+    pub bech32_hrp: &'static str,
+    /// Subdirectory of the data directory where the files of the network are kept, empty for mainnet
+    pub data_subdir: &'static str,
+    pub dns_seeds: Vec<&'static str>,
+    pub checkpoints: Vec<(u32, Vec<u8>)>,
+    pub minimum_chainwork: U256,
+    pub subsidy_halving_interval: u32,
+    pub bip34_height: u32,
+    /// Height from which relative lock times and the median time past cutoff are enforced (BIP68, BIP112, BIP113)
+    pub csv_height: u32,
+    /// Height from which OP_CHECKLOCKTIMEVERIFY is enforced (BIP65)
+    pub bip65_height: u32,
+    /// Height from which signatures must be strict DER (BIP66)
+    pub bip66_height: u32,
+    /// Height from which the dummy element of OP_CHECKMULTISIG must be empty (BIP147)
+    pub segwit_height: u32,
+    /// Blocks, by hash, whose scripts are verified with other flags than the rest
+    pub script_flag_exceptions: Vec<(Vec<u8>, u32)>,
+}
+
+impl ChainParams {
+    /// Returns the parameters of the given network
+    pub fn new(network: Network) -> ChainParams {
+        match network {
+            Network::Mainnet => ChainParams {
+                network,
+                magic: 0xd9b4bef9,
+                default_port: 8333,
+                genesis_header: genesis_header(1231006505, 0x1d00ffff, 2083236893),
+                pow_limit_bits: 0x1d00ffff,
+                pow_target_timespan: 14 * 24 * 60 * 60,
+                pow_target_spacing: 10 * 60,
+                allow_min_difficulty_blocks: false,
+                no_retargeting: false,
+                pubkey_address_prefix: 0x00,
+                script_address_prefix: 0x05,
+                bech32_hrp: "bc",
+                data_subdir: "",
+                dns_seeds: vec![
+                    "seed.bitcoin.sipa.be",
+                    "dnsseed.bluematt.me",
+                    "seed.bitcoinstats.com",
+                    "seed.bitcoin.jonasschnelli.ch",
+                    "seed.btc.petertodd.org",
+                ],
+                checkpoints: checkpoints(&[
+                    (
+                        11111,
+                        "0000000069e244f73d78e8fd29ba2fd2ed618bd6fa2ee92559f542fdb26e7c1d",
+                    ),
+                    (
+                        33333,
+                        "000000002dd5588a74784eaa7ab0507a18ad16a236e7b1ce69f00d7ddfb5d0a6",
+                    ),
+                    (
+                        74000,
+                        "0000000000573993a3c9e41ce34471c079dcf5f52a0e824a81e7f953b8661a20",
+                    ),
+                    (
+                        105000,
+                        "00000000000291ce28027faea320c8d2b054b2e0fe44a773f3eefb151d6bdc97",
+                    ),
+                    (
+                        134444,
+                        "00000000000005b12ffd4cd315cd34ffd4a594f430ac814c91184a0d42d2b0fe",
+                    ),
+                    (
+                        168000,
+                        "000000000000099e61ea72015e79632f216fe6cb33d7899acb35b75c8303b763",
+                    ),
+                    (
+                        193000,
+                        "000000000000059f452a5f7340de6682a977387c17010ff6e6c3bd83ca8b1317",
+                    ),
+                    (
+                        210000,
+                        "000000000000048b95347e83192f69cf0366076336c639f9b7228e9ba171342e",
+                    ),
+                    (
+                        216116,
+                        "00000000000001b4f4b433e81ee46494af945cf96014816a4e2370f11b23df4e",
+                    ),
+                    (
+                        225430,
+                        "00000000000001c108384350f74090433e7fcf79a606b8e797f065b130575932",
+                    ),
+                    (
+                        250000,
+                        "000000000000003887df1f29024b06fc2200b55f8af8f35453d7be294df2d214",
+                    ),
+                    (
+                        279000,
+                        "0000000000000001ae8c72a0b0c301f67e3afca10e819efa9041e458e9bd7e40",
+                    ),
+                    (
+                        295000,
+                        "00000000000000004d9b4ef50f0f9d686fd69db2e03af35a100370c64632a983",
+                    ),
+                ]),
+                minimum_chainwork: chainwork(
+                    "00000000000000000000000000000000000000003404ba0801921119f903495e",
+                ),
+                subsidy_halving_interval: 210_000,
+                bip34_height: 227_931,
+                csv_height: 419_328,
+                bip65_height: 388_381,
+                bip66_height: 363_725,
+                segwit_height: 481_824,
+                script_flag_exceptions: script_flag_exceptions(&[
+                    (
+                        "00000000000002dc756eebf4f49723ed8d30cc28a5f108eb94b1ba88ac4f9c22",
+                        0,
+                    ),
+                    (
+                        "0000000000000000000f14c35b2d841e986ab5441de8c585d5ffe55ea1e395ad",
+                        SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS,
+                    ),
+                ]),
+            },
+            Network::Testnet3 => ChainParams {
+                network,
+                magic: 0x0709110b,
+                default_port: 18333,
+                genesis_header: genesis_header(1296688602, 0x1d00ffff, 414098458),
+                pow_limit_bits: 0x1d00ffff,
+                pow_target_timespan: 14 * 24 * 60 * 60,
+                pow_target_spacing: 10 * 60,
+                allow_min_difficulty_blocks: true,
+                no_retargeting: false,
+                pubkey_address_prefix: 0x6f,
+                script_address_prefix: 0xc4,
+                bech32_hrp: "tb",
+                data_subdir: "testnet3",
+                dns_seeds: vec![
+                    "testnet-seed.bitcoin.jonasschnelli.ch",
+                    "seed.tbtc.petertodd.org",
+                    "seed.testnet.bitcoin.sprovoost.nl",
+                    "testnet-seed.bluematt.me",
+                ],
+                checkpoints: checkpoints(&[(
+                    546,
+                    "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70",
+                )]),
+                minimum_chainwork: chainwork(
+                    "00000000000000000000000000000000000000000000076f6e7cbd0beade5d20",
+                ),
+                subsidy_halving_interval: 210_000,
+                bip34_height: 21_111,
+                csv_height: 770_112,
+                bip65_height: 581_885,
+                bip66_height: 330_776,
+                segwit_height: 834_624,
+                script_flag_exceptions: script_flag_exceptions(&[(
+                    "00000000dd30457c001f4095d208cc1296b0eed002427aa599874af7a432b105",
+                    0,
+                )]),
+            },
+            Network::Signet => ChainParams {
+                network,
+                magic: 0x40cf030a,
+                default_port: 38333,
+                genesis_header: genesis_header(1598918400, 0x1e0377ae, 52613770),
+                pow_limit_bits: 0x1e0377ae,
+                pow_target_timespan: 14 * 24 * 60 * 60,
+                pow_target_spacing: 10 * 60,
+                allow_min_difficulty_blocks: false,
+                no_retargeting: false,
+                pubkey_address_prefix: 0x6f,
+                script_address_prefix: 0xc4,
+                bech32_hrp: "tb",
+                data_subdir: "signet",
+                dns_seeds: vec!["seed.signet.bitcoin.sprovoost.nl"],
+                checkpoints: vec![],
+                minimum_chainwork: chainwork(
+                    "0000000000000000000000000000000000000000000000000000015f5e0c9f13",
+                ),
+                subsidy_halving_interval: 210_000,
+                bip34_height: 1,
+                csv_height: 1,
+                bip65_height: 1,
+                bip66_height: 1,
+                segwit_height: 1,
+                script_flag_exceptions: vec![],
+            },
+            Network::Regtest => ChainParams {
+                network,
+                magic: 0xdab5bffa,
+                default_port: 18444,
+                genesis_header: genesis_header(1296688602, 0x207fffff, 2),
+                pow_limit_bits: 0x207fffff,
+                pow_target_timespan: 14 * 24 * 60 * 60,
+                pow_target_spacing: 10 * 60,
+                allow_min_difficulty_blocks: true,
+                no_retargeting: true,
+                pubkey_address_prefix: 0x6f,
+                script_address_prefix: 0xc4,
+                bech32_hrp: "bcrt",
+                data_subdir: "regtest",
+                dns_seeds: vec![],
+                checkpoints: vec![],
+                minimum_chainwork: U256::ZERO,
+                subsidy_halving_interval: 150,
+                bip34_height: 1,
+                csv_height: 1,
+                bip65_height: 1,
+                bip66_height: 1,
+                segwit_height: 0,
+                script_flag_exceptions: vec![],
+            },
+        }
+    }
+
+    /// Returns the hash of the genesis block
+    pub fn genesis_hash(&self) -> Vec<u8> {
+        self.genesis_header.calculate_hash()
+    }
+
+    /// Amount of blocks between two difficulty adjustments
+    pub fn difficulty_adjustment_interval(&self) -> u32 {
+        self.pow_target_timespan / self.pow_target_spacing
+    }
+}
+
+fn genesis_header(timestamp: u32, bits: u32, nonce: u32) -> BlockHeader {
+    BlockHeader::new(
+        1,
+        vec![0; 32],
+        string_to_reversed_bytes(GENESIS_MERKLE_ROOT.to_owned()),
+        timestamp,
+        bits,
+        nonce,
+    )
+}
+
+fn checkpoints(checkpoints: &[(u32, &str)]) -> Vec<(u32, Vec<u8>)> {
+    checkpoints
+        .iter()
+        .map(|(height, hash)| (*height, string_to_reversed_bytes(hash.to_string())))
+        .collect()
+}
+
+fn script_flag_exceptions(exceptions: &[(&str, u32)]) -> Vec<(Vec<u8>, u32)> {
+    exceptions
+        .iter()
+        .map(|(hash, flags)| (string_to_reversed_bytes(hash.to_string()), *flags))
+        .collect()
+}
+
+fn chainwork(hex: &str) -> U256 {
+    U256::from_le_bytes(&string_to_reversed_bytes(hex.to_owned()))
+}
+
+/// Returns the parameters of the network selected with the network key of the configuration.
+/// They are read only once, the first time they are needed
+pub fn chain_params() -> &'static ChainParams {
+    CHAIN_PARAMS.get_or_init(|| {
+        let network = match get_configuration()
+            .and_then(|mut configuration| configuration.get_value_from_key("network".to_owned()))
+        {
+            Ok(name) => Network::from_name(&name).unwrap_or_else(|| {
+                println!(
+                    "Error: unknown network {}, using {:?}",
+                    name, DEFAULT_NETWORK
+                );
+                DEFAULT_NETWORK
+            }),
+            Err(_) => DEFAULT_NETWORK,
+        };
+
+        ChainParams::new(network)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::auxiliar_functions::reverse_hash;
+
+    fn genesis_hash_hex(network: Network) -> String {
+        reverse_hash(&ChainParams::new(network).genesis_hash())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_genesis_hash_of_every_network() {
+        assert_eq!(
+            genesis_hash_hex(Network::Mainnet),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(
+            genesis_hash_hex(Network::Testnet3),
+            "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943"
+        );
+        assert_eq!(
+            genesis_hash_hex(Network::Signet),
+            "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6"
+        );
+        assert_eq!(
+            genesis_hash_hex(Network::Regtest),
+            "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206"
+        );
+    }
+
+    #[test]
+    pub fn test_network_from_name() {
+        assert_eq!(Network::from_name("testnet3"), Some(Network::Testnet3));
+        assert_eq!(Network::from_name(" Regtest "), Some(Network::Regtest));
+        assert_eq!(Network::from_name("litecoin"), None);
+    }
+
+    #[test]
+    pub fn test_difficulty_adjustment_interval() {
+        assert_eq!(
+            ChainParams::new(Network::Mainnet).difficulty_adjustment_interval(),
+            2016
+        );
+    }
+}
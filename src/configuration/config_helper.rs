@@ -1,5 +1,8 @@
-use super::configuration_loader::{Configuration, ConfigurationError};
-use std::env;
+use super::{
+    chain_params::chain_params,
+    configuration_loader::{Configuration, ConfigurationError},
+};
+use std::{env, path::Path};
 
 /// #ENUM ParametersEnterError
 /// Represents the possible errors in the parameters input
@@ -32,3 +35,59 @@ pub fn read_configuration_file_path_parameter() -> Result<String, ParametersEnte
         }
     }
 }
+
+/// Returns the directory where the node keeps the data of its network, creating it if it does not
+/// exist. It is read from the key data_dir of the configuration file, "data" by default, and the
+/// files of every network but mainnet are kept in a subdirectory, so they are never mixed
+pub fn get_data_dir() -> String {
+    let data_dir = get_configuration()
+        .and_then(|mut configuration| configuration.get_value_from_key("data_dir".to_owned()))
+        .unwrap_or_else(|_| "data".to_owned());
+    let data_dir = match chain_params().data_subdir {
+        "" => data_dir,
+        subdir => Path::new(&data_dir)
+            .join(subdir)
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    if std::fs::create_dir_all(&data_dir).is_err() {
+        println!(
+            "Error: the data directory {} could not be created",
+            data_dir
+        );
+    }
+
+    data_dir
+}
+
+/// Returns true if the key txindex of the configuration file is 1 or true, so the node
+/// keeps an index of the confirmed transactions. It is disabled by default
+pub fn is_txindex_enabled() -> bool {
+    is_option_enabled("txindex")
+}
+
+/// Returns true if the key addressindex of the configuration file is 1 or true, so the node
+/// keeps an index of the history of every script. It is disabled by default
+pub fn is_addressindex_enabled() -> bool {
+    is_option_enabled("addressindex")
+}
+
+/// Returns the unix time of the first block the client downloads, read from the key
+/// blocks_start_time of the configuration file. With 0, the default, every block is downloaded
+/// and validated. Otherwise the chainstate is partial and the inputs of the blocks are not validated
+pub fn get_blocks_start_time() -> u32 {
+    get_configuration()
+        .and_then(|mut configuration| {
+            configuration.get_value_from_key("blocks_start_time".to_owned())
+        })
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn is_option_enabled(key: &str) -> bool {
+    get_configuration()
+        .and_then(|mut configuration| configuration.get_value_from_key(key.to_owned()))
+        .is_ok_and(|value| value == "1" || value == "true")
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, Error, ErrorKind, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+};
+
+use crate::{
+    storage::record_file::write_atomically,
+    testnet_protocol::messages::network_message::NetworkAddress,
+};
+
+/// Name of the file, inside the data directory, where the address book is saved
+pub const ADDRESS_BOOK_FILE: &str = "peers.txt";
+
+/// Maximum amount of addresses kept in the book
+const MAX_ADDRESSES: usize = 2000;
+
+/// Maximum amount of addresses answered to a getaddr message
+pub const MAX_ADDR_TO_SEND: usize = 1000;
+
+/// After this many failed connections in a row the address is discarded
+const MAX_FAILED_ATTEMPTS: u32 = 3;
+
+/// Represents a known address of the network with the information used to score it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressEntry {
+    pub address: SocketAddr,
+    pub services: u64,
+    pub last_seen: u32,
+    pub score: i32,
+    pub attempts: u32,
+}
+
+/// #TDA AddressBook
+/// Keeps the addresses of the peers learned from the DNS seed and the addr messages,
+/// scored by how well the connections to them went
+#[derive(Debug, Clone)]
+pub struct AddressBook {
+    entries: HashMap<SocketAddr, AddressEntry>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        AddressBook {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Loads the book saved in the data directory. If there is no file yet, returns an empty book
+    pub fn load(data_dir: &str) -> Result<AddressBook, Error> {
+        let mut book = AddressBook::new();
+
+        let file = match File::open(Path::new(data_dir).join(ADDRESS_BOOK_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(book),
+            Err(e) => return Err(e),
+        };
+
+        for line in BufReader::new(file).lines() {
+            if let Some(entry) = parse_entry(&line?) {
+                book.entries.insert(entry.address, entry);
+            }
+        }
+
+        Ok(book)
+    }
+
+    /// Saves the book in the data directory, one address per line. The file is replaced
+    /// atomically, so a crash while saving leaves the previous book
+    pub fn save(&self, data_dir: &str) -> Result<(), Error> {
+        fs::create_dir_all(data_dir)?;
+
+        let mut data = Vec::new();
+        for entry in self.entries.values() {
+            writeln!(
+                data,
+                "{} {} {} {} {}",
+                entry.address, entry.services, entry.last_seen, entry.score, entry.attempts
+            )?;
+        }
+
+        write_atomically(&Path::new(data_dir).join(ADDRESS_BOOK_FILE), &data)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds an address to the book. If it was already known only its last seen time is updated
+    pub fn add(&mut self, address: SocketAddr, services: u64, last_seen: u32) {
+        if let Some(entry) = self.entries.get_mut(&address) {
+            entry.last_seen = entry.last_seen.max(last_seen);
+            entry.services |= services;
+            return;
+        }
+
+        if self.entries.len() >= MAX_ADDRESSES {
+            self.remove_worst();
+        }
+
+        self.entries.insert(
+            address,
+            AddressEntry {
+                address,
+                services,
+                last_seen,
+                score: 0,
+                attempts: 0,
+            },
+        );
+    }
+
+    /// Adds the addresses received in an addr message
+    pub fn add_network_addresses(&mut self, addresses: &[NetworkAddress]) {
+        for address in addresses {
+            let ip = match address.ip.to_ipv4_mapped() {
+                Some(ipv4) => IpAddr::V4(ipv4),
+                None => IpAddr::V6(address.ip),
+            };
+
+            if address.port != 0 && !ip.is_unspecified() {
+                self.add(
+                    SocketAddr::new(ip, address.port),
+                    address.services,
+                    address.time,
+                );
+            }
+        }
+    }
+
+    /// Registers a successful connection to the address
+    pub fn mark_success(&mut self, address: &SocketAddr) {
+        let now = chrono::Utc::now().timestamp() as u32;
+
+        match self.entries.get_mut(address) {
+            Some(entry) => {
+                entry.score += 1;
+                entry.attempts = 0;
+                entry.last_seen = now;
+            }
+            None => {
+                self.add(*address, 1, now);
+                self.mark_success(address);
+            }
+        }
+    }
+
+    /// Registers a failed connection to the address, discarding it if it failed too many times
+    pub fn mark_failure(&mut self, address: &SocketAddr) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            entry.score -= 1;
+            entry.attempts += 1;
+
+            if entry.attempts >= MAX_FAILED_ATTEMPTS {
+                self.entries.remove(address);
+            }
+        }
+    }
+
+    /// Returns the best addresses to connect to: highest score first, and the most recently seen
+    /// between the ones with the same score
+    pub fn select_peers(&self, amount: usize) -> Vec<SocketAddr> {
+        let mut entries: Vec<&AddressEntry> = self.entries.values().collect();
+
+        entries.sort_by(|a, b| b.score.cmp(&a.score).then(b.last_seen.cmp(&a.last_seen)));
+
+        entries
+            .into_iter()
+            .take(amount)
+            .map(|entry| entry.address)
+            .collect()
+    }
+
+    /// Returns the addresses to answer a getaddr message
+    pub fn to_network_addresses(&self, amount: usize) -> Vec<NetworkAddress> {
+        self.select_peers(amount)
+            .into_iter()
+            .filter_map(|address| self.entries.get(&address))
+            .map(|entry| {
+                let ip = match entry.address.ip() {
+                    IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+                    IpAddr::V6(ipv6) => ipv6,
+                };
+
+                NetworkAddress {
+                    time: entry.last_seen,
+                    services: entry.services,
+                    ip,
+                    port: entry.address.port(),
+                }
+            })
+            .collect()
+    }
+
+    fn remove_worst(&mut self) {
+        let worst = self
+            .entries
+            .values()
+            .min_by(|a, b| a.score.cmp(&b.score).then(a.last_seen.cmp(&b.last_seen)))
+            .map(|entry| entry.address);
+
+        if let Some(address) = worst {
+            self.entries.remove(&address);
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Option<AddressEntry> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 5 {
+        return None;
+    }
+
+    Some(AddressEntry {
+        address: parts[0].parse().ok()?,
+        services: parts[1].parse().ok()?,
+        last_seen: parts[2].parse().ok()?,
+        score: parts[3].parse().ok()?,
+        attempts: parts[4].parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn address(last: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, last], 18333))
+    }
+
+    #[test]
+    pub fn test_select_peers_prefers_the_best_scored_addresses() {
+        let mut book = AddressBook::new();
+        book.add(address(1), 1, 100);
+        book.add(address(2), 1, 200);
+        book.add(address(3), 1, 300);
+
+        book.mark_success(&address(1));
+        book.mark_failure(&address(3));
+
+        assert_eq!(book.select_peers(2), vec![address(1), address(2)]);
+    }
+
+    #[test]
+    pub fn test_address_is_discarded_after_failing_too_many_times() {
+        let mut book = AddressBook::new();
+        book.add(address(1), 1, 100);
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            book.mark_failure(&address(1));
+        }
+
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    pub fn test_addresses_of_addr_message_are_added() {
+        let mut book = AddressBook::new();
+        let mut network_address = NetworkAddress::from_ipv4(Ipv4Addr::new(10, 0, 0, 7), 18333, 1);
+        network_address.time = 500;
+
+        book.add_network_addresses(&[network_address.clone()]);
+
+        assert_eq!(book.select_peers(10), vec![address(7)]);
+        assert_eq!(book.to_network_addresses(10), vec![network_address]);
+    }
+
+    #[test]
+    pub fn test_book_is_the_same_after_saving_and_loading_it() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_address_book_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let mut book = AddressBook::new();
+        book.add(address(1), 9, 100);
+        book.mark_success(&address(1));
+
+        book.save(data_dir).unwrap();
+        let loaded = AddressBook::load(data_dir).unwrap();
+
+        assert_eq!(loaded.entries, book.entries);
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
@@ -1,7 +1,6 @@
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{self, Error, Read},
+    io::{self, Error},
     net::{TcpListener, TcpStream},
     str::FromStr,
     sync::{
@@ -17,23 +16,37 @@ use secp256k1::SecretKey;
 use crate::{
     components::{
         block::Block,
-        block_header::BlockHeader,
+        chainstate::Chainstate,
         user::{is_tx_valid_in_block, User},
         wallet::{update_wallet, Wallet},
     },
-    connection::connection_protocol::handshake_server,
-    helpers::persistance::{get_blocks_from_file, get_headers_from_file},
+    configuration::{
+        chain_params::chain_params,
+        config_helper::{get_data_dir, is_addressindex_enabled, is_txindex_enabled},
+    },
+    connection::{
+        address_book::{AddressBook, MAX_ADDR_TO_SEND},
+        connection_protocol::handshake_server,
+        network_time::add_time_sample,
+        peer_liveness::{read_peer_message, write_peer_message, PeerTracker},
+    },
     interface::interfaz_grafica::{
         interfaz, BalanceData, ChannelData, DownloadData, TransactionData,
     },
+    storage::{
+        address_index::AddressIndex,
+        block_index::BlockIndex,
+        block_store::BlockStore,
+        chainstate_store::{load_chainstate, restore_wallet},
+    },
     testnet_protocol::{
         block_download::initial_block_download,
         client_handlers::{handle_getdata::handle_getdata, handle_getheaders::handle_getheaders},
-        messages::message_parsers::parse_message,
+        messages::network_message::NetworkMessage,
     },
 };
 
-use super::connection_protocol::fetch_nodes_config;
+use super::connection_protocol::{connect_outbound_peers, MAX_OUTBOUND_PEERS};
 
 #[allow(dead_code)]
 fn handle_user_interface(
@@ -111,6 +124,8 @@ fn handle_recived_data(
     data: ChannelData,
     hashtable_wallets: Arc<Mutex<HashMap<String, Wallet>>>,
     node_sender: Arc<Mutex<glib::Sender<ChannelData>>>,
+    chainstate: &Mutex<Chainstate>,
+    address_index: &Option<Arc<Mutex<AddressIndex>>>,
 ) -> Result<(), io::Error> {
     match data {
         ChannelData::Account(account_info) => {
@@ -123,10 +138,6 @@ fn handle_recived_data(
 
             let mut user = User::new("Nico".to_owned());
             user.create_new_wallet(&private_key, &account_info.address);
-            let _reader = io::BufReader::new(
-                File::open("logs/blocks.txt").expect("Error to open logs/blocks"),
-            );
-
             // let lista_blocks = get_blocks_from_memory(reader, );
 
             // for mut wallet in user.get_wallets() {
@@ -143,10 +154,34 @@ fn handle_recived_data(
                 user.get_wallets()[0].clone(),
             );
 
-            let _wallet = hashtable_wallets_blocked
+            let wallet = hashtable_wallets_blocked
                 .get_mut(&account_info.address.to_string())
                 .unwrap();
 
+            // The wallet saved with the tip of the chainstate is already up to date. Otherwise,
+            // with the address index the history of addresses added after the download is known
+            let chainstate_blocked = chainstate.lock().unwrap();
+            let restored = match chainstate_blocked.tip_hash() {
+                Some(tip_hash) => {
+                    restore_wallet(&get_data_dir(), tip_hash, wallet).unwrap_or_else(|e| {
+                        println!("No se pudo restaurar la wallet guardada: {}", e);
+                        false
+                    })
+                }
+                None => false,
+            };
+            drop(chainstate_blocked);
+
+            if !restored {
+                match (address_index, BlockStore::open(&get_data_dir())) {
+                    (Some(address_index), Ok(block_store)) => {
+                        wallet
+                            .load_history_from_index(&address_index.lock().unwrap(), &block_store);
+                    }
+                    _ => wallet.load_utxos_from_chainstate(&chainstate.lock().unwrap()),
+                }
+            }
+
             //handle_user_interface(wallet, node_sender.clone()); // enviando a la interfaz los nuevos datos
 
             drop(hashtable_wallets_blocked);
@@ -168,7 +203,11 @@ fn handle_recived_data(
             hashtable_wallets_blocked
                 .get_mut(&sender_payment.own_address)
                 .unwrap()
-                .create_transaction(&sender_payment.address, sender_payment.amount as u64);
+                .create_transaction(
+                    &sender_payment.address,
+                    sender_payment.amount as u64,
+                    &chainstate.lock().unwrap(),
+                );
 
             drop(hashtable_wallets_blocked);
         }
@@ -230,11 +269,29 @@ pub fn client_mode() {
 
     let node_sender_copy = Arc::clone(&node_sender);
 
+    let chainstate = Arc::new(Mutex::new(load_stored_chainstate()));
+    let chainstate_copy = Arc::clone(&chainstate);
+
+    let hashtable_wallets: Arc<Mutex<HashMap<String, Wallet>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+    let hashtable_wallets_copy = Arc::clone(&hashtable_wallets);
+
+    let address_index = load_address_index();
+    let address_index_copy = address_index.clone();
+
     let _ibd_thread = thread::spawn(move || {
         println!("Comenzando descarga en hilo descarga...");
-        let nodes = fetch_nodes_config().expect("Error in fetching nodes");
-
-        initial_block_download(nodes[0..6].to_vec(), node_sender_copy).unwrap();
+        let tcp_stream_vec =
+            connect_outbound_peers(MAX_OUTBOUND_PEERS).expect("Error in connecting to peers");
+
+        initial_block_download(
+            tcp_stream_vec,
+            node_sender_copy,
+            chainstate_copy,
+            hashtable_wallets_copy,
+            address_index_copy,
+        )
+        .unwrap();
     });
 
     let node_thread = thread::spawn(move || {
@@ -247,15 +304,17 @@ pub fn client_mode() {
         //     stablish_block_broadcasting(tcp_stream_vec.unwrap(), sender); // en su propio hilo, para que se qeude trabado ese hilo
         // });
 
-        let hashtable_wallets_: HashMap<String, Wallet> = HashMap::new();
-        let hashtable_wallets: Arc<Mutex<HashMap<String, Wallet>>> =
-            Arc::new(Mutex::new(hashtable_wallets_));
-
         loop {
             if let Ok(data) = node_receiver.try_recv() {
                 println!("SE RECIBIO ALGO DE LA INTERFAZ");
-                if handle_recived_data(data, hashtable_wallets.clone(), node_sender.clone())
-                    .is_err()
+                if handle_recived_data(
+                    data,
+                    hashtable_wallets.clone(),
+                    node_sender.clone(),
+                    &chainstate,
+                    &address_index,
+                )
+                .is_err()
                 {
                     break;
                 }
@@ -275,28 +334,72 @@ pub fn client_mode() {
     node_thread.join().expect("Other thread panicked.");
 }
 
+/// Loads the chainstate saved by the previous runs, so the download resumes from its tip
+fn load_stored_chainstate() -> Chainstate {
+    match load_chainstate(&get_data_dir()) {
+        Ok(Some(chainstate)) => {
+            println!(
+                "Chainstate guardado de ejecuciones anteriores ---> altura {}",
+                chainstate.tip_height()
+            );
+            chainstate
+        }
+        Ok(None) => Chainstate::new(),
+        Err(e) => {
+            println!("No se pudo leer el chainstate guardado: {}", e);
+            Chainstate::new()
+        }
+    }
+}
+
+/// Opens the address index of the data directory if it is enabled in the configuration
+fn load_address_index() -> Option<Arc<Mutex<AddressIndex>>> {
+    if !is_addressindex_enabled() {
+        return None;
+    }
+
+    match AddressIndex::open(&get_data_dir()) {
+        Ok(address_index) => Some(Arc::new(Mutex::new(address_index))),
+        Err(e) => {
+            println!("No se pudo abrir el indice de direcciones: {}", e);
+            None
+        }
+    }
+}
+
 pub fn server_mode() -> Result<(), Error> {
     println!("Cargando files en memoria, esperar a ser avisado para correr el Cliente...");
     println!("La carga de files en memoria puede tardar unos minutos...");
 
-    let listener = TcpListener::bind("0.0.0.0:18333")?;
-    let reader_headers = io::BufReader::new(File::open("logs/headers.txt").unwrap());
-    let headers: Vec<BlockHeader> = get_headers_from_file(reader_headers);
-    let headers = Arc::new(headers);
-
-    let reader_blocks = io::BufReader::new(File::open("logs/blocks.txt").unwrap());
-    let blocks = get_blocks_from_file(reader_blocks);
-    let blocks: Arc<Vec<Block>> = Arc::new(blocks);
+    let listener = TcpListener::bind(("0.0.0.0", chain_params().default_port))?;
+    let data_dir = get_data_dir();
+    let mut block_index = BlockIndex::load(&data_dir)?;
+    if is_txindex_enabled() {
+        let indexed = block_index.enable_tx_index(&data_dir)?;
+        println!(
+            " SE AGREGARON {} bloques AL INDICE DE TRANSACCIONES",
+            indexed
+        );
+    }
+    let block_index = Arc::new(block_index);
 
-    println!(" SE DESCARGARON DEL FILE {} headers", headers.len());
-    println!(" SE DESCARGARON DEL FILE {} bloques", blocks.len());
+    println!(" SE CARGARON {} headers", block_index.best_height());
+    println!(" HAY {} bloques GUARDADOS", block_index.stored_blocks());
 
     println!("Ya se puede iniciar el Cliente...");
 
     println!("Servidor escuchando conexiones...");
 
+    let peer_tracker = PeerTracker::new();
+    let address_book = Arc::new(Mutex::new(AddressBook::load(&data_dir)?));
+
     for stream in listener.incoming() {
-        handle_response(stream, headers.clone(), blocks.clone());
+        handle_response(
+            stream,
+            block_index.clone(),
+            &peer_tracker,
+            address_book.clone(),
+        );
     }
 
     Ok(())
@@ -304,32 +407,30 @@ pub fn server_mode() -> Result<(), Error> {
 
 fn handle_response(
     stream: Result<TcpStream, Error>,
-    headers: Arc<Vec<BlockHeader>>,
-    blocks: Arc<Vec<Block>>,
+    block_index: Arc<BlockIndex>,
+    tracker: &PeerTracker,
+    address_book: Arc<Mutex<AddressBook>>,
 ) {
     match stream {
-        Ok(mut stream) => {
+        Ok(stream) => {
             println!(" recibo conexion");
 
-            let headers = Arc::clone(&headers);
-            let blocks = Arc::clone(&blocks);
-
-            let handle = thread::spawn(move || loop {
-                let mut response_buffer = [0; 100000];
-                let read_size = stream.read(&mut response_buffer).unwrap();
-
-                if read_size == 0 {
-                    break;
+            if tracker.start_keepalive(&stream).is_err() {
+                return;
+            }
+            let tracker = tracker.clone();
+
+            let block_index = Arc::clone(&block_index);
+
+            let handle = thread::spawn(move || {
+                while let Ok(message) = read_peer_message(&stream, &tracker) {
+                    handle_command(
+                        message,
+                        stream.try_clone().unwrap(),
+                        &block_index,
+                        &address_book,
+                    );
                 }
-
-                let (command, _payload) = parse_message(&response_buffer, None).unwrap();
-                handle_command(
-                    command,
-                    stream.try_clone().unwrap(),
-                    headers.clone(),
-                    blocks.clone(),
-                    &response_buffer,
-                );
             });
             handle.join().unwrap();
         }
@@ -338,30 +439,45 @@ fn handle_response(
 }
 
 fn handle_command(
-    command: String,
+    message: NetworkMessage,
     mut stream: TcpStream,
-    headers: Arc<Vec<BlockHeader>>,
-    blocks: Arc<Vec<Block>>,
-    buffer: &[u8],
+    block_index: &BlockIndex,
+    address_book: &Mutex<AddressBook>,
 ) {
-    match command.as_str() {
-        "version" => {
+    match message {
+        NetworkMessage::Version(version) => {
             println!(" SE RECIBE VERSION");
+            add_time_sample(version.timestamp);
             let _ = handshake_server(&stream);
         }
-        "verack" => {
+        NetworkMessage::Verack => {
             println!(" SE RECIBE VERACK");
         }
-        "getheaders" => {
+        NetworkMessage::GetHeaders(get_headers) => {
             println!(" SE RECIBE GET HEADERS");
-            handle_getheaders(buffer, &mut stream, &headers);
+            handle_getheaders(&get_headers, &mut stream, block_index);
         }
-        "getdata" => {
+        NetworkMessage::GetData(inventory) => {
             println!(" SE RECIBE GET DATA");
-            handle_getdata(buffer, &mut stream, &blocks);
+            handle_getdata(&inventory, &mut stream, block_index);
+        }
+        NetworkMessage::GetAddr => {
+            println!(" SE RECIBE GET ADDR");
+            let addresses = match address_book.lock() {
+                Ok(book) => book.to_network_addresses(MAX_ADDR_TO_SEND),
+                Err(_) => return,
+            };
+            let _ = write_peer_message(&stream, &NetworkMessage::Addr(addresses).to_bytes());
+        }
+        NetworkMessage::Addr(addresses) => {
+            println!(" SE RECIBE ADDR");
+            if let Ok(mut book) = address_book.lock() {
+                book.add_network_addresses(&addresses);
+                let _ = book.save(&get_data_dir());
+            }
         }
         _ => {
-            println!("Command not found: {}", command);
+            println!("Command not found: {}", message.command());
         }
     }
 }
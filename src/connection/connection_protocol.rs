@@ -1,21 +1,38 @@
 use crate::{
-    configuration::config_helper::get_configuration,
-    testnet_protocol::messages::message_builders::{
-        build_verack_header_message, build_version_message,
+    configuration::{
+        chain_params::chain_params,
+        config_helper::{get_configuration, get_data_dir},
+    },
+    connection::{
+        address_book::AddressBook, network_time::add_time_sample, peer_liveness::write_peer_message,
+    },
+    testnet_protocol::messages::{
+        message_builders::{build_verack_header_message, build_version_message},
+        message_codec::read_until_command,
+        network_message::{read_network_message, NetworkMessage},
     },
 };
 
 use std::{
+    collections::HashSet,
     io::{Error, ErrorKind},
     net::{SocketAddr, TcpStream, ToSocketAddrs},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use std::io::{Read, Write};
+/// Amount of outbound peers the client connects to
+pub const MAX_OUTBOUND_PEERS: usize = 6;
+
+/// Time a peer has to answer the getaddr message
+const GETADDR_TIMEOUT: Duration = Duration::from_secs(5);
+
+use std::io::Write;
 use std::vec;
-use std::vec::IntoIter;
 
-pub fn set_tcp_stream_vec(nodes: Vec<String>) -> Result<Vec<TcpStream>, Error> {
+pub fn set_tcp_stream_vec(
+    nodes: Vec<String>,
+    address_book: &mut AddressBook,
+) -> Result<Vec<TcpStream>, Error> {
     let mut tcp_stream_vec: Vec<TcpStream> = vec![];
     println!("nodes {:?}", nodes);
     for i in &nodes {
@@ -24,10 +41,16 @@ pub fn set_tcp_stream_vec(nodes: Vec<String>) -> Result<Vec<TcpStream>, Error> {
             match TcpStream::connect_timeout(&addr, Duration::from_secs(2)) {
                 Ok(socket_1) => {
                     println!(" socket {:?} ", socket_1);
-                    handshake(&socket_1)?;
+                    if handshake(&socket_1).is_err() {
+                        address_book.mark_failure(&addr);
+                        continue;
+                    }
+                    address_book.mark_success(&addr);
+                    request_peer_addresses(&socket_1, address_book);
                     tcp_stream_vec.push(socket_1);
                 }
                 Err(_e) => {
+                    address_book.mark_failure(&addr);
                     continue;
                 }
             }
@@ -37,47 +60,163 @@ pub fn set_tcp_stream_vec(nodes: Vec<String>) -> Result<Vec<TcpStream>, Error> {
     Ok(tcp_stream_vec)
 }
 
+/// Connects to the best known peers of the address book saved in the data directory, going down
+/// the scored addresses until the amount of peers is connected or every address was tried.
+/// The addresses the connected peers send are tried too. The DNS seed is only queried when the
+/// book does not have enough addresses
+pub fn connect_outbound_peers(amount: usize) -> Result<Vec<TcpStream>, Error> {
+    let data_dir = get_data_dir();
+    let mut address_book = AddressBook::load(&data_dir)?;
+    add_seed_nodes(&mut address_book, amount);
+
+    let mut tried: HashSet<String> = HashSet::new();
+    let mut tcp_stream_vec: Vec<TcpStream> = vec![];
+    while tcp_stream_vec.len() < amount {
+        let mut nodes: Vec<String> = vec![];
+        for node in select_outbound_peers(&address_book)? {
+            if nodes.len() + tcp_stream_vec.len() >= amount {
+                break;
+            }
+            if tried.insert(node.clone()) {
+                nodes.push(node);
+            }
+        }
+        if nodes.is_empty() {
+            break;
+        }
+
+        tcp_stream_vec.extend(set_tcp_stream_vec(nodes, &mut address_book)?);
+    }
+
+    address_book.save(&data_dir)?;
+
+    Ok(tcp_stream_vec)
+}
+
+/// Returns the addresses to connect to: the custom ip of the configuration first
+/// and then every address of the book, the best scored first
+fn select_outbound_peers(address_book: &AddressBook) -> Result<Vec<String>, Error> {
+    let mut nodes: Vec<String> = fetch_custom_node_config()?.into_iter().collect();
+
+    for addr in address_book.select_peers(address_book.len()) {
+        let node = addr.to_string();
+        if !nodes.contains(&node) {
+            nodes.push(node);
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Adds the nodes of the DNS seeds to the book when it does not have the amount of addresses
+fn add_seed_nodes(address_book: &mut AddressBook, amount: usize) {
+    if address_book.len() < amount {
+        match fetch_nodes_config() {
+            Ok(seed_nodes) => {
+                for node in seed_nodes {
+                    if let Ok(addr) = node.parse::<SocketAddr>() {
+                        address_book.add(addr, 1, 0);
+                    }
+                }
+            }
+            Err(_) => {
+                println!("No se pudo consultar el DNS seed, se usa la libreta de direcciones")
+            }
+        }
+    }
+}
+
+/// Returns the custom node of the configuration, if there is one
+fn fetch_custom_node_config() -> Result<Option<String>, Error> {
+    let custom_ip = get_configuration()?.get_value_from_key("custom_ip".to_owned())?;
+
+    if custom_ip.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(format!(
+        "{}:{}",
+        custom_ip,
+        chain_params().default_port
+    )))
+}
+
+/// Sends a getaddr message to the peer and adds the addresses it sends to the book, until it
+/// answers with more than one address or the time to answer runs out. The messages with a single
+/// address are usually the peer announcing itself, so they do not end the wait
+pub fn request_peer_addresses(mut socket: &TcpStream, address_book: &mut AddressBook) {
+    if socket
+        .write_all(&NetworkMessage::GetAddr.to_bytes())
+        .is_err()
+    {
+        return;
+    }
+
+    let deadline = Instant::now() + GETADDR_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+        let message = match read_network_message(&mut socket) {
+            Ok(message) => message,
+            Err(_) => break,
+        };
+
+        match message {
+            NetworkMessage::Addr(addresses) => {
+                println!("Direcciones recibidas ---> {}", addresses.len());
+                address_book.add_network_addresses(&addresses);
+                if addresses.len() > 1 {
+                    break;
+                }
+            }
+            NetworkMessage::Ping(nonce) => {
+                let _ = socket.write_all(&NetworkMessage::Pong(nonce).to_bytes());
+            }
+            _ => {}
+        }
+    }
+
+    let _ = socket.set_read_timeout(None);
+}
+
+/// Returns the custom node of the configuration followed by the nodes answered by the DNS seeds
+/// of the network
 pub fn fetch_nodes_config() -> Result<Vec<String>, Error> {
-    let mut config = get_configuration()
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get the configuration"))?;
-
-    let dns_protocol = config
-        .get_value_from_key("protocol_version".to_owned())
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get the protocol version"))?;
-
-    let dns_direction = config
-        .get_value_from_key("direction".to_owned())
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get direction"))?;
-
-    let addrs_iter: IntoIter<SocketAddr> = format!("{}:{}", dns_direction, dns_protocol)
-        .to_socket_addrs()
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to convert to socket addresses"))?;
-
-    let custom_ip = config
-        .get_value_from_key("custom_ip".to_owned())
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get cusotm_ip"))?;
-
-    let nodes: Vec<String> = if !custom_ip.is_empty() {
-        format!("{}:{}", custom_ip, dns_protocol)
-            .to_socket_addrs()?
-            .chain(addrs_iter)
-            .map(|addr| addr.to_string())
-            .collect()
-    } else {
-        addrs_iter.map(|addr| addr.to_string()).collect()
-    };
+    let params = chain_params();
+
+    let mut addrs: Vec<SocketAddr> = vec![];
+    for dns_seed in &params.dns_seeds {
+        match (*dns_seed, params.default_port).to_socket_addrs() {
+            Ok(seed_addrs) => addrs.extend(seed_addrs),
+            Err(_) => println!("No se pudo consultar el DNS seed {}", dns_seed),
+        }
+    }
+
+    let mut nodes: Vec<String> = fetch_custom_node_config()?.into_iter().collect();
+    nodes.extend(addrs.into_iter().map(|addr| addr.to_string()));
+
+    if nodes.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "Failed to convert to socket addresses",
+        ));
+    }
 
     println!("Peers discovered ---> {:?}", nodes.len());
 
     Ok(nodes)
 }
 
-pub fn handshake_server(mut socket: &TcpStream) -> Result<(), Error> {
+/// Answers the version of a peer that connected to the server. Its keepalive is already running,
+/// so the messages are written with the lock of the socket
+pub fn handshake_server(socket: &TcpStream) -> Result<(), Error> {
     let version_msg = build_version_message()?;
-    socket.write_all(&version_msg)?;
+    write_peer_message(socket, &version_msg)?;
 
     let verack_msg = build_verack_header_message();
-    socket.write_all(&verack_msg)?;
+    write_peer_message(socket, &verack_msg)?;
 
     Ok(())
 }
@@ -88,8 +227,10 @@ pub fn handshake(mut socket: &TcpStream) -> Result<(), Error> {
     socket.write_all(&version_msg)?;
 
     // lectura del version del servidor
-    let mut response_buffer = [0; 1024];
-    let _ = socket.read(&mut response_buffer)?;
+    let version = read_until_command(&mut socket, "version")?;
+    if let NetworkMessage::Version(version) = NetworkMessage::deserialize(version)? {
+        add_time_sample(version.timestamp);
+    }
 
     let header_verack_msg = build_verack_header_message();
     socket.write_all(&header_verack_msg)?;
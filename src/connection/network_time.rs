@@ -0,0 +1,74 @@
+use std::sync::Mutex;
+
+use chrono::Utc;
+
+/// Minimum amount of peers needed before the local clock is adjusted
+const MIN_TIME_SAMPLES: usize = 5;
+
+/// Maximum amount of peers whose offset is kept
+const MAX_TIME_SAMPLES: usize = 200;
+
+/// Biggest adjustment applied to the local clock, in seconds
+const MAX_TIME_ADJUSTMENT: i64 = 70 * 60;
+
+/// Offsets between the clocks of the peers and the local clock, in seconds
+static TIME_OFFSETS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+/// Registers the timestamp sent by a peer in its version message
+pub fn add_time_sample(peer_timestamp: i64) {
+    if let Ok(mut offsets) = TIME_OFFSETS.lock() {
+        if offsets.len() < MAX_TIME_SAMPLES {
+            offsets.push(peer_timestamp - Utc::now().timestamp());
+        }
+    }
+}
+
+/// Returns the median of the offsets of the peers. The local clock is used as it is
+/// while there are few peers or when they are too far away from it
+pub fn time_offset() -> i64 {
+    match TIME_OFFSETS.lock() {
+        Ok(offsets) => median_offset(&offsets),
+        Err(_) => 0,
+    }
+}
+
+fn median_offset(offsets: &[i64]) -> i64 {
+    if offsets.len() < MIN_TIME_SAMPLES {
+        return 0;
+    }
+
+    let mut sorted = offsets.to_vec();
+    sorted.sort();
+    let median = sorted[sorted.len() / 2];
+
+    if median.abs() > MAX_TIME_ADJUSTMENT {
+        return 0;
+    }
+
+    median
+}
+
+/// Returns the local time adjusted with the clocks of the peers
+pub fn network_adjusted_time() -> i64 {
+    Utc::now().timestamp() + time_offset()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_few_samples_do_not_adjust_the_clock() {
+        assert_eq!(median_offset(&[100, 100, 100, 100]), 0);
+    }
+
+    #[test]
+    pub fn test_offset_is_the_median_of_the_samples() {
+        assert_eq!(median_offset(&[-30, 500, 10, 20, 4000]), 20);
+    }
+
+    #[test]
+    pub fn test_offsets_too_far_from_the_local_clock_are_ignored() {
+        assert_eq!(median_offset(&[5000, 5000, 5000, 5000, 5000]), 0);
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashMap,
+    io::{Error, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::testnet_protocol::messages::network_message::{read_network_message, NetworkMessage};
+
+/// Time between two pings sent to the same peer
+pub const PING_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Time a peer has to answer a ping before being disconnected
+pub const PING_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Lock of the socket of every peer, so the messages written by different threads on clones of
+/// the socket do not interleave their bytes
+static PEER_WRITERS: OnceLock<Mutex<HashMap<SocketAddr, Arc<Mutex<()>>>>> = OnceLock::new();
+
+/// Represents what we know about the activity of a connected peer
+#[derive(Debug, Clone)]
+pub struct PeerLiveness {
+    pending_ping: Option<(u64, Instant)>,
+    latency: Option<Duration>,
+}
+
+impl PeerLiveness {
+    fn new() -> Self {
+        PeerLiveness {
+            pending_ping: None,
+            latency: None,
+        }
+    }
+
+    /// A peer timed out if it did not answer the pending ping in the given time
+    pub fn has_timed_out(&self, timeout: Duration) -> bool {
+        match self.pending_ping {
+            Some((_, sent_at)) => sent_at.elapsed() > timeout,
+            None => false,
+        }
+    }
+}
+
+/// #TDA PeerTracker
+/// Keeps the liveness of every connected peer. It can be cloned and shared
+/// between the threads that read from the peers and the keepalive threads
+#[derive(Debug, Clone)]
+pub struct PeerTracker {
+    peers: Arc<Mutex<HashMap<SocketAddr, PeerLiveness>>>,
+}
+
+impl PeerTracker {
+    pub fn new() -> Self {
+        PeerTracker {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn with_peer<T>(&self, addr: &SocketAddr, f: impl FnOnce(&mut PeerLiveness) -> T) -> Option<T> {
+        match self.peers.lock() {
+            Ok(mut peers) => peers.get_mut(addr).map(f),
+            Err(_) => None,
+        }
+    }
+
+    /// Starts tracking a peer
+    pub fn register(&self, addr: SocketAddr) {
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.insert(addr, PeerLiveness::new());
+        }
+    }
+
+    /// Stops tracking a peer, its keepalive thread finishes on the next interval
+    pub fn remove(&self, addr: &SocketAddr) {
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.remove(addr);
+        }
+        if let Ok(mut writers) = PEER_WRITERS.get_or_init(Default::default).lock() {
+            writers.remove(addr);
+        }
+    }
+
+    /// Returns true while the peer is connected and tracked
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.with_peer(addr, |_| ()).is_some()
+    }
+
+    /// Returns the addresses of all the tracked peers
+    #[allow(dead_code)]
+    pub fn connected_peers(&self) -> Vec<SocketAddr> {
+        match self.peers.lock() {
+            Ok(peers) => peers.keys().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Returns true if the peer is connected and answered the last ping, so a new one can be sent.
+    /// While a ping is pending no other is sent, so its age is the time the peer did not answer
+    pub fn needs_ping(&self, addr: &SocketAddr) -> bool {
+        self.with_peer(addr, |peer| peer.pending_ping.is_none())
+            .unwrap_or(false)
+    }
+
+    /// Registers the nonce of a ping sent to the peer
+    pub fn ping_sent(&self, addr: &SocketAddr, nonce: u64) {
+        self.with_peer(addr, |peer| {
+            peer.pending_ping = Some((nonce, Instant::now()))
+        });
+    }
+
+    /// Registers a pong of the peer. Returns true if it answers the pending ping,
+    /// in which case the round trip latency is updated
+    pub fn pong_received(&self, addr: &SocketAddr, nonce: u64) -> bool {
+        self.with_peer(addr, |peer| match peer.pending_ping {
+            Some((pending_nonce, sent_at)) if pending_nonce == nonce => {
+                peer.latency = Some(sent_at.elapsed());
+                peer.pending_ping = None;
+                true
+            }
+            _ => false,
+        })
+        .unwrap_or(false)
+    }
+
+    /// Returns the last round trip latency measured for the peer
+    #[allow(dead_code)]
+    pub fn latency(&self, addr: &SocketAddr) -> Option<Duration> {
+        self.with_peer(addr, |peer| peer.latency).flatten()
+    }
+
+    /// Returns true if the peer did not answer the last ping in time
+    pub fn is_unresponsive(&self, addr: &SocketAddr) -> bool {
+        self.with_peer(addr, |peer| peer.has_timed_out(PING_TIMEOUT))
+            .unwrap_or(true)
+    }
+
+    /// Registers the peer and spawns the thread that pings it periodically.
+    /// If the peer does not answer in time the socket is closed, so any thread
+    /// blocked reading from it finishes with an error
+    pub fn start_keepalive(&self, socket: &TcpStream) -> Result<(), Error> {
+        let addr = socket.peer_addr()?;
+        let socket = socket.try_clone()?;
+        let tracker = self.clone();
+
+        tracker.register(addr);
+
+        thread::spawn(move || loop {
+            thread::sleep(PING_INTERVAL);
+
+            if !tracker.is_connected(&addr) {
+                break;
+            }
+
+            if tracker.is_unresponsive(&addr) {
+                println!("El peer {} no responde, se desconecta", addr);
+                tracker.remove(&addr);
+                let _ = socket.shutdown(Shutdown::Both);
+                break;
+            }
+
+            if !tracker.needs_ping(&addr) {
+                continue;
+            }
+
+            let nonce: u64 = rand::random();
+            tracker.ping_sent(&addr, nonce);
+            if write_peer_message(&socket, &NetworkMessage::Ping(nonce).to_bytes()).is_err() {
+                tracker.remove(&addr);
+                break;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Writes the message to the peer holding the lock of its socket, so it is not mixed with the
+/// messages other threads write to the same peer
+pub fn write_peer_message(mut socket: &TcpStream, message: &[u8]) -> Result<(), Error> {
+    let addr = socket.peer_addr()?;
+    let writer = match PEER_WRITERS.get_or_init(Default::default).lock() {
+        Ok(mut writers) => writers.entry(addr).or_default().clone(),
+        Err(_) => return Err(Error::other("Peer writers lock poisoned")),
+    };
+
+    let _guard = writer
+        .lock()
+        .map_err(|_| Error::other("Peer writer lock poisoned"))?;
+    socket.write_all(message)
+}
+
+/// Reads the next message of the peer answering its pings and registering its pongs,
+/// so the callers only receive the messages they are interested in
+pub fn read_peer_message(
+    mut socket: &TcpStream,
+    tracker: &PeerTracker,
+) -> Result<NetworkMessage, Error> {
+    let addr = socket.peer_addr()?;
+
+    loop {
+        let message = match read_network_message(&mut socket) {
+            Ok(message) => message,
+            Err(e) => {
+                tracker.remove(&addr);
+                return Err(e);
+            }
+        };
+
+        match message {
+            NetworkMessage::Ping(nonce) => {
+                write_peer_message(socket, &NetworkMessage::Pong(nonce).to_bytes())?;
+            }
+            NetworkMessage::Pong(nonce) => {
+                tracker.pong_received(&addr, nonce);
+            }
+            _ => return Ok(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 18333))
+    }
+
+    #[test]
+    pub fn test_pong_with_the_pending_nonce_updates_latency() {
+        let tracker = PeerTracker::new();
+        tracker.register(addr());
+        tracker.ping_sent(&addr(), 42);
+
+        assert!(tracker.pong_received(&addr(), 42));
+        assert!(tracker.latency(&addr()).is_some());
+        assert!(!tracker.is_unresponsive(&addr()));
+    }
+
+    #[test]
+    pub fn test_pong_with_another_nonce_is_ignored() {
+        let tracker = PeerTracker::new();
+        tracker.register(addr());
+        tracker.ping_sent(&addr(), 42);
+
+        assert!(!tracker.pong_received(&addr(), 7));
+        assert!(tracker.latency(&addr()).is_none());
+    }
+
+    #[test]
+    pub fn test_peer_without_answer_times_out() {
+        let mut peer = PeerLiveness::new();
+        assert!(!peer.has_timed_out(Duration::ZERO));
+
+        peer.pending_ping = Some((1, Instant::now()));
+        thread::sleep(Duration::from_millis(5));
+
+        assert!(peer.has_timed_out(Duration::ZERO));
+        assert!(!peer.has_timed_out(PING_TIMEOUT));
+    }
+
+    #[test]
+    pub fn test_no_ping_is_sent_while_one_is_pending() {
+        let tracker = PeerTracker::new();
+        tracker.register(addr());
+        assert!(tracker.needs_ping(&addr()));
+
+        tracker.ping_sent(&addr(), 42);
+        assert!(!tracker.needs_ping(&addr()));
+
+        tracker.pong_received(&addr(), 42);
+        assert!(tracker.needs_ping(&addr()));
+    }
+
+    #[test]
+    pub fn test_messages_of_two_threads_do_not_interleave() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut reader, _) = listener.accept().unwrap();
+
+        let handles: Vec<_> = [1u8, 2u8]
+            .into_iter()
+            .map(|byte| {
+                let socket = writer.try_clone().unwrap();
+                thread::spawn(move || {
+                    for _ in 0..50 {
+                        write_peer_message(&socket, &[byte; 4096]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut message = [0u8; 4096];
+        for _ in 0..100 {
+            std::io::Read::read_exact(&mut reader, &mut message).unwrap();
+            assert!(message.iter().all(|byte| *byte == message[0]));
+        }
+    }
+
+    #[test]
+    pub fn test_removed_peer_is_not_connected() {
+        let tracker = PeerTracker::new();
+        tracker.register(addr());
+        assert!(tracker.is_connected(&addr()));
+
+        tracker.remove(&addr());
+
+        assert!(!tracker.is_connected(&addr()));
+        assert!(tracker.is_unresponsive(&addr()));
+    }
+}
@@ -7,6 +7,8 @@ use secp256k1::{Secp256k1, SecretKey};
 
 use crate::components::transaction::TransactionInput;
 use crate::components::utxo_struct::Utxo;
+use crate::configuration::chain_params::chain_params;
+use crate::script::taproot::is_p2tr;
 
 pub fn u8_to_hex_string(slice: &[u8]) -> String {
     let hex_digits: Vec<String> = slice
@@ -67,14 +69,14 @@ pub fn get_flag_value(data: &[u8]) -> bool {
 
 pub fn find_spent_utxo(input: &TransactionInput, wallet_utxos: &Vec<Utxo>) -> Option<Utxo> {
     let txid_hash = u8_to_hex_string(&input.previous_output[0..32]);
-    let _index = u32::from_be_bytes([
+    let index = u32::from_le_bytes([
         input.previous_output[32],
         input.previous_output[33],
         input.previous_output[34],
         input.previous_output[35],
     ]);
     for utxo in wallet_utxos {
-        if utxo.txid == txid_hash {
+        if utxo.txid == txid_hash && utxo.index == index {
             return Some(utxo.clone());
         }
     }
@@ -91,7 +93,7 @@ pub fn test_pubkey_to_address(address: &str) -> bool {
             .unwrap();
     let public_key: [u8; 33] = private_key.public_key(&secp).serialize();
     let h160: [u8; 20] = hash160::Hash::hash(&public_key).into_inner();
-    let version_prefix = [0x6f];
+    let version_prefix = [chain_params().pubkey_address_prefix];
     let vec_to_hash: &Vec<u8> = &[&version_prefix[..], &h160[..]].concat();
     let hash1 = sha256::Hash::hash(vec_to_hash);
     let hash2 = sha256::Hash::hash(&hash1[..]);
@@ -127,7 +129,7 @@ pub fn address_from_script(script: &[u8]) -> Option<String> {
         20 => {
             // P2PKH
             // Address: version + hash160(public key) + checksum
-            let mut address = vec![0x6f];
+            let mut address = vec![chain_params().pubkey_address_prefix];
             address.extend_from_slice(script);
             let checksum = sha256d::Hash::hash(&address).into_inner()[..4].to_vec();
             address.extend_from_slice(&checksum);
@@ -135,13 +137,18 @@ pub fn address_from_script(script: &[u8]) -> Option<String> {
         }
         22 if script.starts_with(&[0x00, 0x14]) => {
             // P2WPKH
-            // Address: version + hash160(public key) + checksum
-            None
+            // Address: bech32 of the version and hash160(public key)
+            Some(segwit_address(0, &script[2..]))
+        }
+        34 if is_p2tr(script) => {
+            // P2TR
+            // Address: bech32m of the version and the output key
+            Some(segwit_address(1, &script[2..]))
         }
         23 if script.starts_with(&[0xa9, 0x14]) && script.ends_with(&[0x87]) => {
             // P2SH
             // Address: version + hash160(script) + checksum
-            let mut address = vec![0xc4];
+            let mut address = vec![chain_params().script_address_prefix];
             address.extend_from_slice(&script[2..22]);
             let checksum = sha256d::Hash::hash(&address).into_inner()[..4].to_vec();
             address.extend_from_slice(&checksum);
@@ -150,7 +157,7 @@ pub fn address_from_script(script: &[u8]) -> Option<String> {
         25 if script.starts_with(&[0x76, 0xa9, 0x14]) && script.ends_with(&[0x88, 0xac]) => {
             // P2PKH
             // Address: version + hash160(public key) + checksum
-            let mut address = vec![0x6f]; // version 0x6f for testnet
+            let mut address = vec![chain_params().pubkey_address_prefix];
             address.extend_from_slice(&script[3..23]);
 
             // Calculate the double SHA256 hash of the result
@@ -166,74 +173,6 @@ pub fn address_from_script(script: &[u8]) -> Option<String> {
     }
 }
 
-pub fn get_value_after_keyword(input: &str, keyword: &str) -> Option<String> {
-    if input.trim().starts_with(keyword) {
-        return Some(input.trim().trim_start_matches(keyword).trim().to_string());
-    }
-    None
-}
-
-pub fn parse_inputs(inputs: Vec<String>) -> Vec<(Vec<u8>, Vec<u8>, u32)> {
-    inputs
-        .into_iter()
-        .map(|input| {
-            let split_input: Vec<&str> = input.split(',').collect();
-
-            let previous_output_end = split_input
-                .iter()
-                .position(|&s| s.contains("script"))
-                .unwrap();
-            let script_end = split_input
-                .iter()
-                .position(|&s| s.contains("sequence"))
-                .unwrap();
-
-            let previous_output: Vec<u8> = split_input[0..previous_output_end]
-                .iter()
-                .map(|s| s.replace("previous_output:", "").trim().parse().unwrap())
-                .collect();
-
-            let script: Vec<u8> = split_input[previous_output_end..script_end]
-                .iter()
-                .filter(|s| **s != "[]" && !s.replace("script:", "").trim().is_empty())
-                .map(|s| s.replace("script:", "").trim().parse().unwrap_or(0)) // default to 0 if parsing fails
-                .collect();
-
-            let sequence: u32 = split_input[script_end]
-                .replace("sequence:", "")
-                .trim()
-                .parse()
-                .unwrap();
-
-            (previous_output, script, sequence)
-        })
-        .collect()
-}
-
-pub fn parse_output_values(outputs: Vec<String>) -> Vec<(u64, Vec<u8>)> {
-    let mut result = Vec::new();
-
-    for output in outputs {
-        let split_output: Vec<&str> = output.split(", script_pubkey:").collect();
-
-        let value: u64 = split_output[0]
-            .trim_start_matches("value: ")
-            .parse()
-            .unwrap();
-
-        let script_pubkey: Vec<u8> = split_output[1]
-            .split(',')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.parse().unwrap())
-            .collect();
-
-        result.push((value, script_pubkey));
-    }
-
-    result
-}
-
 pub fn string_to_reversed_bytes(string: String) -> Vec<u8> {
     let bytes: Vec<u8> = string
         .as_bytes()
@@ -247,138 +186,13 @@ pub fn string_to_reversed_bytes(string: String) -> Vec<u8> {
     bytes.into_iter().rev().collect()
 }
 
-pub fn split_ignore_brackets(s: &str) -> Vec<String> {
-    let mut result = Vec::new();
-    let mut current = String::new();
-    let mut inside_brackets = false;
-
-    for c in s.chars() {
-        match c {
-            '[' => {
-                inside_brackets = true;
-                current.push(c);
-            }
-            ']' => {
-                inside_brackets = false;
-                current.push(c);
-            }
-            ',' => {
-                if inside_brackets {
-                    current.push(c);
-                } else {
-                    result.push(current.trim().to_string());
-                    current.clear();
-                }
-            }
-            _ => current.push(c),
-        }
-    }
-
-    if !current.trim().is_empty() {
-        result.push(current.trim().to_string());
-    }
-
-    result
-}
-
-pub fn str_to_bytes(s: &str) -> Vec<u8> {
-    s.trim_start_matches('[')
-        .trim_end_matches(']')
-        .split(',')
-        .map(|byte_str| byte_str.trim().parse().unwrap())
-        .collect()
-}
-pub fn split_ignore_curly_brackets(s: &str) -> Vec<String> {
-    let mut result = Vec::new();
-    let mut current_item = String::new();
-    let mut curly_brackets_count = 0;
-    let mut square_brackets_count = 0;
-
-    // Skip the first '{' character
-    let s = s.strip_prefix('{').unwrap_or(s);
-
-    for c in s.chars() {
-        match c {
-            '{' => curly_brackets_count += 1,
-            '}' => curly_brackets_count -= 1,
-            '[' => square_brackets_count += 1,
-            ']' => square_brackets_count -= 1,
-            ',' => {
-                if curly_brackets_count == 0 && square_brackets_count == 0 {
-                    result.push(current_item.clone());
-                    current_item.clear();
-                } else {
-                    current_item.push(c);
-                }
-            }
-            _ => current_item.push(c),
-        }
-    }
-    if !current_item.is_empty() {
-        result.push(current_item);
-    }
-
-    result
-}
-
-pub fn split_transaction_outputs(s: &str) -> Vec<String> {
-    let split_string: Vec<&str> = s.split("TransactionOutput").collect();
-    let mut result: Vec<String> = Vec::new();
-
-    for item in &split_string[1..] {
-        let trimmed_item = item.trim();
-        if !trimmed_item.is_empty() {
-            result.push(trimmed_item.to_string());
-        }
-    }
-
-    result
-}
-
-pub fn split_transaction_inputs(s: &str) -> Vec<String> {
-    let split_string: Vec<&str> = s.split("TransactionInput").collect();
-    let mut result: Vec<String> = Vec::new();
-
-    for item in &split_string[1..] {
-        let trimmed_item = item.trim();
-        if !trimmed_item.is_empty() {
-            result.push(trimmed_item.to_string());
-        }
-    }
-
-    result
-}
-
-#[allow(dead_code)]
-pub fn process_outputs(s: &str) -> Vec<u32> {
-    let value_str = s
-        .split("value: ")
-        .nth(1)
-        .unwrap()
-        .split(',')
-        .next()
-        .unwrap();
-    let value = value_str.trim().parse().unwrap();
-
-    let pubkey_str = s.split("script_pubkey: ").nth(1).unwrap().trim();
-    let pubkey_vec: Vec<u32> = pubkey_str
-        .split(',')
-        .map(|s| s.trim().parse().unwrap())
-        .collect();
-
-    // Construct the result vector
-    let mut result = vec![value];
-    result.extend(pubkey_vec);
-    result
-}
-
 #[allow(dead_code)]
 pub fn pubkey_to_address(pubkey: &[u8]) -> String {
     let sha = Sha256::hash(pubkey);
     let ripe: ripemd160::Hash = ripemd160::Hash::hash(&sha[..]);
 
     // Convertir el hash en una dirección de Bitcoin
-    let mut address_payload = vec![0];
+    let mut address_payload = vec![chain_params().pubkey_address_prefix];
     address_payload.extend_from_slice(&ripe[..]);
 
     // Aplicar Base58Check
@@ -416,6 +230,63 @@ pub fn hex_to_bytes_rev(hex: &str) -> Vec<u8> {
     bytes
 }
 
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// Constant of the checksum of bech32m, used by witness versions above 0 (BIP350)
+const BECH32M_CONSTANT: u32 = 0x2bc830a3;
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ *value as u32;
+        for (index, generator) in GENERATOR.iter().enumerate() {
+            if (top >> index) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Address of a witness program with the prefix of the network: bech32 for version 0 (BIP173)
+/// and bech32m for the rest
+pub fn segwit_address(version: u8, program: &[u8]) -> String {
+    let hrp = chain_params().bech32_hrp;
+
+    // The program is regrouped from 8 to 5 bits, padding the last group with zeros
+    let mut data = vec![version];
+    let mut accumulator: u32 = 0;
+    let mut bits = 0;
+    for byte in program {
+        accumulator = accumulator << 8 | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            data.push((accumulator >> bits) as u8 & 0x1f);
+        }
+    }
+    if bits > 0 {
+        data.push((accumulator << (5 - bits)) as u8 & 0x1f);
+    }
+
+    let mut values: Vec<u8> = hrp.bytes().map(|byte| byte >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|byte| byte & 0x1f));
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0; 6]);
+    let constant = if version == 0 { 1 } else { BECH32M_CONSTANT };
+    let polymod = bech32_polymod(&values) ^ constant;
+    data.extend((0..6).map(|index| (polymod >> (5 * (5 - index))) as u8 & 0x1f));
+
+    let mut address = format!("{}1", hrp);
+    address.extend(
+        data.iter()
+            .map(|value| BECH32_CHARSET[*value as usize] as char),
+    );
+    address
+}
+
 // script P2PKH
 pub fn address_to_script_pubkey(address: &str) -> Vec<u8> {
     let mut decoded = bs58::decode(address).into_vec().unwrap();
@@ -430,3 +301,24 @@ pub fn address_to_script_pubkey(address: &str) -> Vec<u8> {
     script.push(0xac); // OP_CHECKSIG
     script
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_addresses_of_witness_programs() {
+        let p2wpkh = hex_to_bytes("0014751e76e8199196d454941c45d1b3a323f1433bd6");
+        assert_eq!(
+            address_from_script(&p2wpkh),
+            Some("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string())
+        );
+
+        let p2tr =
+            hex_to_bytes("5120000000c4a5cad46221b2a187905e5266362b99d5e91c6ce24d165dab93e86433");
+        assert_eq!(
+            address_from_script(&p2tr),
+            Some("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c".to_string())
+        );
+    }
+}
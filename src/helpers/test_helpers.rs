@@ -0,0 +1,49 @@
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::components::{
+    block_header::BlockHeader,
+    transaction::{Transaction, TransactionInput, TransactionOutput},
+};
+
+/// Input that spends the previous output with the script, final and without witness
+pub fn input(previous_output: [u8; 36], script: Vec<u8>) -> TransactionInput {
+    TransactionInput {
+        previous_output,
+        script,
+        sequence: 0xffffffff,
+        witness: vec![],
+    }
+}
+
+/// Output that pays the value to the script
+pub fn output(value: u64, script_pubkey: Vec<u8>) -> TransactionOutput {
+    TransactionOutput {
+        value,
+        script_pubkey,
+    }
+}
+
+/// Transaction of version 1 with the inputs and the outputs, with its hash already computed
+pub fn transaction(inputs: Vec<TransactionInput>, outputs: Vec<TransactionOutput>) -> Transaction {
+    let mut tx = Transaction {
+        hash: sha256d::Hash::hash(&[]),
+        version: 1,
+        tx_in_count: inputs.len() as u32,
+        inputs,
+        tx_out_count: outputs.len() as u32,
+        outputs,
+        lock_time: 0,
+        txid: vec![],
+    };
+    tx.hash = tx.compute_txid();
+    tx
+}
+
+/// Changes the nonce of the header until its hash meets the target of its bits. The nonce grows
+/// in steps of 2^16, so headers that only differ in a small nonce stay different
+pub fn mine(mut header: BlockHeader) -> BlockHeader {
+    while !header.validate_pow() {
+        header.nonce = header.nonce.wrapping_add(0x10000);
+    }
+    header
+}
@@ -0,0 +1,286 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Div, Mul, Not, Shl, Shr, Sub},
+};
+
+/// #TDA U256
+/// Unsigned integer of 256 bits used for targets and chainwork.
+/// The limbs are stored from the least significant to the most significant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct U256([u64; 4]);
+
+#[allow(dead_code)]
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(value: u64) -> U256 {
+        U256([value, 0, 0, 0])
+    }
+
+    /// Reads a number stored in 32 little endian bytes, like the hashes of the protocol
+    pub fn from_le_bytes(bytes: &[u8]) -> U256 {
+        let mut limbs = [0u64; 4];
+        for (i, byte) in bytes.iter().take(32).enumerate() {
+            limbs[i / 8] |= (*byte as u64) << (8 * (i % 8));
+        }
+        U256(limbs)
+    }
+
+    /// Returns the number as 32 little endian bytes
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            bytes[i * 8..(i + 1) * 8].copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes the compact format of the bits field of the headers.
+    /// Negative or overflowed values are returned as zero, since they are never a valid target
+    pub fn from_compact(bits: u32) -> U256 {
+        let size = bits >> 24;
+        let mut word = bits & 0x007fffff;
+
+        let negative = word != 0 && (bits & 0x00800000) != 0;
+        let overflow =
+            word != 0 && (size > 34 || (word > 0xff && size > 33) || (word > 0xffff && size > 32));
+        if negative || overflow {
+            return U256::ZERO;
+        }
+
+        if size <= 3 {
+            word >>= 8 * (3 - size);
+            U256::from_u64(word as u64)
+        } else {
+            U256::from_u64(word as u64) << (8 * (size - 3))
+        }
+    }
+
+    /// Encodes the number in the compact format of the bits field of the headers
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (self >> (8 * (size - 3))).low_u64() as u32
+        };
+
+        if compact & 0x00800000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+
+        compact | (size << 24)
+    }
+
+    /// Amount of significant bits of the number
+    pub fn bits(&self) -> u32 {
+        for i in (0..4).rev() {
+            if self.0[i] != 0 {
+                return 64 * i as u32 + (64 - self.0[i].leading_zeros());
+            }
+        }
+        0
+    }
+
+    pub fn low_u64(&self) -> u64 {
+        self.0[0]
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == U256::ZERO
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        (self.0[(index / 64) as usize] >> (index % 64)) & 1 == 1
+    }
+
+    /// Returns the quotient and the remainder of the division
+    pub fn div_rem(self, divisor: U256) -> (U256, U256) {
+        if divisor.is_zero() {
+            panic!("division by zero");
+        }
+
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+
+        for i in (0..self.bits()).rev() {
+            remainder = remainder << 1;
+            if self.bit(i) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.0[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        }
+
+        (quotient, remainder)
+    }
+}
+
+/// Returns the expected amount of hashes needed to find a block with the given bits,
+/// that is 2^256 / (target + 1)
+pub fn work_from_compact(bits: u32) -> U256 {
+    let target = U256::from_compact(bits);
+    if target.is_zero() {
+        return U256::ZERO;
+    }
+
+    // 2^256 does not fit, but 2^256 / (target + 1) == ~target / (target + 1) + 1
+    (!target / (target + U256::ONE)) + U256::ONE
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, carry_1) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, carry_2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = carry_1 || carry_2;
+        }
+        U256(result)
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (difference, borrow_1) = self.0[i].overflowing_sub(other.0[i]);
+            let (difference, borrow_2) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = borrow_1 || borrow_2;
+        }
+        U256(result)
+    }
+}
+
+impl Mul<u64> for U256 {
+    type Output = U256;
+
+    fn mul(self, other: u64) -> U256 {
+        let mut result = [0u64; 4];
+        let mut carry: u128 = 0;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let product = self.0[i] as u128 * other as u128 + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        U256(result)
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    fn div(self, other: U256) -> U256 {
+        self.div_rem(other).0
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    #[allow(clippy::needless_range_loop)]
+    fn shl(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for i in limbs..4 {
+            result[i] = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                result[i] |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    #[allow(clippy::needless_range_loop)]
+    fn shr(self, shift: u32) -> U256 {
+        let mut result = [0u64; 4];
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        for i in 0..4usize.saturating_sub(limbs) {
+            result[i] = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                result[i] |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_compact_round_trip() {
+        for bits in [0x1d00ffff, 0x1b0404cb, 0x207fffff, 0x1e0377ae, 0x03123456] {
+            assert_eq!(U256::from_compact(bits).to_compact(), bits);
+        }
+    }
+
+    #[test]
+    pub fn test_from_compact_of_the_minimum_difficulty() {
+        let target = U256::from_compact(0x1d00ffff);
+
+        assert_eq!(target, U256::from_u64(0xffff) << 208);
+        assert_eq!(target.bits(), 224);
+    }
+
+    #[test]
+    pub fn test_negative_compact_is_zero() {
+        assert!(U256::from_compact(0x04923456).is_zero());
+    }
+
+    #[test]
+    pub fn test_work_of_the_minimum_difficulty() {
+        assert_eq!(work_from_compact(0x1d00ffff), U256::from_u64(0x0100010001));
+    }
+
+    #[test]
+    pub fn test_arithmetic_carries_between_limbs() {
+        let number = U256::from_u64(u64::MAX) + U256::ONE;
+
+        assert_eq!(number, U256::ONE << 64);
+        assert_eq!(number - U256::ONE, U256::from_u64(u64::MAX));
+        assert_eq!(number * 3, U256::from_u64(3) << 64);
+        assert_eq!((number * 3) / U256::from_u64(3), number);
+        assert_eq!(number >> 64, U256::ONE);
+    }
+}
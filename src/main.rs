@@ -1,24 +1,25 @@
-use crate::configuration::config_helper::get_configuration;
+use crate::configuration::config_helper::{get_configuration, get_data_dir};
+use crate::storage::record_file::recover_data_dir;
 
 use connection::connection_modes::{client_mode, server_mode};
 use std::io::{Error, ErrorKind};
 
 mod configuration {
+    pub mod chain_params;
     pub mod config_helper;
     pub mod configuration_loader;
 }
 
 mod testnet_protocol;
 
-mod logger {
-    pub mod log_printer;
-    pub mod logger_impl;
-}
-
 mod components {
     pub mod block;
     pub mod block_header;
+    pub mod chainstate;
+    pub mod difficulty;
+    pub mod header_chain;
     pub mod transaction;
+    pub mod transaction_validation;
     pub mod user;
     pub mod utxo_set;
     pub mod utxo_struct;
@@ -27,7 +28,29 @@ mod components {
 
 mod helpers {
     pub mod auxiliar_functions;
-    pub mod persistance;
+    #[cfg(test)]
+    pub mod test_helpers;
+    pub mod uint256;
+}
+
+mod script {
+    pub mod interpreter;
+    pub mod opcodes;
+    pub mod script_parser;
+    pub mod sighash;
+    pub mod signature_checker;
+    pub mod sigops;
+    pub mod taproot;
+}
+
+mod storage {
+    pub mod address_index;
+    pub mod block_index;
+    pub mod block_store;
+    pub mod chainstate_store;
+    pub mod header_store;
+    pub mod record_file;
+    pub mod tx_index;
 }
 
 mod merkle_tree {
@@ -35,8 +58,11 @@ mod merkle_tree {
 }
 
 mod connection {
+    pub mod address_book;
     pub mod connection_modes;
     pub mod connection_protocol;
+    pub mod network_time;
+    pub mod peer_liveness;
 }
 
 mod interface {
@@ -51,6 +77,9 @@ fn main() -> Result<(), Error> {
         .get_value_from_key("mode".to_owned())
         .map_err(|_| Error::new(ErrorKind::Other, "Failed to get the mode"))?;
 
+    // Nothing is reading or writing the files yet, so what a crash left half written is discarded
+    recover_data_dir(&get_data_dir())?;
+
     match mode.as_str() {
         "client" => client_mode(),
         "server" => server_mode()?,
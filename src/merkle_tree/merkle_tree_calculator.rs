@@ -5,31 +5,43 @@ use bitcoin_hashes::{sha256d, Hash};
 #[derive(Debug)]
 pub enum MerkleTreeError {
     TransactionNotFound,
+    EmptyHashList,
 }
 
 ///Function to calculate merkle tree of a hash transactions list
 pub fn calculate_merkle_tree(
-    mut hash_list: Vec<sha256d::Hash>,
+    hash_list: Vec<sha256d::Hash>,
 ) -> Result<sha256d::Hash, MerkleTreeError> {
-    if hash_list.len() == 1 {
-        return Ok(hash_list[0]);
+    calculate_merkle_tree_with_mutation(hash_list).map(|(root, _)| root)
+}
+
+/// Calculates the merkle root of the hash list and whether the list is mutated: a level has
+/// two equal hashes in the same pair. The last hash of an odd level is paired with itself, so a
+/// list with its last hashes repeated has the same root as the honest one (CVE-2012-2459)
+pub fn calculate_merkle_tree_with_mutation(
+    mut hash_list: Vec<sha256d::Hash>,
+) -> Result<(sha256d::Hash, bool), MerkleTreeError> {
+    if hash_list.is_empty() {
+        return Err(MerkleTreeError::EmptyHashList);
     }
 
-    let mut new_hash_list = Vec::new();
-    while !hash_list.is_empty() {
-        let left = hash_list.remove(0);
-        let right = if hash_list.is_empty() {
-            left
-        } else {
-            hash_list.remove(0)
-        };
-
-        let concatenated = [left.into_inner().as_ref(), right.into_inner().as_ref()].concat();
-        let new_hash = sha256d::Hash::hash(&sha256d::Hash::hash(&concatenated).into_inner());
-        new_hash_list.push(new_hash);
+    let mut mutated = false;
+    while hash_list.len() > 1 {
+        mutated |= hash_list.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+
+        hash_list = hash_list
+            .chunks(2)
+            .map(|pair| merkle_node(pair[0], *pair.last().unwrap()))
+            .collect();
     }
 
-    calculate_merkle_tree(new_hash_list)
+    Ok((hash_list[0], mutated))
+}
+
+/// Hash of a node of the tree from the hashes of its children
+pub fn merkle_node(left: sha256d::Hash, right: sha256d::Hash) -> sha256d::Hash {
+    let concatenated = [left.into_inner().as_ref(), right.into_inner().as_ref()].concat();
+    sha256d::Hash::hash(&concatenated)
 }
 
 #[cfg(test)]
@@ -60,7 +72,7 @@ mod tests {
 
         let merkle_tree_root = result.unwrap();
         let expected_hash = sha256d::Hash::from_str(
-            "49c87b8126c7fe211f65f6df1f65651d2825748c709edab2bfd4913ed5e0e1ee",
+            "8651ccc262f9c3d4c5bac35654790f40ad1471af2ebaa489af254b361565cada",
         )
         .unwrap();
 
@@ -77,10 +89,37 @@ mod tests {
 
         let merkle_tree_root = result.unwrap();
         let expected_hash = sha256d::Hash::from_str(
-            "9f51bfe21feeb680aaf19161da852577494589deea3b85388a91f034e24df91c",
+            "5861550e6499c10d0a2cbf188f1feb2b07c1a66e3582b1065ed1a537416a7202",
         )
         .unwrap();
 
         assert_eq!(merkle_tree_root, expected_hash);
     }
+
+    #[test]
+    pub fn test_merkle_root_of_two_hashes_is_the_hash_of_both() {
+        let left = sha256d::Hash::hash(b"left");
+        let right = sha256d::Hash::hash(b"right");
+        let concatenated = [left.into_inner(), right.into_inner()].concat();
+
+        let (root, mutated) = calculate_merkle_tree_with_mutation(vec![left, right]).unwrap();
+
+        assert_eq!(root, sha256d::Hash::hash(&concatenated));
+        assert!(!mutated);
+    }
+
+    #[test]
+    pub fn test_repeated_last_hashes_give_the_same_root_but_are_mutated() {
+        let hashes: Vec<sha256d::Hash> = (0..3u8).map(|i| sha256d::Hash::hash(&[i])).collect();
+        let mut repeated = hashes.clone();
+        repeated.push(hashes[2]);
+
+        let (root, mutated) = calculate_merkle_tree_with_mutation(hashes).unwrap();
+        let (repeated_root, repeated_mutated) =
+            calculate_merkle_tree_with_mutation(repeated).unwrap();
+
+        assert_eq!(root, repeated_root);
+        assert!(!mutated);
+        assert!(repeated_mutated);
+    }
 }
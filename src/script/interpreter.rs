@@ -0,0 +1,1534 @@
+use bitcoin_hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+
+use super::{
+    opcodes::*,
+    script_parser::{
+        find_and_delete, is_minimal_push, is_p2sh, is_push_only, parse_script, push_data,
+        read_instruction,
+    },
+    sighash::ExecutionData,
+    signature_checker::{
+        check_pubkey_encoding, check_signature_encoding, SignatureChecker,
+        TransactionSignatureChecker,
+    },
+    sigops::witness_program,
+    taproot::{
+        is_valid_control_block_size, tap_leaf_hash, verify_taproot_commitment, ANNEX_TAG,
+        TAPROOT_LEAF_MASK, TAPROOT_LEAF_TAPSCRIPT, VALIDATION_WEIGHT_OFFSET,
+        VALIDATION_WEIGHT_PER_SIGOP_PASSED,
+    },
+};
+use crate::{
+    components::{
+        transaction::{Transaction, TransactionOutput},
+        transaction_validation::SEQUENCE_LOCKTIME_DISABLE_FLAG,
+    },
+    configuration::chain_params::chain_params,
+    helpers::auxiliar_functions::serialize_var_int,
+};
+
+/// Evaluate the redeem script of pay to script hash outputs (BIP16)
+pub const SCRIPT_VERIFY_P2SH: u32 = 1 << 0;
+/// Signatures and public keys must be strictly encoded, with a defined hash type
+pub const SCRIPT_VERIFY_STRICTENC: u32 = 1 << 1;
+/// Signatures must be strict DER (BIP66)
+pub const SCRIPT_VERIFY_DERSIG: u32 = 1 << 2;
+/// The S of the signatures must be in the lower half of the curve order
+pub const SCRIPT_VERIFY_LOW_S: u32 = 1 << 3;
+/// The extra element popped by CHECKMULTISIG must be empty (BIP147)
+pub const SCRIPT_VERIFY_NULLDUMMY: u32 = 1 << 4;
+/// The script sig can only push data
+pub const SCRIPT_VERIFY_SIGPUSHONLY: u32 = 1 << 5;
+/// Data and numbers must be pushed with the smallest encoding
+pub const SCRIPT_VERIFY_MINIMALDATA: u32 = 1 << 6;
+/// The NOP opcodes reserved for soft forks make the script fail
+pub const SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS: u32 = 1 << 7;
+/// Only one element can be left in the stack after the evaluation
+pub const SCRIPT_VERIFY_CLEANSTACK: u32 = 1 << 8;
+/// OP_CHECKLOCKTIMEVERIFY checks the lock time of the transaction (BIP65)
+pub const SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY: u32 = 1 << 9;
+/// OP_CHECKSEQUENCEVERIFY checks the relative lock of the input (BIP112)
+pub const SCRIPT_VERIFY_CHECKSEQUENCEVERIFY: u32 = 1 << 10;
+/// Evaluate the witness of segwit outputs (BIP141)
+pub const SCRIPT_VERIFY_WITNESS: u32 = 1 << 11;
+/// Witness programs of versions without rules yet make the script fail
+pub const SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: u32 = 1 << 12;
+/// Failed signature checks must use empty signatures
+pub const SCRIPT_VERIFY_NULLFAIL: u32 = 1 << 14;
+/// Verify the key path and script path spends of taproot outputs (BIP341 and BIP342)
+pub const SCRIPT_VERIFY_TAPROOT: u32 = 1 << 17;
+/// Leaf versions without rules yet make the script fail
+pub const SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: u32 = 1 << 18;
+/// The OP_SUCCESS opcodes of tapscript make the script fail
+pub const SCRIPT_VERIFY_DISCOURAGE_OP_SUCCESS: u32 = 1 << 19;
+/// Public keys of tapscript of unknown types make the script fail
+pub const SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_PUBKEYTYPE: u32 = 1 << 20;
+
+/// Flags every block must follow
+#[allow(dead_code)]
+pub const MANDATORY_SCRIPT_VERIFY_FLAGS: u32 = SCRIPT_VERIFY_P2SH
+    | SCRIPT_VERIFY_DERSIG
+    | SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY
+    | SCRIPT_VERIFY_CHECKSEQUENCEVERIFY
+    | SCRIPT_VERIFY_WITNESS
+    | SCRIPT_VERIFY_TAPROOT;
+
+/// Flags the transactions relayed by the node must follow
+#[allow(dead_code)]
+pub const STANDARD_SCRIPT_VERIFY_FLAGS: u32 = MANDATORY_SCRIPT_VERIFY_FLAGS
+    | SCRIPT_VERIFY_STRICTENC
+    | SCRIPT_VERIFY_LOW_S
+    | SCRIPT_VERIFY_NULLDUMMY
+    | SCRIPT_VERIFY_MINIMALDATA
+    | SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS
+    | SCRIPT_VERIFY_CLEANSTACK
+    | SCRIPT_VERIFY_NULLFAIL
+    | SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM
+    | SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_TAPROOT_VERSION
+    | SCRIPT_VERIFY_DISCOURAGE_OP_SUCCESS
+    | SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_PUBKEYTYPE;
+
+/// Flags the scripts of the block at the height are verified with. Pay to script hash, witness and
+/// taproot apply to every block but the exceptions of the network, the rest since their soft fork
+pub fn block_script_flags(block_hash: &[u8], height: u32) -> u32 {
+    let params = chain_params();
+    let mut flags = params
+        .script_flag_exceptions
+        .iter()
+        .find(|(hash, _)| hash == block_hash)
+        .map_or(
+            SCRIPT_VERIFY_P2SH | SCRIPT_VERIFY_WITNESS | SCRIPT_VERIFY_TAPROOT,
+            |(_, flags)| *flags,
+        );
+
+    if height >= params.bip66_height {
+        flags |= SCRIPT_VERIFY_DERSIG;
+    }
+    if height >= params.bip65_height {
+        flags |= SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY;
+    }
+    if height >= params.csv_height {
+        flags |= SCRIPT_VERIFY_CHECKSEQUENCEVERIFY;
+    }
+    if height >= params.segwit_height {
+        flags |= SCRIPT_VERIFY_NULLDUMMY;
+    }
+
+    flags
+}
+
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// Most bytes of the numbers used by the arithmetic opcodes
+const MAX_NUM_SIZE: usize = 4;
+/// Lock times and sequences are numbers of up to 5 bytes, to fit every unsigned 32 bits value
+const LOCKTIME_NUM_SIZE: usize = 5;
+
+/// #ENUM ScriptError
+/// Reasons for a script to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    EvalFalse,
+    OpReturn,
+    ScriptSize,
+    PushSize,
+    OpCount,
+    StackSize,
+    SigCount,
+    PubkeyCount,
+    Verify,
+    EqualVerify,
+    CheckSigVerify,
+    CheckMultisigVerify,
+    NumEqualVerify,
+    BadOpcode,
+    DisabledOpcode,
+    InvalidStackOperation,
+    InvalidAltstackOperation,
+    UnbalancedConditional,
+    InvalidNumber,
+    SigHashType,
+    SigDer,
+    MinimalData,
+    SigPushOnly,
+    SigHighS,
+    SigNullDummy,
+    PubkeyType,
+    CleanStack,
+    NullFail,
+    DiscourageUpgradableNops,
+    SpentOutputsMismatch,
+    MinimalIf,
+    WitnessProgramWrongLength,
+    WitnessProgramWitnessEmpty,
+    WitnessProgramMismatch,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    DiscourageUpgradableWitnessProgram,
+    DiscourageUpgradableTaprootVersion,
+    DiscourageOpSuccess,
+    DiscourageUpgradablePubkeyType,
+    SchnorrSigSize,
+    SchnorrSigHashType,
+    SchnorrSig,
+    TaprootWrongControlSize,
+    TapscriptValidationWeight,
+    TapscriptCheckMultisig,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+}
+
+/// #ENUM SigVersion
+/// Rules a script is evaluated with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigVersion {
+    /// Script sigs, script pubkeys and redeem scripts
+    Base,
+    /// Witness scripts of version 0 witness programs (BIP143)
+    WitnessV0,
+    /// Leaf scripts of taproot outputs (BIP342)
+    Tapscript,
+}
+
+/// Decodes a number of the stack: little endian with the sign in the highest bit of the last byte
+pub fn decode_script_num(
+    bytes: &[u8],
+    require_minimal: bool,
+    max_size: usize,
+) -> Result<i64, ScriptError> {
+    if bytes.len() > max_size {
+        return Err(ScriptError::InvalidNumber);
+    }
+
+    let last = match bytes.last() {
+        Some(last) => *last,
+        None => return Ok(0),
+    };
+
+    // The last byte can only be zero, or just the sign, if the previous one needs its highest bit
+    if require_minimal
+        && last & 0x7f == 0
+        && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0)
+    {
+        return Err(ScriptError::MinimalData);
+    }
+
+    let mut value: i64 = 0;
+    for (index, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * index);
+    }
+
+    if last & 0x80 != 0 {
+        return Ok(-(value & !(0x80_i64 << (8 * (bytes.len() - 1)))));
+    }
+
+    Ok(value)
+}
+
+/// Encodes a number with the smallest amount of bytes
+pub fn encode_script_num(value: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut absolute = value.unsigned_abs();
+
+    while absolute > 0 {
+        bytes.push((absolute & 0xff) as u8);
+        absolute >>= 8;
+    }
+
+    if let Some(last) = bytes.last_mut() {
+        if *last & 0x80 != 0 {
+            bytes.push(if value < 0 { 0x80 } else { 0x00 });
+        } else if value < 0 {
+            *last |= 0x80;
+        }
+    }
+
+    bytes
+}
+
+/// Returns the boolean value of an element of the stack. Zero and negative zero are false
+pub fn cast_to_bool(bytes: &[u8]) -> bool {
+    for (index, byte) in bytes.iter().enumerate() {
+        if *byte != 0 {
+            return !(index == bytes.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+fn bool_to_element(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+/// Returns the element at the depth, counting from the top of the stack
+fn top(stack: &[Vec<u8>], depth: usize) -> Result<&Vec<u8>, ScriptError> {
+    if depth >= stack.len() {
+        return Err(ScriptError::InvalidStackOperation);
+    }
+    Ok(&stack[stack.len() - 1 - depth])
+}
+
+fn pop_num(stack: &mut Vec<Vec<u8>>, flags: u32) -> Result<i64, ScriptError> {
+    let bytes = pop(stack)?;
+    decode_script_num(&bytes, flags & SCRIPT_VERIFY_MINIMALDATA != 0, MAX_NUM_SIZE)
+}
+
+/// Evaluates the script over the stack
+pub fn eval_script(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+) -> Result<(), ScriptError> {
+    let mut execution = ExecutionData::key_path(None);
+    execute_script(
+        stack,
+        script,
+        flags,
+        checker,
+        SigVersion::Base,
+        &mut execution,
+    )
+}
+
+/// Evaluates the script over the stack with the rules of the signature version. Tapscripts have
+/// no size or opcode limits, use Schnorr signatures and record their code separator in the execution data
+fn execute_script(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+    sig_version: SigVersion,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    let tapscript = sig_version == SigVersion::Tapscript;
+    if !tapscript && script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+
+    let instructions = parse_script(script)?;
+    let require_minimal = flags & SCRIPT_VERIFY_MINIMALDATA != 0;
+
+    let mut alt_stack: Vec<Vec<u8>> = vec![];
+    let mut exec_stack: Vec<bool> = vec![];
+    let mut op_count = 0;
+    let mut code_separator = 0;
+
+    for (position, instruction) in instructions.iter().enumerate() {
+        let opcode = instruction.opcode;
+        let executing = exec_stack.iter().all(|branch| *branch);
+
+        if instruction.data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(ScriptError::PushSize);
+        }
+
+        if !tapscript && opcode > OP_16 {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+
+        if is_disabled(opcode) {
+            return Err(ScriptError::DisabledOpcode);
+        }
+
+        if executing && opcode <= OP_PUSHDATA4 {
+            if require_minimal && !is_minimal_push(opcode, instruction.data) {
+                return Err(ScriptError::MinimalData);
+            }
+            stack.push(instruction.data.to_vec());
+        } else if executing || (OP_IF..=OP_ENDIF).contains(&opcode) {
+            match opcode {
+                OP_1NEGATE | OP_1..=OP_16 => {
+                    stack.push(encode_script_num(opcode as i64 - (OP_1 - 1) as i64));
+                }
+
+                OP_NOP => {}
+                OP_CHECKLOCKTIMEVERIFY if flags & SCRIPT_VERIFY_CHECKLOCKTIMEVERIFY != 0 => {
+                    let lock_time =
+                        decode_script_num(top(stack, 0)?, require_minimal, LOCKTIME_NUM_SIZE)?;
+                    if lock_time < 0 {
+                        return Err(ScriptError::NegativeLocktime);
+                    }
+                    if !checker.check_lock_time(lock_time) {
+                        return Err(ScriptError::UnsatisfiedLocktime);
+                    }
+                }
+                OP_CHECKSEQUENCEVERIFY if flags & SCRIPT_VERIFY_CHECKSEQUENCEVERIFY != 0 => {
+                    let sequence =
+                        decode_script_num(top(stack, 0)?, require_minimal, LOCKTIME_NUM_SIZE)?;
+                    if sequence < 0 {
+                        return Err(ScriptError::NegativeLocktime);
+                    }
+                    // With the disable flag the opcode is a NOP, left for future soft forks
+                    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0
+                        && !checker.check_sequence(sequence)
+                    {
+                        return Err(ScriptError::UnsatisfiedLocktime);
+                    }
+                }
+                OP_NOP1 | OP_NOP2 | OP_NOP3 | OP_NOP4..=OP_NOP10 => {
+                    if flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_NOPS != 0 {
+                        return Err(ScriptError::DiscourageUpgradableNops);
+                    }
+                }
+
+                OP_IF | OP_NOTIF => {
+                    let mut value = false;
+                    if executing {
+                        let condition = pop(stack)?;
+                        // Tapscript only accepts an empty element or one as condition
+                        if tapscript
+                            && (condition.len() > 1
+                                || condition.first().is_some_and(|byte| *byte != 1))
+                        {
+                            return Err(ScriptError::MinimalIf);
+                        }
+                        value = cast_to_bool(&condition);
+                        if opcode == OP_NOTIF {
+                            value = !value;
+                        }
+                    }
+                    exec_stack.push(value);
+                }
+                OP_ELSE => {
+                    let branch = exec_stack
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *branch = !*branch;
+                }
+                OP_ENDIF => {
+                    exec_stack.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                OP_VERIFY => {
+                    if !cast_to_bool(top(stack, 0)?) {
+                        return Err(ScriptError::Verify);
+                    }
+                    stack.pop();
+                }
+                OP_RETURN => return Err(ScriptError::OpReturn),
+
+                OP_TOALTSTACK => alt_stack.push(pop(stack)?),
+                OP_FROMALTSTACK => {
+                    let element = alt_stack
+                        .pop()
+                        .ok_or(ScriptError::InvalidAltstackOperation)?;
+                    stack.push(element);
+                }
+                OP_2DROP => {
+                    top(stack, 1)?;
+                    stack.truncate(stack.len() - 2);
+                }
+                OP_2DUP => {
+                    let first = top(stack, 1)?.clone();
+                    let second = top(stack, 0)?.clone();
+                    stack.push(first);
+                    stack.push(second);
+                }
+                OP_3DUP => {
+                    let first = top(stack, 2)?.clone();
+                    let second = top(stack, 1)?.clone();
+                    let third = top(stack, 0)?.clone();
+                    stack.push(first);
+                    stack.push(second);
+                    stack.push(third);
+                }
+                OP_2OVER => {
+                    let first = top(stack, 3)?.clone();
+                    let second = top(stack, 2)?.clone();
+                    stack.push(first);
+                    stack.push(second);
+                }
+                OP_2ROT => {
+                    top(stack, 5)?;
+                    let start = stack.len() - 6;
+                    let moved: Vec<Vec<u8>> = stack.drain(start..start + 2).collect();
+                    stack.extend(moved);
+                }
+                OP_2SWAP => {
+                    top(stack, 3)?;
+                    let len = stack.len();
+                    stack.swap(len - 4, len - 2);
+                    stack.swap(len - 3, len - 1);
+                }
+                OP_IFDUP => {
+                    let element = top(stack, 0)?.clone();
+                    if cast_to_bool(&element) {
+                        stack.push(element);
+                    }
+                }
+                OP_DEPTH => stack.push(encode_script_num(stack.len() as i64)),
+                OP_DROP => {
+                    pop(stack)?;
+                }
+                OP_DUP => {
+                    let element = top(stack, 0)?.clone();
+                    stack.push(element);
+                }
+                OP_NIP => {
+                    top(stack, 1)?;
+                    stack.remove(stack.len() - 2);
+                }
+                OP_OVER => {
+                    let element = top(stack, 1)?.clone();
+                    stack.push(element);
+                }
+                OP_PICK | OP_ROLL => {
+                    let depth = pop_num(stack, flags)?;
+                    if depth < 0 {
+                        return Err(ScriptError::InvalidStackOperation);
+                    }
+                    let element = top(stack, depth as usize)?.clone();
+                    if opcode == OP_ROLL {
+                        stack.remove(stack.len() - 1 - depth as usize);
+                    }
+                    stack.push(element);
+                }
+                OP_ROT => {
+                    top(stack, 2)?;
+                    let element = stack.remove(stack.len() - 3);
+                    stack.push(element);
+                }
+                OP_SWAP => {
+                    top(stack, 1)?;
+                    let len = stack.len();
+                    stack.swap(len - 2, len - 1);
+                }
+                OP_TUCK => {
+                    top(stack, 1)?;
+                    let element = top(stack, 0)?.clone();
+                    stack.insert(stack.len() - 2, element);
+                }
+
+                OP_SIZE => {
+                    let size = top(stack, 0)?.len();
+                    stack.push(encode_script_num(size as i64));
+                }
+
+                OP_EQUAL | OP_EQUALVERIFY => {
+                    let second = pop(stack)?;
+                    let first = pop(stack)?;
+                    let equal = first == second;
+                    if opcode == OP_EQUALVERIFY {
+                        if !equal {
+                            return Err(ScriptError::EqualVerify);
+                        }
+                    } else {
+                        stack.push(bool_to_element(equal));
+                    }
+                }
+
+                OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                    let value = pop_num(stack, flags)?;
+                    let result = match opcode {
+                        OP_1ADD => value + 1,
+                        OP_1SUB => value - 1,
+                        OP_NEGATE => -value,
+                        OP_ABS => value.abs(),
+                        OP_NOT => (value == 0) as i64,
+                        _ => (value != 0) as i64,
+                    };
+                    stack.push(encode_script_num(result));
+                }
+                OP_ADD
+                | OP_SUB
+                | OP_BOOLAND
+                | OP_BOOLOR
+                | OP_NUMEQUAL
+                | OP_NUMEQUALVERIFY
+                | OP_NUMNOTEQUAL
+                | OP_LESSTHAN
+                | OP_GREATERTHAN
+                | OP_LESSTHANOREQUAL
+                | OP_GREATERTHANOREQUAL
+                | OP_MIN
+                | OP_MAX => {
+                    let second = pop_num(stack, flags)?;
+                    let first = pop_num(stack, flags)?;
+                    let result = match opcode {
+                        OP_ADD => first + second,
+                        OP_SUB => first - second,
+                        OP_BOOLAND => (first != 0 && second != 0) as i64,
+                        OP_BOOLOR => (first != 0 || second != 0) as i64,
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => (first == second) as i64,
+                        OP_NUMNOTEQUAL => (first != second) as i64,
+                        OP_LESSTHAN => (first < second) as i64,
+                        OP_GREATERTHAN => (first > second) as i64,
+                        OP_LESSTHANOREQUAL => (first <= second) as i64,
+                        OP_GREATERTHANOREQUAL => (first >= second) as i64,
+                        OP_MIN => first.min(second),
+                        _ => first.max(second),
+                    };
+
+                    if opcode == OP_NUMEQUALVERIFY {
+                        if result == 0 {
+                            return Err(ScriptError::NumEqualVerify);
+                        }
+                    } else {
+                        stack.push(encode_script_num(result));
+                    }
+                }
+                OP_WITHIN => {
+                    let max = pop_num(stack, flags)?;
+                    let min = pop_num(stack, flags)?;
+                    let value = pop_num(stack, flags)?;
+                    stack.push(bool_to_element(min <= value && value < max));
+                }
+
+                OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => {
+                    let element = pop(stack)?;
+                    let hash = match opcode {
+                        OP_RIPEMD160 => ripemd160::Hash::hash(&element).to_vec(),
+                        OP_SHA1 => sha1::Hash::hash(&element).to_vec(),
+                        OP_SHA256 => sha256::Hash::hash(&element).to_vec(),
+                        OP_HASH160 => hash160::Hash::hash(&element).to_vec(),
+                        _ => sha256d::Hash::hash(&element).to_vec(),
+                    };
+                    stack.push(hash);
+                }
+                OP_CODESEPARATOR => {
+                    code_separator = instruction.end;
+                    execution.code_separator = position as u32;
+                }
+
+                OP_CHECKSIG | OP_CHECKSIGVERIFY if tapscript => {
+                    let pubkey = pop(stack)?;
+                    let signature = pop(stack)?;
+                    let success =
+                        check_tapscript_sig(&signature, &pubkey, flags, checker, execution)?;
+
+                    if opcode == OP_CHECKSIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckSigVerify);
+                        }
+                    } else {
+                        stack.push(bool_to_element(success));
+                    }
+                }
+                OP_CHECKSIGADD if tapscript => {
+                    let pubkey = pop(stack)?;
+                    let value = pop_num(stack, flags)?;
+                    let signature = pop(stack)?;
+                    let success =
+                        check_tapscript_sig(&signature, &pubkey, flags, checker, execution)?;
+                    stack.push(encode_script_num(value + success as i64));
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY if tapscript => {
+                    return Err(ScriptError::TapscriptCheckMultisig);
+                }
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                    let pubkey = pop(stack)?;
+                    let signature = pop(stack)?;
+
+                    // The signature cannot sign itself, so it is removed from the signed script.
+                    // Witness signatures do not sign the script sig, so they are kept
+                    let script_code = if sig_version == SigVersion::Base {
+                        find_and_delete(&script[code_separator..], &push_data(&signature))
+                    } else {
+                        script[code_separator..].to_vec()
+                    };
+
+                    check_signature_encoding(&signature, flags)?;
+                    check_pubkey_encoding(&pubkey, flags)?;
+                    let success = checker.check_sig(&signature, &pubkey, &script_code, sig_version);
+
+                    if !success && flags & SCRIPT_VERIFY_NULLFAIL != 0 && !signature.is_empty() {
+                        return Err(ScriptError::NullFail);
+                    }
+
+                    if opcode == OP_CHECKSIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckSigVerify);
+                        }
+                    } else {
+                        stack.push(bool_to_element(success));
+                    }
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    let key_count = pop_num(stack, flags)?;
+                    if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&key_count) {
+                        return Err(ScriptError::PubkeyCount);
+                    }
+                    op_count += key_count as usize;
+                    if op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::OpCount);
+                    }
+
+                    let mut pubkeys = vec![];
+                    for _ in 0..key_count {
+                        pubkeys.push(pop(stack)?);
+                    }
+                    pubkeys.reverse();
+
+                    let sig_count = pop_num(stack, flags)?;
+                    if sig_count < 0 || sig_count > key_count {
+                        return Err(ScriptError::SigCount);
+                    }
+
+                    let mut signatures = vec![];
+                    for _ in 0..sig_count {
+                        signatures.push(pop(stack)?);
+                    }
+                    signatures.reverse();
+
+                    // An extra element is popped because of an off by one of the original implementation
+                    let dummy = pop(stack)?;
+                    if flags & SCRIPT_VERIFY_NULLDUMMY != 0 && !dummy.is_empty() {
+                        return Err(ScriptError::SigNullDummy);
+                    }
+
+                    let mut script_code = script[code_separator..].to_vec();
+                    if sig_version == SigVersion::Base {
+                        for signature in &signatures {
+                            script_code = find_and_delete(&script_code, &push_data(signature));
+                        }
+                    }
+
+                    // Signatures must be in the same order as their public keys
+                    let mut success = true;
+                    let mut signature_index = 0;
+                    let mut key_index = 0;
+                    while success && signature_index < signatures.len() {
+                        let signature = &signatures[signature_index];
+                        let pubkey = &pubkeys[key_index];
+
+                        check_signature_encoding(signature, flags)?;
+                        check_pubkey_encoding(pubkey, flags)?;
+
+                        if checker.check_sig(signature, pubkey, &script_code, sig_version) {
+                            signature_index += 1;
+                        }
+                        key_index += 1;
+
+                        if signatures.len() - signature_index > pubkeys.len() - key_index {
+                            success = false;
+                        }
+                    }
+
+                    if !success
+                        && flags & SCRIPT_VERIFY_NULLFAIL != 0
+                        && signatures.iter().any(|signature| !signature.is_empty())
+                    {
+                        return Err(ScriptError::NullFail);
+                    }
+
+                    if opcode == OP_CHECKMULTISIGVERIFY {
+                        if !success {
+                            return Err(ScriptError::CheckMultisigVerify);
+                        }
+                    } else {
+                        stack.push(bool_to_element(success));
+                    }
+                }
+
+                _ => return Err(ScriptError::BadOpcode),
+            }
+        }
+
+        if stack.len() + alt_stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !exec_stack.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+
+    Ok(())
+}
+
+/// Checks a signature of a tapscript. Empty signatures fail without an error, any other one must be
+/// valid and uses part of the validation weight. Public keys that are not 32 bytes are of future types
+fn check_tapscript_sig(
+    signature: &[u8],
+    pubkey: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+    execution: &mut ExecutionData,
+) -> Result<bool, ScriptError> {
+    if !signature.is_empty() {
+        execution.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+        if execution.validation_weight_left < 0 {
+            return Err(ScriptError::TapscriptValidationWeight);
+        }
+    }
+
+    if pubkey.is_empty() {
+        return Err(ScriptError::PubkeyType);
+    }
+    if pubkey.len() == 32 {
+        if !signature.is_empty() {
+            checker.check_schnorr_sig(signature, pubkey, execution)?;
+        }
+    } else if flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_PUBKEYTYPE != 0 {
+        return Err(ScriptError::DiscourageUpgradablePubkeyType);
+    }
+
+    Ok(!signature.is_empty())
+}
+
+/// Verifies that the script sig and the witness of an input satisfy the script pubkey of the output
+/// it spends. With the P2SH flag the redeem script of pay to script hash outputs is evaluated too,
+/// and with the witness flag the witness of segwit outputs
+pub fn verify_script(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    witness: &[Vec<u8>],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+) -> Result<(), ScriptError> {
+    if flags & SCRIPT_VERIFY_SIGPUSHONLY != 0 && !is_push_only(script_sig) {
+        return Err(ScriptError::SigPushOnly);
+    }
+
+    let mut stack: Vec<Vec<u8>> = vec![];
+    eval_script(&mut stack, script_sig, flags, checker)?;
+
+    let stack_copy = stack.clone();
+    eval_script(&mut stack, script_pubkey, flags, checker)?;
+
+    if !stack.last().is_some_and(|element| cast_to_bool(element)) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    let mut had_witness = false;
+    if flags & SCRIPT_VERIFY_WITNESS != 0 {
+        if let Some(program) = witness_program(script_pubkey) {
+            had_witness = true;
+            // The witness is the only thing that can spend a native witness program
+            if !script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated);
+            }
+            verify_witness_program(witness, program, flags, checker, false)?;
+            stack.truncate(1);
+        }
+    }
+
+    if flags & SCRIPT_VERIFY_P2SH != 0 && is_p2sh(script_pubkey) {
+        if !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly);
+        }
+
+        stack = stack_copy;
+        let redeem_script = pop(&mut stack)?;
+        eval_script(&mut stack, &redeem_script, flags, checker)?;
+
+        if !stack.last().is_some_and(|element| cast_to_bool(element)) {
+            return Err(ScriptError::EvalFalse);
+        }
+
+        if flags & SCRIPT_VERIFY_WITNESS != 0 {
+            if let Some(program) = witness_program(&redeem_script) {
+                had_witness = true;
+                if script_sig != push_data(&redeem_script) {
+                    return Err(ScriptError::WitnessMalleatedP2sh);
+                }
+                verify_witness_program(witness, program, flags, checker, true)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    if flags & SCRIPT_VERIFY_CLEANSTACK != 0 && stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+
+    if flags & SCRIPT_VERIFY_WITNESS != 0 && !had_witness && !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+
+    Ok(())
+}
+
+/// Verifies the witness of a witness program. Version 0 programs are spent like pay to public key
+/// hash or with the witness script they commit to. Taproot outputs, version 1 programs of 32 bytes
+/// that are not nested in pay to script hash, are spent with a signature of the output key or with
+/// a script of the tree committed in it
+fn verify_witness_program(
+    witness: &[Vec<u8>],
+    (version, program): (u8, &[u8]),
+    flags: u32,
+    checker: &dyn SignatureChecker,
+    is_p2sh: bool,
+) -> Result<(), ScriptError> {
+    if version == 0 {
+        return verify_witness_v0_program(witness, program, flags, checker);
+    }
+
+    if version != 1 || program.len() != 32 || is_p2sh || flags & SCRIPT_VERIFY_TAPROOT == 0 {
+        if flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM != 0 {
+            return Err(ScriptError::DiscourageUpgradableWitnessProgram);
+        }
+        return Ok(());
+    }
+
+    let mut stack = witness.to_vec();
+    if stack.is_empty() {
+        return Err(ScriptError::WitnessProgramWitnessEmpty);
+    }
+
+    let mut annex = None;
+    if stack.len() >= 2
+        && stack
+            .last()
+            .is_some_and(|last| last.first() == Some(&ANNEX_TAG))
+    {
+        annex = stack.pop();
+    }
+
+    if stack.len() == 1 {
+        // Key path spend: the program is the output key
+        let execution = ExecutionData::key_path(annex);
+        return checker.check_schnorr_sig(&stack[0], program, &execution);
+    }
+
+    // Script path spend
+    let control_block = pop(&mut stack)?;
+    let script = pop(&mut stack)?;
+    if !is_valid_control_block_size(&control_block) {
+        return Err(ScriptError::TaprootWrongControlSize);
+    }
+
+    let leaf_version = control_block[0] & TAPROOT_LEAF_MASK;
+    let leaf_hash = tap_leaf_hash(leaf_version, &script);
+    if !verify_taproot_commitment(&control_block, program, &leaf_hash) {
+        return Err(ScriptError::WitnessProgramMismatch);
+    }
+
+    if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+        if flags & SCRIPT_VERIFY_DISCOURAGE_UPGRADABLE_TAPROOT_VERSION != 0 {
+            return Err(ScriptError::DiscourageUpgradableTaprootVersion);
+        }
+        return Ok(());
+    }
+
+    let validation_weight = VALIDATION_WEIGHT_OFFSET + serialized_witness_size(witness) as i64;
+    let mut execution = ExecutionData::script_path(annex, leaf_hash, validation_weight);
+    verify_tapscript(stack, &script, flags, checker, &mut execution)
+}
+
+/// Verifies the witness of a version 0 program (BIP141). A program of 20 bytes is the hash of the
+/// public key, and the witness is its signature and the key, checked like pay to public key hash.
+/// A program of 32 bytes is the sha256 of the witness script, the last element of the witness,
+/// which is evaluated over the rest of it
+fn verify_witness_v0_program(
+    witness: &[Vec<u8>],
+    program: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+) -> Result<(), ScriptError> {
+    let mut stack = witness.to_vec();
+
+    let script = match program.len() {
+        32 => {
+            let script = stack.pop().ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if sha256::Hash::hash(&script)[..] != *program {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            script
+        }
+        20 => {
+            if stack.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            [
+                vec![OP_DUP, OP_HASH160],
+                push_data(program),
+                vec![OP_EQUALVERIFY, OP_CHECKSIG],
+            ]
+            .concat()
+        }
+        _ => return Err(ScriptError::WitnessProgramWrongLength),
+    };
+
+    let mut execution = ExecutionData::key_path(None);
+    execute_witness_script(
+        stack,
+        &script,
+        flags,
+        checker,
+        SigVersion::WitnessV0,
+        &mut execution,
+    )
+}
+
+/// Evaluates the leaf script of a taproot spend over the rest of the witness. A tapscript succeeds as
+/// soon as an OP_SUCCESS opcode is found, and must leave a single true element in the stack
+fn verify_tapscript(
+    stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    let mut position = 0;
+    while position < script.len() {
+        let instruction = read_instruction(script, position)?;
+        if is_op_success(instruction.opcode) {
+            if flags & SCRIPT_VERIFY_DISCOURAGE_OP_SUCCESS != 0 {
+                return Err(ScriptError::DiscourageOpSuccess);
+            }
+            return Ok(());
+        }
+        position = instruction.end;
+    }
+
+    execute_witness_script(
+        stack,
+        script,
+        flags,
+        checker,
+        SigVersion::Tapscript,
+        execution,
+    )
+}
+
+/// Evaluates a witness script over the rest of the witness, which must leave a single true element
+fn execute_witness_script(
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: u32,
+    checker: &dyn SignatureChecker,
+    sig_version: SigVersion,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    if sig_version == SigVersion::Tapscript && stack.len() > MAX_STACK_SIZE {
+        return Err(ScriptError::StackSize);
+    }
+    if stack
+        .iter()
+        .any(|element| element.len() > MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(ScriptError::PushSize);
+    }
+
+    execute_script(&mut stack, script, flags, checker, sig_version, execution)?;
+
+    if stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    if !cast_to_bool(&stack[0]) {
+        return Err(ScriptError::EvalFalse);
+    }
+
+    Ok(())
+}
+
+/// Size of the witness serialized as in a transaction
+fn serialized_witness_size(witness: &[Vec<u8>]) -> usize {
+    let mut size = serialize_var_int(witness.len() as u64).len();
+    for element in witness {
+        size += serialize_var_int(element.len() as u64).len() + element.len();
+    }
+    size
+}
+
+/// Verifies the scripts of every input of the transaction. The spent outputs must be in the
+/// same order as the inputs that spend them
+pub fn verify_transaction_scripts(
+    transaction: &Transaction,
+    spent_outputs: &[TransactionOutput],
+    flags: u32,
+) -> Result<(), ScriptError> {
+    if spent_outputs.len() != transaction.inputs.len() {
+        return Err(ScriptError::SpentOutputsMismatch);
+    }
+
+    for (index, (input, spent_output)) in transaction.inputs.iter().zip(spent_outputs).enumerate() {
+        let checker = TransactionSignatureChecker::new(transaction, index, spent_outputs);
+        verify_script(
+            &input.script,
+            &spent_output.script_pubkey,
+            &input.witness,
+            flags,
+            &checker,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        helpers::test_helpers,
+        script::{
+            sighash::{
+                legacy_signature_hash, taproot_signature_hash, witness_v0_signature_hash,
+                SIGHASH_ALL, SIGHASH_DEFAULT,
+            },
+            taproot::{tagged_hash, tweak_public_key},
+        },
+    };
+    use k256::{
+        elliptic_curve::{ff::PrimeField, NonZeroScalar},
+        schnorr::{signature::hazmat::PrehashSigner, SigningKey},
+        FieldBytes, Scalar,
+    };
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+    struct NoSignatures;
+
+    impl SignatureChecker for NoSignatures {
+        fn check_sig(
+            &self,
+            _signature: &[u8],
+            _pubkey: &[u8],
+            _script_code: &[u8],
+            _sig_version: SigVersion,
+        ) -> bool {
+            false
+        }
+    }
+
+    fn eval(script: &[u8]) -> Result<Vec<Vec<u8>>, ScriptError> {
+        let mut stack = vec![];
+        eval_script(
+            &mut stack,
+            script,
+            STANDARD_SCRIPT_VERIFY_FLAGS,
+            &NoSignatures,
+        )?;
+        Ok(stack)
+    }
+
+    fn secret_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn pubkey(byte: u8) -> Vec<u8> {
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(byte))
+            .serialize()
+            .to_vec()
+    }
+
+    fn spending_transaction() -> Transaction {
+        test_helpers::transaction(
+            vec![test_helpers::input([7; 36], vec![])],
+            vec![test_helpers::output(1000, vec![OP_TRUE])],
+        )
+    }
+
+    fn sign(transaction: &Transaction, script_code: &[u8], key: u8) -> Vec<u8> {
+        let sighash = legacy_signature_hash(transaction, 0, script_code, SIGHASH_ALL);
+        let message = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut signature = Secp256k1::new()
+            .sign_ecdsa(&message, &secret_key(key))
+            .serialize_der()
+            .to_vec();
+        signature.push(SIGHASH_ALL as u8);
+        signature
+    }
+
+    fn sign_witness_v0(transaction: &Transaction, script_code: &[u8], key: u8) -> Vec<u8> {
+        let sighash =
+            witness_v0_signature_hash(transaction, 0, script_code, 5000, SIGHASH_ALL).unwrap();
+        let message = secp256k1::Message::from_slice(&sighash).unwrap();
+        let mut signature = Secp256k1::new()
+            .sign_ecdsa(&message, &secret_key(key))
+            .serialize_der()
+            .to_vec();
+        signature.push(SIGHASH_ALL as u8);
+        signature
+    }
+
+    fn p2pkh_script(pubkey: &[u8]) -> Vec<u8> {
+        let mut script = vec![OP_DUP, OP_HASH160];
+        script.extend_from_slice(&push_data(&hash160::Hash::hash(pubkey)));
+        script.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+        script
+    }
+
+    fn multisig_script(required: u8, keys: &[u8]) -> Vec<u8> {
+        let mut script = vec![OP_1 + required - 1];
+        for key in keys {
+            script.extend_from_slice(&push_data(&pubkey(*key)));
+        }
+        script.extend_from_slice(&[OP_1 + keys.len() as u8 - 1, OP_CHECKMULTISIG]);
+        script
+    }
+
+    fn verify(tx: &Transaction, script_pubkey: &[u8]) -> Result<(), ScriptError> {
+        let spent = TransactionOutput {
+            value: 5000,
+            script_pubkey: script_pubkey.to_vec(),
+        };
+        verify_transaction_scripts(tx, &[spent], STANDARD_SCRIPT_VERIFY_FLAGS)
+    }
+
+    fn schnorr_key(byte: u8) -> SigningKey {
+        SigningKey::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn xonly(byte: u8) -> Vec<u8> {
+        schnorr_key(byte).verifying_key().to_bytes().to_vec()
+    }
+
+    /// Key of the output of a key path only spend of the internal key
+    fn tweaked_key(byte: u8) -> SigningKey {
+        let internal_key = schnorr_key(byte);
+        let tweak = tagged_hash("TapTweak", &internal_key.verifying_key().to_bytes());
+        let tweak = Scalar::from_repr(FieldBytes::from(tweak)).unwrap();
+        let secret =
+            NonZeroScalar::new(*internal_key.as_nonzero_scalar().as_ref() + tweak).unwrap();
+        SigningKey::from(secret)
+    }
+
+    fn p2tr_output(output_key: &[u8]) -> Vec<TransactionOutput> {
+        vec![TransactionOutput {
+            value: 5000,
+            script_pubkey: [vec![OP_1, 0x20], output_key.to_vec()].concat(),
+        }]
+    }
+
+    fn sign_schnorr(
+        tx: &Transaction,
+        spent: &[TransactionOutput],
+        execution: &ExecutionData,
+        key: &SigningKey,
+    ) -> Vec<u8> {
+        let sighash = taproot_signature_hash(tx, 0, spent, SIGHASH_DEFAULT, execution).unwrap();
+        key.sign_prehash(&sighash).unwrap().to_bytes().to_vec()
+    }
+
+    /// Spent output with a tree of a single tapscript leaf, and the control block to spend it
+    fn tapscript_output(script: &[u8]) -> (Vec<TransactionOutput>, Vec<u8>) {
+        let leaf_hash = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, script);
+        let (output_key, odd) = tweak_public_key(&xonly(1), Some(&leaf_hash)).unwrap();
+
+        let mut control_block = vec![TAPROOT_LEAF_TAPSCRIPT | odd as u8];
+        control_block.extend_from_slice(&xonly(1));
+        (p2tr_output(&output_key), control_block)
+    }
+
+    #[test]
+    pub fn test_script_numbers_round_trip() {
+        for value in [0, 1, -1, 127, 128, -128, 255, 32767, -32768, 2147483647] {
+            let bytes = encode_script_num(value);
+            assert_eq!(decode_script_num(&bytes, true, 4), Ok(value));
+        }
+        assert_eq!(encode_script_num(-1), vec![0x81]);
+        assert_eq!(
+            decode_script_num(&[0x01, 0x00], true, 4),
+            Err(ScriptError::MinimalData)
+        );
+    }
+
+    #[test]
+    pub fn test_arithmetic_and_conditionals() {
+        let stack = eval(&[OP_2, OP_3, OP_ADD, OP_1 + 4, OP_NUMEQUAL]).unwrap();
+        assert!(cast_to_bool(&stack[0]));
+
+        let stack = eval(&[OP_0, OP_IF, OP_2, OP_ELSE, OP_3, OP_ENDIF]).unwrap();
+        assert_eq!(stack, vec![vec![3]]);
+    }
+
+    #[test]
+    pub fn test_invalid_scripts() {
+        assert_eq!(
+            eval(&[OP_1, OP_IF]),
+            Err(ScriptError::UnbalancedConditional)
+        );
+        assert_eq!(
+            eval(&[OP_0, OP_IF, OP_CAT, OP_ENDIF]),
+            Err(ScriptError::DisabledOpcode)
+        );
+        assert_eq!(eval(&[OP_DROP]), Err(ScriptError::InvalidStackOperation));
+        assert_eq!(eval(&[OP_RETURN]), Err(ScriptError::OpReturn));
+        assert_eq!(eval(&[0x01, 0x05]), Err(ScriptError::MinimalData));
+    }
+
+    #[test]
+    pub fn test_p2pk_spend() {
+        let script_pubkey = [push_data(&pubkey(1)), vec![OP_CHECKSIG]].concat();
+        let mut tx = spending_transaction();
+        tx.inputs[0].script = push_data(&sign(&tx, &script_pubkey, 1));
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        tx.inputs[0].script = push_data(&sign(&tx, &script_pubkey, 2));
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::NullFail));
+    }
+
+    #[test]
+    pub fn test_p2pkh_spend() {
+        let script_pubkey = p2pkh_script(&pubkey(1));
+        let mut tx = spending_transaction();
+        let signature = sign(&tx, &script_pubkey, 1);
+        tx.inputs[0].script = [push_data(&signature), push_data(&pubkey(1))].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // The signature does not cover a different output
+        tx.outputs[0].value = 999;
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::NullFail));
+
+        tx.inputs[0].script = [push_data(&signature), push_data(&pubkey(2))].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::EqualVerify));
+    }
+
+    #[test]
+    pub fn test_bare_multisig_spend() {
+        let script_pubkey = multisig_script(2, &[1, 2, 3]);
+        let mut tx = spending_transaction();
+        let first = sign(&tx, &script_pubkey, 1);
+        let third = sign(&tx, &script_pubkey, 3);
+
+        tx.inputs[0].script = [vec![OP_0], push_data(&first), push_data(&third)].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // Signatures in a different order than their keys fail
+        tx.inputs[0].script = [vec![OP_0], push_data(&third), push_data(&first)].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::NullFail));
+
+        tx.inputs[0].script = [vec![OP_1], push_data(&first), push_data(&third)].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::SigNullDummy));
+    }
+
+    #[test]
+    pub fn test_p2sh_multisig_spend() {
+        let redeem_script = multisig_script(1, &[1, 2]);
+        let mut script_pubkey = vec![OP_HASH160];
+        script_pubkey.extend_from_slice(&push_data(&hash160::Hash::hash(&redeem_script)));
+        script_pubkey.push(OP_EQUAL);
+
+        let mut tx = spending_transaction();
+        let signature = sign(&tx, &redeem_script, 2);
+        tx.inputs[0].script =
+            [vec![OP_0], push_data(&signature), push_data(&redeem_script)].concat();
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // Without the P2SH flag only the hash of the redeem script is checked
+        let spent = TransactionOutput {
+            value: 5000,
+            script_pubkey: script_pubkey.clone(),
+        };
+        tx.inputs[0].script = [vec![OP_0], push_data(&[]), push_data(&redeem_script)].concat();
+        let spent = [spent];
+        assert_eq!(verify_transaction_scripts(&tx, &spent, 0), Ok(()));
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, SCRIPT_VERIFY_P2SH),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    pub fn test_p2wpkh_spend() {
+        let script_pubkey = [vec![OP_0], push_data(&hash160::Hash::hash(&pubkey(1)))].concat();
+        let mut tx = spending_transaction();
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+
+        let signature = sign_witness_v0(&tx, &p2pkh_script(&pubkey(1)), 1);
+        tx.inputs[0].witness = vec![signature.clone(), pubkey(1)];
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // The signature commits to the amount of the spent output
+        let spent = TransactionOutput {
+            value: 4000,
+            script_pubkey: script_pubkey.clone(),
+        };
+        assert_eq!(
+            verify_transaction_scripts(&tx, &[spent], STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::NullFail)
+        );
+
+        tx.inputs[0].witness = vec![signature.clone(), pubkey(2)];
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::EqualVerify));
+
+        // A segwit input can not be spent with the script sig
+        tx.inputs[0].witness = vec![];
+        tx.inputs[0].script = [push_data(&signature), push_data(&pubkey(1))].concat();
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::WitnessMalleated)
+        );
+    }
+
+    #[test]
+    pub fn test_p2wsh_spend() {
+        let witness_script = multisig_script(1, &[1, 2]);
+        let script_pubkey = [vec![OP_0], push_data(&sha256::Hash::hash(&witness_script))].concat();
+        let mut tx = spending_transaction();
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::WitnessProgramWitnessEmpty)
+        );
+
+        tx.inputs[0].witness = vec![vec![OP_TRUE]];
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+
+        let signature = sign_witness_v0(&tx, &witness_script, 2);
+        tx.inputs[0].witness = vec![vec![], signature, witness_script.clone()];
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // A legacy signature does not sign the witness script
+        let signature = sign(&tx, &witness_script, 2);
+        tx.inputs[0].witness = vec![vec![], signature, witness_script];
+        assert_eq!(verify(&tx, &script_pubkey), Err(ScriptError::NullFail));
+    }
+
+    #[test]
+    pub fn test_checklocktimeverify_compares_with_the_lock_time() {
+        let script_pubkey = [
+            push_data(&encode_script_num(500)),
+            vec![OP_CHECKLOCKTIMEVERIFY, OP_DROP, OP_1],
+        ]
+        .concat();
+        let mut tx = spending_transaction();
+
+        tx.lock_time = 400;
+        tx.inputs[0].sequence = 0xfffffffe;
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+
+        tx.lock_time = 500;
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        // The final sequence disables the lock time of the transaction
+        tx.inputs[0].sequence = 0xffffffff;
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    pub fn test_checksequenceverify_compares_with_the_sequence() {
+        let script_pubkey = vec![OP_1 + 9, OP_CHECKSEQUENCEVERIFY, OP_DROP, OP_1];
+        let mut tx = spending_transaction();
+        tx.version = 2;
+
+        tx.inputs[0].sequence = 10;
+        assert_eq!(verify(&tx, &script_pubkey), Ok(()));
+
+        tx.inputs[0].sequence = 9;
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+
+        tx.version = 1;
+        tx.inputs[0].sequence = 10;
+        assert_eq!(
+            verify(&tx, &script_pubkey),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    pub fn test_taproot_key_path_spend() {
+        let key = tweaked_key(1);
+        let spent = p2tr_output(&key.verifying_key().to_bytes());
+        let mut tx = spending_transaction();
+        let signature = sign_schnorr(&tx, &spent, &ExecutionData::key_path(None), &key);
+
+        tx.inputs[0].witness = vec![signature.clone()];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Ok(())
+        );
+
+        // The default hash type can not be explicit
+        tx.inputs[0].witness = vec![[signature.clone(), vec![0x00]].concat()];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::SchnorrSigHashType)
+        );
+
+        tx.inputs[0].witness = vec![signature];
+        tx.outputs[0].value = 999;
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::SchnorrSig)
+        );
+    }
+
+    #[test]
+    pub fn test_tapscript_spend_with_checksigadd() {
+        let script = [
+            push_data(&xonly(2)),
+            vec![OP_CHECKSIG],
+            push_data(&xonly(3)),
+            vec![OP_CHECKSIGADD, OP_2, OP_NUMEQUAL],
+        ]
+        .concat();
+        let (spent, control_block) = tapscript_output(&script);
+        let mut tx = spending_transaction();
+
+        let leaf_hash = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &script);
+        let execution = ExecutionData::script_path(None, leaf_hash, 0);
+        let first = sign_schnorr(&tx, &spent, &execution, &schnorr_key(2));
+        let second = sign_schnorr(&tx, &spent, &execution, &schnorr_key(3));
+
+        tx.inputs[0].witness = vec![
+            second.clone(),
+            first.clone(),
+            script.clone(),
+            control_block.clone(),
+        ];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Ok(())
+        );
+
+        // An empty signature fails the check without an error
+        tx.inputs[0].witness = vec![vec![], first, script.clone(), control_block.clone()];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::EvalFalse)
+        );
+
+        // A script that is not in the tree
+        tx.inputs[0].witness = vec![second, vec![OP_1], control_block];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+    }
+
+    #[test]
+    pub fn test_tapscript_op_success_and_multisig() {
+        let script = vec![OP_RESERVED];
+        let (spent, control_block) = tapscript_output(&script);
+        let mut tx = spending_transaction();
+        tx.inputs[0].witness = vec![script, control_block];
+
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS),
+            Ok(())
+        );
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, STANDARD_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::DiscourageOpSuccess)
+        );
+
+        let script = vec![OP_0, OP_0, OP_0, OP_CHECKMULTISIG];
+        let (spent, control_block) = tapscript_output(&script);
+        tx.inputs[0].witness = vec![script, control_block];
+        assert_eq!(
+            verify_transaction_scripts(&tx, &spent, MANDATORY_SCRIPT_VERIFY_FLAGS),
+            Err(ScriptError::TapscriptCheckMultisig)
+        );
+    }
+}
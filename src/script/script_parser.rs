@@ -0,0 +1,210 @@
+use super::{
+    interpreter::ScriptError,
+    opcodes::{
+        OP_0, OP_1, OP_16, OP_1NEGATE, OP_CODESEPARATOR, OP_EQUAL, OP_HASH160, OP_PUSHDATA1,
+        OP_PUSHDATA2, OP_PUSHDATA4,
+    },
+};
+
+/// #TDA Instruction
+/// Opcode of a script with the data it pushes, empty for the opcodes that do not push.
+/// End is the position of the script where the next instruction starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction<'a> {
+    pub opcode: u8,
+    pub data: &'a [u8],
+    pub end: usize,
+}
+
+/// Reads the instruction that starts at the position of the script
+pub fn read_instruction(script: &[u8], position: usize) -> Result<Instruction<'_>, ScriptError> {
+    let opcode = *script.get(position).ok_or(ScriptError::BadOpcode)?;
+    let mut start = position + 1;
+
+    let size = match opcode {
+        OP_PUSHDATA1 => {
+            let size = *script.get(start).ok_or(ScriptError::BadOpcode)? as usize;
+            start += 1;
+            size
+        }
+        OP_PUSHDATA2 => {
+            let bytes = script.get(start..start + 2).ok_or(ScriptError::BadOpcode)?;
+            start += 2;
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+        }
+        OP_PUSHDATA4 => {
+            let bytes = script.get(start..start + 4).ok_or(ScriptError::BadOpcode)?;
+            start += 4;
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+        }
+        size if size < OP_PUSHDATA1 => size as usize,
+        _ => 0,
+    };
+
+    let data = script
+        .get(start..start + size)
+        .ok_or(ScriptError::BadOpcode)?;
+
+    Ok(Instruction {
+        opcode,
+        data,
+        end: start + size,
+    })
+}
+
+/// Splits the script in its instructions. Fails if a push goes past the end of the script
+pub fn parse_script(script: &[u8]) -> Result<Vec<Instruction<'_>>, ScriptError> {
+    let mut instructions = vec![];
+    let mut position = 0;
+
+    while position < script.len() {
+        let instruction = read_instruction(script, position)?;
+        position = instruction.end;
+        instructions.push(instruction);
+    }
+
+    Ok(instructions)
+}
+
+/// Returns true if the script only has push opcodes
+pub fn is_push_only(script: &[u8]) -> bool {
+    match parse_script(script) {
+        Ok(instructions) => instructions
+            .iter()
+            .all(|instruction| instruction.opcode <= OP_16),
+        Err(_) => false,
+    }
+}
+
+/// Returns true if the script is a pay to script hash one: OP_HASH160 <20 bytes> OP_EQUAL
+pub fn is_p2sh(script_pubkey: &[u8]) -> bool {
+    script_pubkey.len() == 23
+        && script_pubkey[0] == OP_HASH160
+        && script_pubkey[1] == 0x14
+        && script_pubkey[22] == OP_EQUAL
+}
+
+/// Returns true if the data was pushed with the smallest possible opcode
+pub fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    match data.len() {
+        0 => opcode == OP_0,
+        1 if (1..=16).contains(&data[0]) => opcode == OP_1 + data[0] - 1,
+        1 if data[0] == 0x81 => opcode == OP_1NEGATE,
+        size if size < OP_PUSHDATA1 as usize => opcode as usize == size,
+        size if size <= 0xff => opcode == OP_PUSHDATA1,
+        size if size <= 0xffff => opcode == OP_PUSHDATA2,
+        _ => true,
+    }
+}
+
+/// Returns the script that pushes the data
+pub fn push_data(data: &[u8]) -> Vec<u8> {
+    let mut script = vec![];
+
+    if data.len() < OP_PUSHDATA1 as usize {
+        script.push(data.len() as u8);
+    } else if data.len() <= 0xff {
+        script.push(OP_PUSHDATA1);
+        script.push(data.len() as u8);
+    } else if data.len() <= 0xffff {
+        script.push(OP_PUSHDATA2);
+        script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    } else {
+        script.push(OP_PUSHDATA4);
+        script.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    }
+
+    script.extend_from_slice(data);
+    script
+}
+
+/// Removes every appearance of the pattern that starts at the beginning of an instruction
+pub fn find_and_delete(script: &[u8], pattern: &[u8]) -> Vec<u8> {
+    if pattern.is_empty() {
+        return script.to_vec();
+    }
+
+    let mut result = vec![];
+    let mut position = 0;
+
+    while position < script.len() {
+        if script[position..].starts_with(pattern) {
+            position += pattern.len();
+            continue;
+        }
+
+        match read_instruction(script, position) {
+            Ok(instruction) => {
+                result.extend_from_slice(&script[position..instruction.end]);
+                position = instruction.end;
+            }
+            Err(_) => {
+                result.extend_from_slice(&script[position..]);
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+/// Removes the OP_CODESEPARATOR of the script, as the signature hash does with the script code
+pub fn remove_code_separators(script: &[u8]) -> Vec<u8> {
+    let mut result = vec![];
+    let mut position = 0;
+
+    while position < script.len() {
+        match read_instruction(script, position) {
+            Ok(instruction) => {
+                if instruction.opcode != OP_CODESEPARATOR {
+                    result.extend_from_slice(&script[position..instruction.end]);
+                }
+                position = instruction.end;
+            }
+            Err(_) => {
+                result.extend_from_slice(&script[position..]);
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::opcodes::{OP_CHECKSIG, OP_DUP};
+
+    #[test]
+    pub fn test_push_past_the_end_of_the_script_is_invalid() {
+        assert!(parse_script(&[0x05, 0x01, 0x02]).is_err());
+        assert!(parse_script(&[OP_PUSHDATA1]).is_err());
+    }
+
+    #[test]
+    pub fn test_find_and_delete_only_removes_whole_instructions() {
+        let signature = push_data(&[0xab, 0xcd]);
+        let mut script = signature.clone();
+        script.push(OP_DUP);
+        // The pattern appears inside the data of this push, so it must stay
+        script.extend_from_slice(&push_data(&[0x02, 0xab, 0xcd]));
+        script.extend_from_slice(&signature);
+        script.push(OP_CHECKSIG);
+
+        let mut expected = vec![OP_DUP];
+        expected.extend_from_slice(&push_data(&[0x02, 0xab, 0xcd]));
+        expected.push(OP_CHECKSIG);
+
+        assert_eq!(find_and_delete(&script, &signature), expected);
+    }
+
+    #[test]
+    pub fn test_minimal_pushes() {
+        assert!(is_minimal_push(OP_0, &[]));
+        assert!(!is_minimal_push(0x01, &[0x05]));
+        assert!(is_minimal_push(0x01, &[0x20]));
+        assert!(!is_minimal_push(OP_PUSHDATA1, &[0x20; 10]));
+        assert!(is_minimal_push(OP_PUSHDATA1, &[0x20; 80]));
+    }
+}
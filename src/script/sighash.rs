@@ -0,0 +1,391 @@
+use bitcoin_hashes::{sha256, sha256d, Hash};
+
+use super::{script_parser::remove_code_separators, taproot::tagged_hash};
+use crate::{
+    components::transaction::{Transaction, TransactionOutput},
+    helpers::auxiliar_functions::serialize_var_int,
+};
+
+/// Hash type of taproot signatures without the hash type byte, it signs like SIGHASH_ALL
+pub const SIGHASH_DEFAULT: u32 = 0x00;
+pub const SIGHASH_ALL: u32 = 0x01;
+pub const SIGHASH_NONE: u32 = 0x02;
+pub const SIGHASH_SINGLE: u32 = 0x03;
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// Hash signed when there is no output for a SIGHASH_SINGLE input, kept for compatibility
+const SIGHASH_SINGLE_BUG: [u8; 32] = {
+    let mut hash = [0u8; 32];
+    hash[0] = 1;
+    hash
+};
+
+/// Returns true if the hash type is one of the defined ones
+pub fn is_defined_hash_type(hash_type: u32) -> bool {
+    let base_type = hash_type & !SIGHASH_ANYONECANPAY;
+    (SIGHASH_ALL..=SIGHASH_SINGLE).contains(&base_type)
+}
+
+/// Calculates the hash that the signatures of the input sign for scripts that are not segwit.
+/// The input signs the script code and, depending on the hash type, the rest of the inputs and outputs
+pub fn legacy_signature_hash(
+    transaction: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    hash_type: u32,
+) -> [u8; 32] {
+    let base_type = hash_type & 0x1f;
+
+    if input_index >= transaction.inputs.len()
+        || (base_type == SIGHASH_SINGLE && input_index >= transaction.outputs.len())
+    {
+        return SIGHASH_SINGLE_BUG;
+    }
+
+    let mut tx_copy = transaction.clone();
+    let script_code = remove_code_separators(script_code);
+
+    for (index, input) in tx_copy.inputs.iter_mut().enumerate() {
+        if index == input_index {
+            input.script = script_code.clone();
+        } else {
+            input.script = vec![];
+            if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+                input.sequence = 0;
+            }
+        }
+    }
+
+    if hash_type & SIGHASH_ANYONECANPAY != 0 {
+        tx_copy.inputs = vec![tx_copy.inputs[input_index].clone()];
+    }
+
+    if base_type == SIGHASH_NONE {
+        tx_copy.outputs = vec![];
+    } else if base_type == SIGHASH_SINGLE {
+        tx_copy.outputs.truncate(input_index + 1);
+        for output in tx_copy.outputs.iter_mut().take(input_index) {
+            *output = TransactionOutput {
+                value: u64::MAX,
+                script_pubkey: vec![],
+            };
+        }
+    }
+
+    let mut bytes = tx_copy.serialize_without_witness();
+    bytes.extend_from_slice(&hash_type.to_le_bytes());
+
+    sha256d::Hash::hash(&bytes).into_inner()
+}
+
+/// Calculates the hash that the signatures of version 0 witness programs sign (BIP143). The input
+/// commits to the amount of the output it spends, and the hashes of the other inputs and outputs
+/// are the same for every input. Returns None if the transaction has no such input
+pub fn witness_v0_signature_hash(
+    transaction: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    amount: u64,
+    hash_type: u32,
+) -> Option<[u8; 32]> {
+    let input = transaction.inputs.get(input_index)?;
+    let base_type = hash_type & 0x1f;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+    let mut hash_prevouts = [0u8; 32];
+    let mut hash_sequence = [0u8; 32];
+    let mut hash_outputs = [0u8; 32];
+
+    if !anyone_can_pay {
+        let prevouts: Vec<u8> = transaction
+            .inputs
+            .iter()
+            .flat_map(|input| input.previous_output)
+            .collect();
+        hash_prevouts = sha256d::Hash::hash(&prevouts).into_inner();
+    }
+
+    if !anyone_can_pay && base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+        let sequences: Vec<u8> = transaction
+            .inputs
+            .iter()
+            .flat_map(|input| input.sequence.to_le_bytes())
+            .collect();
+        hash_sequence = sha256d::Hash::hash(&sequences).into_inner();
+    }
+
+    if base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE {
+        let outputs: Vec<u8> = transaction
+            .outputs
+            .iter()
+            .flat_map(|output| output.to_bytes())
+            .collect();
+        hash_outputs = sha256d::Hash::hash(&outputs).into_inner();
+    } else if base_type == SIGHASH_SINGLE && input_index < transaction.outputs.len() {
+        hash_outputs =
+            sha256d::Hash::hash(&transaction.outputs[input_index].to_bytes()).into_inner();
+    }
+
+    let mut bytes = transaction.version.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&hash_prevouts);
+    bytes.extend_from_slice(&hash_sequence);
+    bytes.extend_from_slice(&input.previous_output);
+    bytes.extend(serialize_script(script_code));
+    bytes.extend_from_slice(&amount.to_le_bytes());
+    bytes.extend_from_slice(&input.sequence.to_le_bytes());
+    bytes.extend_from_slice(&hash_outputs);
+    bytes.extend_from_slice(&transaction.lock_time.to_le_bytes());
+    bytes.extend_from_slice(&hash_type.to_le_bytes());
+
+    Some(sha256d::Hash::hash(&bytes).into_inner())
+}
+
+/// #TDA ExecutionData
+/// Data of a taproot spend, besides the transaction, that its signatures commit to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionData {
+    pub annex: Option<Vec<u8>>,
+    /// Hash of the executed leaf, only in script path spends
+    pub leaf_hash: Option<[u8; 32]>,
+    /// Opcode position of the last executed OP_CODESEPARATOR, u32::MAX if there was none
+    pub code_separator: u32,
+    /// Weight left for signature checks of the tapscript (BIP342)
+    pub validation_weight_left: i64,
+}
+
+impl ExecutionData {
+    pub fn key_path(annex: Option<Vec<u8>>) -> Self {
+        ExecutionData {
+            annex,
+            leaf_hash: None,
+            code_separator: u32::MAX,
+            validation_weight_left: 0,
+        }
+    }
+
+    pub fn script_path(
+        annex: Option<Vec<u8>>,
+        leaf_hash: [u8; 32],
+        validation_weight_left: i64,
+    ) -> Self {
+        ExecutionData {
+            annex,
+            leaf_hash: Some(leaf_hash),
+            code_separator: u32::MAX,
+            validation_weight_left,
+        }
+    }
+}
+
+/// Returns true if the hash type can be used by taproot signatures
+pub fn is_defined_taproot_hash_type(hash_type: u32) -> bool {
+    hash_type == SIGHASH_DEFAULT || is_defined_hash_type(hash_type)
+}
+
+/// Calculates the hash that taproot signatures of the input sign (BIP341). Every input commits
+/// to the amounts and scripts of all the spent outputs, that must be in the same order as the inputs.
+/// Returns None for undefined hash types and for SIGHASH_SINGLE inputs without an output
+pub fn taproot_signature_hash(
+    transaction: &Transaction,
+    input_index: usize,
+    spent_outputs: &[TransactionOutput],
+    hash_type: u32,
+    execution: &ExecutionData,
+) -> Option<[u8; 32]> {
+    if !is_defined_taproot_hash_type(hash_type)
+        || input_index >= transaction.inputs.len()
+        || spent_outputs.len() != transaction.inputs.len()
+    {
+        return None;
+    }
+
+    let base_type = hash_type & 0x03;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+
+    // Epoch of the signature hash
+    let mut bytes = vec![0x00];
+    bytes.push(hash_type as u8);
+    bytes.extend_from_slice(&transaction.version.to_le_bytes());
+    bytes.extend_from_slice(&transaction.lock_time.to_le_bytes());
+
+    if !anyone_can_pay {
+        let mut prevouts = vec![];
+        let mut amounts = vec![];
+        let mut script_pubkeys = vec![];
+        let mut sequences = vec![];
+        for (input, spent_output) in transaction.inputs.iter().zip(spent_outputs) {
+            prevouts.extend_from_slice(&input.previous_output);
+            amounts.extend_from_slice(&spent_output.value.to_le_bytes());
+            script_pubkeys.extend(serialize_script(&spent_output.script_pubkey));
+            sequences.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        bytes.extend_from_slice(&sha256::Hash::hash(&prevouts));
+        bytes.extend_from_slice(&sha256::Hash::hash(&amounts));
+        bytes.extend_from_slice(&sha256::Hash::hash(&script_pubkeys));
+        bytes.extend_from_slice(&sha256::Hash::hash(&sequences));
+    }
+
+    if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+        let outputs: Vec<u8> = transaction
+            .outputs
+            .iter()
+            .flat_map(|output| output.to_bytes())
+            .collect();
+        bytes.extend_from_slice(&sha256::Hash::hash(&outputs));
+    }
+
+    let spend_type = (execution.leaf_hash.is_some() as u8) * 2 + execution.annex.is_some() as u8;
+    bytes.push(spend_type);
+
+    if anyone_can_pay {
+        let input = &transaction.inputs[input_index];
+        let spent_output = &spent_outputs[input_index];
+        bytes.extend_from_slice(&input.previous_output);
+        bytes.extend_from_slice(&spent_output.value.to_le_bytes());
+        bytes.extend(serialize_script(&spent_output.script_pubkey));
+        bytes.extend_from_slice(&input.sequence.to_le_bytes());
+    } else {
+        bytes.extend_from_slice(&(input_index as u32).to_le_bytes());
+    }
+
+    if let Some(annex) = &execution.annex {
+        bytes.extend_from_slice(&sha256::Hash::hash(&serialize_script(annex)));
+    }
+
+    if base_type == SIGHASH_SINGLE {
+        let output = transaction.outputs.get(input_index)?;
+        bytes.extend_from_slice(&sha256::Hash::hash(&output.to_bytes()));
+    }
+
+    if let Some(leaf_hash) = execution.leaf_hash {
+        bytes.extend_from_slice(&leaf_hash);
+        // Version of the public keys of tapscript
+        bytes.push(0x00);
+        bytes.extend_from_slice(&execution.code_separator.to_le_bytes());
+    }
+
+    Some(tagged_hash("TapSighash", &bytes))
+}
+
+/// Bytes prefixed with their length, the way scripts are serialized
+fn serialize_script(script: &[u8]) -> Vec<u8> {
+    let mut bytes = serialize_var_int(script.len() as u64);
+    bytes.extend_from_slice(script);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        helpers::{auxiliar_functions::hex_to_bytes, test_helpers},
+        testnet_protocol::messages::message_parsers::parse_transaction,
+    };
+
+    fn transaction(inputs: usize, outputs: usize) -> Transaction {
+        test_helpers::transaction(
+            (0..inputs)
+                .map(|index| test_helpers::input([index as u8; 36], vec![0x51]))
+                .collect(),
+            (0..outputs)
+                .map(|index| test_helpers::output(index as u64, vec![0x51]))
+                .collect(),
+        )
+    }
+
+    #[test]
+    pub fn test_sighash_single_without_output_signs_one() {
+        let tx = transaction(2, 1);
+
+        assert_eq!(
+            legacy_signature_hash(&tx, 1, &[0x51], SIGHASH_SINGLE),
+            SIGHASH_SINGLE_BUG
+        );
+    }
+
+    #[test]
+    pub fn test_witness_v0_signature_hash_of_the_bip143_example() {
+        // Native P2WPKH example of BIP143, the second input spends 6 BTC
+        let tx = parse_transaction(
+            hex_to_bytes(
+                "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000\
+                 00eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+                 00ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac90\
+                 93510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+            ),
+            &mut 0,
+        )
+        .unwrap();
+        let script_code = hex_to_bytes("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac");
+
+        assert_eq!(
+            witness_v0_signature_hash(&tx, 1, &script_code, 600_000_000, SIGHASH_ALL).unwrap(),
+            hex_to_bytes("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")[..]
+        );
+    }
+
+    #[test]
+    pub fn test_sighash_all_commits_to_every_output() {
+        let tx = transaction(2, 2);
+        let mut changed = tx.clone();
+        changed.outputs[1].value = 50;
+
+        assert_ne!(
+            legacy_signature_hash(&tx, 0, &[0x51], SIGHASH_ALL),
+            legacy_signature_hash(&changed, 0, &[0x51], SIGHASH_ALL)
+        );
+        assert_eq!(
+            legacy_signature_hash(&tx, 0, &[0x51], SIGHASH_SINGLE),
+            legacy_signature_hash(&changed, 0, &[0x51], SIGHASH_SINGLE)
+        );
+    }
+
+    #[test]
+    pub fn test_taproot_signature_hash_commits_to_every_spent_amount() {
+        let tx = transaction(2, 1);
+        let spent = vec![
+            TransactionOutput {
+                value: 10,
+                script_pubkey: vec![0x51],
+            },
+            TransactionOutput {
+                value: 20,
+                script_pubkey: vec![0x51],
+            },
+        ];
+        let mut changed = spent.clone();
+        changed[1].value = 21;
+        let execution = ExecutionData::key_path(None);
+
+        assert_ne!(
+            taproot_signature_hash(&tx, 0, &spent, SIGHASH_DEFAULT, &execution),
+            taproot_signature_hash(&tx, 0, &changed, SIGHASH_DEFAULT, &execution)
+        );
+        let hash_type = SIGHASH_ALL | SIGHASH_ANYONECANPAY;
+        assert_eq!(
+            taproot_signature_hash(&tx, 0, &spent, hash_type, &execution),
+            taproot_signature_hash(&tx, 0, &changed, hash_type, &execution)
+        );
+        assert_eq!(
+            taproot_signature_hash(&tx, 1, &spent, SIGHASH_SINGLE, &execution),
+            None
+        );
+        assert_eq!(
+            taproot_signature_hash(&tx, 0, &spent, 0x04, &execution),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_anyonecanpay_ignores_the_other_inputs() {
+        let tx = transaction(2, 1);
+        let mut changed = tx.clone();
+        changed.inputs[1].previous_output = [9; 36];
+        let hash_type = SIGHASH_ALL | SIGHASH_ANYONECANPAY;
+
+        assert_eq!(
+            legacy_signature_hash(&tx, 0, &[0x51], hash_type),
+            legacy_signature_hash(&changed, 0, &[0x51], hash_type)
+        );
+    }
+}
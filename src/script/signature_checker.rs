@@ -0,0 +1,367 @@
+use std::sync::OnceLock;
+
+use k256::schnorr::{self, signature::hazmat::PrehashVerifier, VerifyingKey};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, VerifyOnly};
+
+use super::{
+    interpreter::{
+        ScriptError, SigVersion, SCRIPT_VERIFY_DERSIG, SCRIPT_VERIFY_LOW_S, SCRIPT_VERIFY_STRICTENC,
+    },
+    sighash::{
+        is_defined_hash_type, legacy_signature_hash, taproot_signature_hash,
+        witness_v0_signature_hash, ExecutionData, SIGHASH_DEFAULT,
+    },
+};
+use crate::components::{
+    transaction::{Transaction, TransactionOutput},
+    transaction_validation::{
+        LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK,
+        SEQUENCE_LOCKTIME_TYPE_FLAG,
+    },
+};
+
+/// Checks the signatures found while evaluating a script
+pub trait SignatureChecker {
+    /// Returns true if the signature, with its hash type as last byte, signs the script code with the public key.
+    /// The signature version tells which hash was signed
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool;
+
+    /// Checks a Schnorr signature, with an optional hash type byte, of a taproot spend with the x only public key
+    fn check_schnorr_sig(
+        &self,
+        _signature: &[u8],
+        _pubkey: &[u8],
+        _execution: &ExecutionData,
+    ) -> Result<(), ScriptError> {
+        Err(ScriptError::SchnorrSig)
+    }
+
+    /// Returns true if the lock time of the transaction is at least the one required by OP_CHECKLOCKTIMEVERIFY
+    fn check_lock_time(&self, _lock_time: i64) -> bool {
+        false
+    }
+
+    /// Returns true if the relative lock of the input is at least the one required by OP_CHECKSEQUENCEVERIFY
+    fn check_sequence(&self, _sequence: i64) -> bool {
+        false
+    }
+}
+
+/// #TDA TransactionSignatureChecker
+/// Checks the signatures of an input of a transaction. Amount is the value of the output it spends,
+/// taproot signatures sign every spent output, in the same order as the inputs
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TransactionSignatureChecker<'a> {
+    pub transaction: &'a Transaction,
+    pub input_index: usize,
+    pub amount: u64,
+    pub spent_outputs: &'a [TransactionOutput],
+}
+
+impl<'a> TransactionSignatureChecker<'a> {
+    pub fn new(
+        transaction: &'a Transaction,
+        input_index: usize,
+        spent_outputs: &'a [TransactionOutput],
+    ) -> Self {
+        TransactionSignatureChecker {
+            transaction,
+            input_index,
+            amount: spent_outputs
+                .get(input_index)
+                .map_or(0, |spent_output| spent_output.value),
+            spent_outputs,
+        }
+    }
+}
+
+impl SignatureChecker for TransactionSignatureChecker<'_> {
+    fn check_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        let (hash_type, der) = match signature.split_last() {
+            Some((hash_type, der)) => (*hash_type as u32, der),
+            None => return false,
+        };
+
+        let sighash = match sig_version {
+            SigVersion::WitnessV0 => match witness_v0_signature_hash(
+                self.transaction,
+                self.input_index,
+                script_code,
+                self.amount,
+                hash_type,
+            ) {
+                Some(sighash) => sighash,
+                None => return false,
+            },
+            _ => legacy_signature_hash(self.transaction, self.input_index, script_code, hash_type),
+        };
+
+        verify_ecdsa(der, pubkey, &sighash)
+    }
+
+    fn check_schnorr_sig(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        execution: &ExecutionData,
+    ) -> Result<(), ScriptError> {
+        let (signature, hash_type) = match signature.len() {
+            64 => (signature, SIGHASH_DEFAULT),
+            // The default hash type can not be explicit, so there is only one encoding of each signature
+            65 if signature[64] as u32 != SIGHASH_DEFAULT => {
+                (&signature[..64], signature[64] as u32)
+            }
+            65 => return Err(ScriptError::SchnorrSigHashType),
+            _ => return Err(ScriptError::SchnorrSigSize),
+        };
+
+        let sighash = taproot_signature_hash(
+            self.transaction,
+            self.input_index,
+            self.spent_outputs,
+            hash_type,
+            execution,
+        )
+        .ok_or(ScriptError::SchnorrSigHashType)?;
+
+        if !verify_schnorr(signature, pubkey, &sighash) {
+            return Err(ScriptError::SchnorrSig);
+        }
+        Ok(())
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.transaction.lock_time as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+
+        // Heights and times can not be compared
+        if (tx_lock_time < threshold) != (lock_time < threshold) {
+            return false;
+        }
+        if lock_time > tx_lock_time {
+            return false;
+        }
+
+        // A final input would make the lock time of the transaction not apply
+        match self.transaction.inputs.get(self.input_index) {
+            Some(input) => input.sequence != SEQUENCE_FINAL,
+            None => false,
+        }
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        let tx_sequence = match self.transaction.inputs.get(self.input_index) {
+            Some(input) => input.sequence as i64,
+            None => return false,
+        };
+
+        // Relative locks only apply to transactions of version 2 or more (BIP68)
+        if self.transaction.version < 2 {
+            return false;
+        }
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 {
+            return false;
+        }
+
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let tx_masked = tx_sequence & mask;
+        let masked = sequence & mask;
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+
+        // Blocks and times can not be compared
+        if (tx_masked < type_flag) != (masked < type_flag) {
+            return false;
+        }
+        masked <= tx_masked
+    }
+}
+
+/// Verifies a 64 bytes Schnorr signature of the hash with an x only public key (BIP340)
+pub fn verify_schnorr(signature: &[u8], pubkey: &[u8], hash: &[u8; 32]) -> bool {
+    let pubkey = match VerifyingKey::from_bytes(pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return false,
+    };
+
+    match schnorr::Signature::try_from(signature) {
+        Ok(signature) => pubkey.verify_prehash(hash, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn verifier() -> &'static Secp256k1<VerifyOnly> {
+    static VERIFIER: OnceLock<Secp256k1<VerifyOnly>> = OnceLock::new();
+    VERIFIER.get_or_init(Secp256k1::verification_only)
+}
+
+/// Verifies a DER signature of the hash. Signatures with a high S are accepted, the
+/// standard flags are the ones that reject them
+pub fn verify_ecdsa(der: &[u8], pubkey: &[u8], hash: &[u8; 32]) -> bool {
+    let pubkey = match PublicKey::from_slice(pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return false,
+    };
+
+    let mut signature = match Signature::from_der_lax(der) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    signature.normalize_s();
+
+    match Message::from_slice(hash) {
+        Ok(message) => verifier()
+            .verify_ecdsa(&message, &signature, &pubkey)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Checks the encoding of a signature required by the flags. Empty signatures are allowed,
+/// they are the way to make a CHECKSIG fail on purpose
+pub fn check_signature_encoding(signature: &[u8], flags: u32) -> Result<(), ScriptError> {
+    if signature.is_empty() {
+        return Ok(());
+    }
+
+    if flags & (SCRIPT_VERIFY_DERSIG | SCRIPT_VERIFY_LOW_S | SCRIPT_VERIFY_STRICTENC) != 0
+        && !is_valid_signature_encoding(signature)
+    {
+        return Err(ScriptError::SigDer);
+    }
+
+    if flags & SCRIPT_VERIFY_LOW_S != 0 && !has_low_s(&signature[..signature.len() - 1]) {
+        return Err(ScriptError::SigHighS);
+    }
+
+    if flags & SCRIPT_VERIFY_STRICTENC != 0
+        && !is_defined_hash_type(signature[signature.len() - 1] as u32)
+    {
+        return Err(ScriptError::SigHashType);
+    }
+
+    Ok(())
+}
+
+/// Checks that the public key is compressed or uncompressed when the flags require it
+pub fn check_pubkey_encoding(pubkey: &[u8], flags: u32) -> Result<(), ScriptError> {
+    if flags & SCRIPT_VERIFY_STRICTENC == 0 {
+        return Ok(());
+    }
+
+    let valid = match pubkey.first() {
+        Some(0x02) | Some(0x03) => pubkey.len() == 33,
+        Some(0x04) => pubkey.len() == 65,
+        _ => false,
+    };
+
+    if !valid {
+        return Err(ScriptError::PubkeyType);
+    }
+
+    Ok(())
+}
+
+/// Returns true if the signature, with its hash type, is a strict DER encoding (BIP66)
+pub fn is_valid_signature_encoding(signature: &[u8]) -> bool {
+    let size = signature.len();
+    if !(9..=73).contains(&size) || signature[0] != 0x30 || signature[1] as usize != size - 3 {
+        return false;
+    }
+
+    let len_r = signature[3] as usize;
+    if 5 + len_r >= size {
+        return false;
+    }
+
+    let len_s = signature[5 + len_r] as usize;
+    if len_r + len_s + 7 != size {
+        return false;
+    }
+
+    // R must be a positive integer without padding
+    if signature[2] != 0x02 || len_r == 0 || signature[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && signature[4] == 0x00 && signature[5] & 0x80 == 0 {
+        return false;
+    }
+
+    // S must be a positive integer without padding
+    if signature[len_r + 4] != 0x02 || len_s == 0 || signature[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && signature[len_r + 6] == 0x00 && signature[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+
+    true
+}
+
+fn has_low_s(der: &[u8]) -> bool {
+    match Signature::from_der_lax(der) {
+        Ok(signature) => {
+            let mut normalized = signature;
+            normalized.normalize_s();
+            normalized == signature
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::test_helpers;
+
+    #[test]
+    pub fn test_signature_with_padded_r_is_not_strict_der() {
+        let mut signature = vec![0x30, 0x07, 0x02, 0x02, 0x00, 0x01, 0x02, 0x01, 0x01, 0x01];
+        assert!(!is_valid_signature_encoding(&signature));
+
+        signature = vec![0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01];
+        assert!(is_valid_signature_encoding(&signature));
+    }
+
+    #[test]
+    pub fn test_hybrid_pubkeys_are_rejected_with_strict_encoding() {
+        let mut pubkey = vec![0x06];
+        pubkey.extend_from_slice(&[1; 64]);
+
+        assert!(check_pubkey_encoding(&pubkey, 0).is_ok());
+        assert_eq!(
+            check_pubkey_encoding(&pubkey, SCRIPT_VERIFY_STRICTENC),
+            Err(ScriptError::PubkeyType)
+        );
+    }
+
+    #[test]
+    pub fn test_check_sequence_compares_the_version_as_unsigned() {
+        let mut input = test_helpers::input([0; 36], vec![]);
+        input.sequence = 10;
+        let mut tx = test_helpers::transaction(vec![input], vec![test_helpers::output(1, vec![])]);
+        let spent_outputs = [test_helpers::output(1, vec![])];
+
+        tx.version = 1;
+        assert!(!TransactionSignatureChecker::new(&tx, 0, &spent_outputs).check_sequence(10));
+
+        for version in [2, 0xffffffff] {
+            tx.version = version;
+            let checker = TransactionSignatureChecker::new(&tx, 0, &spent_outputs);
+            assert!(checker.check_sequence(10));
+            assert!(!checker.check_sequence(11));
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::{
+    interpreter::MAX_PUBKEYS_PER_MULTISIG,
+    opcodes::{
+        OP_0, OP_1, OP_16, OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGVERIFY,
+    },
+    script_parser::{is_p2sh, is_push_only, parse_script, read_instruction},
+};
+
+/// Counts the signature operations of the script. When it is not accurate every multisig
+/// counts as the maximum of keys, otherwise as the amount of keys pushed before it.
+/// The count stops at the first instruction that can not be read
+pub fn count_sigops(script: &[u8], accurate: bool) -> u32 {
+    let mut count = 0;
+    let mut last_opcode = 0xff;
+    let mut position = 0;
+
+    while let Ok(instruction) = read_instruction(script, position) {
+        match instruction.opcode {
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => count += 1,
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                if accurate && (OP_1..=OP_16).contains(&last_opcode) {
+                    count += (last_opcode - OP_1 + 1) as u32;
+                } else {
+                    count += MAX_PUBKEYS_PER_MULTISIG as u32;
+                }
+            }
+            _ => {}
+        }
+        last_opcode = instruction.opcode;
+        position = instruction.end;
+    }
+
+    count
+}
+
+/// Counts the signature operations of the redeem script of a pay to script hash spend,
+/// the last push of the script sig
+pub fn p2sh_sigops(script_sig: &[u8], script_pubkey: &[u8]) -> u32 {
+    if !is_p2sh(script_pubkey) || !is_push_only(script_sig) {
+        return 0;
+    }
+
+    match parse_script(script_sig) {
+        Ok(instructions) => instructions
+            .last()
+            .map_or(0, |redeem_script| count_sigops(redeem_script.data, true)),
+        Err(_) => 0,
+    }
+}
+
+/// Returns the version and the program of a witness program: a push of the version and
+/// a push of 2 to 40 bytes
+pub fn witness_program(script: &[u8]) -> Option<(u8, &[u8])> {
+    if script.len() < 4 || script.len() > 42 {
+        return None;
+    }
+    if script[0] != OP_0 && !(OP_1..=OP_16).contains(&script[0]) {
+        return None;
+    }
+    if script[1] as usize + 2 != script.len() {
+        return None;
+    }
+
+    let version = match script[0] {
+        OP_0 => 0,
+        opcode => opcode - OP_1 + 1,
+    };
+    Some((version, &script[2..]))
+}
+
+/// Counts the signature operations of a segwit spend: one for a pay to witness public key hash
+/// and the ones of the witness script for a pay to witness script hash. Outputs of other
+/// versions have none. The witness program can be nested in a pay to script hash
+pub fn witness_sigops(script_sig: &[u8], script_pubkey: &[u8], witness: &[Vec<u8>]) -> u32 {
+    if let Some(program) = witness_program(script_pubkey) {
+        return program_sigops(program, witness);
+    }
+
+    if is_p2sh(script_pubkey) && is_push_only(script_sig) {
+        if let Ok(instructions) = parse_script(script_sig) {
+            if let Some(program) = instructions
+                .last()
+                .and_then(|redeem_script| witness_program(redeem_script.data))
+            {
+                return program_sigops(program, witness);
+            }
+        }
+    }
+
+    0
+}
+
+fn program_sigops((version, program): (u8, &[u8]), witness: &[Vec<u8>]) -> u32 {
+    match (version, program.len()) {
+        (0, 20) => 1,
+        (0, 32) => witness
+            .last()
+            .map_or(0, |witness_script| count_sigops(witness_script, true)),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{
+        opcodes::{OP_2, OP_3, OP_EQUAL, OP_HASH160},
+        script_parser::push_data,
+    };
+
+    #[test]
+    pub fn test_multisig_counts_its_keys_only_when_accurate() {
+        let multisig = vec![OP_2, OP_3, OP_CHECKMULTISIG, OP_CHECKSIG];
+
+        assert_eq!(count_sigops(&multisig, false), 21);
+        assert_eq!(count_sigops(&multisig, true), 4);
+    }
+
+    #[test]
+    pub fn test_sigops_of_p2sh_and_witness_spends() {
+        let redeem_script = vec![OP_2, OP_3, OP_CHECKMULTISIG];
+        let mut p2sh = vec![OP_HASH160, 0x14];
+        p2sh.extend_from_slice(&[0; 20]);
+        p2sh.push(OP_EQUAL);
+
+        assert_eq!(p2sh_sigops(&push_data(&redeem_script), &p2sh), 3);
+
+        let mut p2wsh = vec![OP_0, 0x20];
+        p2wsh.extend_from_slice(&[0; 32]);
+        assert_eq!(
+            witness_sigops(&[], &p2wsh, &[vec![], redeem_script.clone()]),
+            3
+        );
+
+        let mut p2wpkh = vec![OP_0, 0x14];
+        p2wpkh.extend_from_slice(&[0; 20]);
+        assert_eq!(witness_sigops(&push_data(&p2wpkh), &p2sh, &[]), 1);
+    }
+}
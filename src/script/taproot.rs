@@ -0,0 +1,171 @@
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use k256::{
+    elliptic_curve::{ff::PrimeField, group::Group, point::AffineCoordinates},
+    schnorr::VerifyingKey,
+    FieldBytes, ProjectivePoint, Scalar,
+};
+
+use super::opcodes::OP_1;
+use crate::helpers::auxiliar_functions::serialize_var_int;
+
+/// Leaf version of the scripts evaluated with the tapscript rules
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// Bits of the first byte of the control block that hold the leaf version
+pub const TAPROOT_LEAF_MASK: u8 = 0xfe;
+pub const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+pub const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// First byte of the last witness element when it is an annex
+pub const ANNEX_TAG: u8 = 0x50;
+/// Weight each executed signature check uses of the budget of a tapscript
+pub const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+/// Weight added to the size of the witness to get the budget of a tapscript
+pub const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// Hash of the message prefixed twice with the hash of the tag (BIP340)
+pub fn tagged_hash(tag: &str, message: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash);
+    engine.input(&tag_hash);
+    engine.input(message);
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Returns true if the script pubkey is a pay to taproot output: version 1 and a 32 bytes program
+pub fn is_p2tr(script_pubkey: &[u8]) -> bool {
+    script_pubkey.len() == 34 && script_pubkey[0] == OP_1 && script_pubkey[1] == 0x20
+}
+
+/// Hash of a leaf of the script tree
+pub fn tap_leaf_hash(leaf_version: u8, script: &[u8]) -> [u8; 32] {
+    let mut message = vec![leaf_version];
+    message.extend(serialize_var_int(script.len() as u64));
+    message.extend_from_slice(script);
+    tagged_hash("TapLeaf", &message)
+}
+
+/// Hash of a branch of the script tree. The children are sorted, so their order does not matter
+pub fn tap_branch_hash(first: &[u8; 32], second: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if first <= second {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    tagged_hash("TapBranch", &[left.as_slice(), right.as_slice()].concat())
+}
+
+/// Tweaks the x only internal key with the merkle root of the script tree, if there is one.
+/// Returns the x only output key and whether its y is odd, or None if the internal key is not
+/// a point of the curve
+pub fn tweak_public_key(
+    internal_key: &[u8],
+    merkle_root: Option<&[u8; 32]>,
+) -> Option<([u8; 32], bool)> {
+    let internal_key = VerifyingKey::from_bytes(internal_key).ok()?;
+
+    let mut message = internal_key.to_bytes().to_vec();
+    if let Some(merkle_root) = merkle_root {
+        message.extend_from_slice(merkle_root);
+    }
+    let tweak = tagged_hash("TapTweak", &message);
+    let tweak: Scalar = Option::from(Scalar::from_repr(FieldBytes::from(tweak)))?;
+
+    let output_key =
+        ProjectivePoint::from(*internal_key.as_affine()) + ProjectivePoint::GENERATOR * tweak;
+    if bool::from(output_key.is_identity()) {
+        return None;
+    }
+
+    let output_key = output_key.to_affine();
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&output_key.x());
+    Some((x, bool::from(output_key.y_is_odd())))
+}
+
+/// Returns true if the size of the control block is the base plus up to 128 hashes of the path
+pub fn is_valid_control_block_size(control_block: &[u8]) -> bool {
+    control_block.len() >= TAPROOT_CONTROL_BASE_SIZE
+        && (control_block.len() - TAPROOT_CONTROL_BASE_SIZE)
+            .is_multiple_of(TAPROOT_CONTROL_NODE_SIZE)
+        && (control_block.len() - TAPROOT_CONTROL_BASE_SIZE) / TAPROOT_CONTROL_NODE_SIZE
+            <= TAPROOT_CONTROL_MAX_NODE_COUNT
+}
+
+/// Verifies that the leaf is committed in the output key of the program: the control block holds
+/// the parity of the output key, the internal key and the path from the leaf to the merkle root
+pub fn verify_taproot_commitment(
+    control_block: &[u8],
+    program: &[u8],
+    leaf_hash: &[u8; 32],
+) -> bool {
+    if !is_valid_control_block_size(control_block) {
+        return false;
+    }
+
+    let mut node = *leaf_hash;
+    for sibling in control_block[TAPROOT_CONTROL_BASE_SIZE..].chunks(TAPROOT_CONTROL_NODE_SIZE) {
+        let mut sibling_hash = [0u8; 32];
+        sibling_hash.copy_from_slice(sibling);
+        node = tap_branch_hash(&node, &sibling_hash);
+    }
+
+    match tweak_public_key(&control_block[1..TAPROOT_CONTROL_BASE_SIZE], Some(&node)) {
+        Some((output_key, odd)) => {
+            output_key.as_slice() == program && odd == (control_block[0] & 1 == 1)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::auxiliar_functions::hex_to_bytes;
+
+    #[test]
+    pub fn test_key_path_output_key_of_bip341_vector() {
+        let internal_key =
+            hex_to_bytes("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d");
+        let (output_key, _) = tweak_public_key(&internal_key, None).unwrap();
+
+        assert_eq!(
+            output_key.to_vec(),
+            hex_to_bytes("53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343")
+        );
+    }
+
+    #[test]
+    pub fn test_commitment_of_a_leaf_in_a_tree_of_two() {
+        let internal_key = k256::schnorr::SigningKey::from_bytes(&[3; 32])
+            .unwrap()
+            .verifying_key()
+            .to_bytes();
+        let leaf = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &[OP_1]);
+        let sibling = tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &[OP_1, OP_1]);
+        let root = tap_branch_hash(&leaf, &sibling);
+        let (output_key, odd) = tweak_public_key(&internal_key, Some(&root)).unwrap();
+
+        let mut control_block = vec![TAPROOT_LEAF_TAPSCRIPT | odd as u8];
+        control_block.extend_from_slice(&internal_key);
+        control_block.extend_from_slice(&sibling);
+
+        assert!(verify_taproot_commitment(
+            &control_block,
+            &output_key,
+            &leaf
+        ));
+        assert!(!verify_taproot_commitment(
+            &control_block,
+            &output_key,
+            &sibling
+        ));
+
+        control_block[0] ^= 1;
+        assert!(!verify_taproot_commitment(
+            &control_block,
+            &output_key,
+            &leaf
+        ));
+    }
+}
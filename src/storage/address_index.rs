@@ -0,0 +1,412 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use bitcoin_hashes::{sha256, Hash};
+
+use crate::components::{block::Block, chainstate::OutPoint, header_chain::HeaderChain};
+
+use super::{
+    block_store::BlockStore,
+    record_file::{append_records, read_records},
+};
+
+/// Name of the file, inside the data directory, where the address index is appended
+pub const ADDRESS_INDEX_FILE: &str = "addrindex.dat";
+
+/// Size of an entry: the kind, the script hash, the outpoint, the value, the txid and the block hash
+const ENTRY_SIZE: usize = 141;
+
+/// Entry of an output that pays to the script
+const ENTRY_FUNDING: u8 = 0;
+/// Entry of an input that spends an output of the script
+const ENTRY_SPENDING: u8 = 1;
+/// Entry written after the others of a connected block
+const ENTRY_BLOCK_CONNECTED: u8 = 2;
+/// Entry that removes the entries of a block that left the best chain
+const ENTRY_BLOCK_DISCONNECTED: u8 = 3;
+
+/// Output that paid to a script, or input that spent it. For a funding the txid is the one of
+/// the outpoint, for a spending it is the txid of the transaction of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    pub txid: [u8; 32],
+    pub block_hash: Vec<u8>,
+}
+
+/// #TDA ScriptHistory
+/// Outputs that paid to a script and the inputs that spent them, in the order of the chain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptHistory {
+    pub funded: Vec<HistoryEntry>,
+    pub spent: Vec<HistoryEntry>,
+}
+
+impl ScriptHistory {
+    /// Outputs of the script that were not spent
+    pub fn unspent(&self) -> Vec<&HistoryEntry> {
+        let spent: HashSet<&OutPoint> = self.spent.iter().map(|entry| &entry.outpoint).collect();
+        self.funded
+            .iter()
+            .filter(|entry| !spent.contains(&entry.outpoint))
+            .collect()
+    }
+
+    #[allow(dead_code)]
+    pub fn balance(&self) -> u64 {
+        self.unspent().iter().map(|entry| entry.value).sum()
+    }
+
+    /// Transactions that paid to the script or spent from it, with the hash of their block
+    pub fn transactions(&self) -> Vec<([u8; 32], Vec<u8>)> {
+        let mut seen = HashSet::new();
+        self.funded
+            .iter()
+            .chain(self.spent.iter())
+            .filter(|entry| seen.insert(entry.txid))
+            .map(|entry| (entry.txid, entry.block_hash.clone()))
+            .collect()
+    }
+}
+
+/// #TDA AddressIndex
+/// Optional index from the hash of each script pubkey to the outputs that paid to it and the inputs
+/// that spent them. Only the spends of outputs of indexed blocks are known, so the blocks are
+/// indexed in the order of the chain
+#[derive(Debug)]
+pub struct AddressIndex {
+    path: PathBuf,
+    histories: HashMap<[u8; 32], ScriptHistory>,
+    funded_outputs: HashMap<OutPoint, ([u8; 32], u64)>,
+    indexed_blocks: HashSet<Vec<u8>>,
+}
+
+/// Hash of the script pubkey used as the key of the index
+pub fn script_hash(script_pubkey: &[u8]) -> [u8; 32] {
+    sha256::Hash::hash(script_pubkey).into_inner()
+}
+
+impl AddressIndex {
+    /// Opens the index of the data directory, replaying its records. Every block is appended in
+    /// a single checksummed record, so a block that was not completely written is skipped
+    pub fn open(data_dir: &str) -> Result<AddressIndex, Error> {
+        fs::create_dir_all(data_dir)?;
+
+        let mut address_index = AddressIndex {
+            path: Path::new(data_dir).join(ADDRESS_INDEX_FILE),
+            histories: HashMap::new(),
+            funded_outputs: HashMap::new(),
+            indexed_blocks: HashSet::new(),
+        };
+
+        for record in read_records(&address_index.path)? {
+            if record.payload.len() % ENTRY_SIZE != 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Invalid address index record",
+                ));
+            }
+
+            for entry in record.payload.chunks_exact(ENTRY_SIZE) {
+                let (kind, script_hash, entry) = parse_entry(entry);
+                match kind {
+                    ENTRY_FUNDING | ENTRY_SPENDING => address_index.apply(kind, script_hash, entry),
+                    ENTRY_BLOCK_CONNECTED => {
+                        address_index.indexed_blocks.insert(entry.block_hash);
+                    }
+                    ENTRY_BLOCK_DISCONNECTED => address_index.remove_block(&entry.block_hash),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(address_index)
+    }
+
+    /// Indexes the stored blocks of the best chain that are not in the index yet, in height order.
+    /// Returns the amount of blocks indexed
+    pub fn sync(
+        &mut self,
+        header_chain: &HeaderChain,
+        block_store: &BlockStore,
+    ) -> Result<usize, Error> {
+        let mut indexed = 0;
+        for height in 1..=header_chain.best_height() {
+            let hash = match header_chain.hash_at(height) {
+                Some(hash) => hash,
+                None => break,
+            };
+            if self.indexed_blocks.contains(hash) {
+                continue;
+            }
+            if let Some(block) = block_store.read_block(hash)? {
+                self.index_block(&block)?;
+                indexed += 1;
+            }
+        }
+
+        Ok(indexed)
+    }
+
+    /// Appends the outputs and the spends of the block to the index, if it was not indexed before
+    pub fn index_block(&mut self, block: &Block) -> Result<(), Error> {
+        let block_hash = block.header.calculate_hash();
+        if self.indexed_blocks.contains(&block_hash) {
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        // Outputs of the block, that can be spent by its next transactions
+        let mut block_outputs: HashMap<OutPoint, ([u8; 32], u64)> = HashMap::new();
+        for tx in &block.txns {
+            let txid = tx.hash.into_inner();
+
+            for input in &tx.inputs {
+                let outpoint = OutPoint::from_input(input);
+                let funded = self
+                    .funded_outputs
+                    .get(&outpoint)
+                    .or_else(|| block_outputs.get(&outpoint));
+                if let Some((script_hash, value)) = funded {
+                    let entry = HistoryEntry {
+                        outpoint,
+                        value: *value,
+                        txid,
+                        block_hash: block_hash.clone(),
+                    };
+                    entries.push((ENTRY_SPENDING, *script_hash, entry));
+                }
+            }
+
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let entry = HistoryEntry {
+                    outpoint: OutPoint::new(txid, index as u32),
+                    value: output.value,
+                    txid,
+                    block_hash: block_hash.clone(),
+                };
+                let script_hash = script_hash(&output.script_pubkey);
+                block_outputs.insert(entry.outpoint.clone(), (script_hash, entry.value));
+                entries.push((ENTRY_FUNDING, script_hash, entry));
+            }
+        }
+
+        let mut record = Vec::with_capacity((entries.len() + 1) * ENTRY_SIZE);
+        for (kind, script_hash, entry) in &entries {
+            record.extend(serialize_entry(*kind, script_hash, entry));
+        }
+        record.extend(block_entry(ENTRY_BLOCK_CONNECTED, &block_hash));
+        append_records(&self.path, &[record])?;
+
+        for (kind, script_hash, entry) in entries {
+            self.apply(kind, script_hash, entry);
+        }
+        self.indexed_blocks.insert(block_hash);
+
+        Ok(())
+    }
+
+    /// Removes the outputs and the spends of a block that left the best chain
+    pub fn disconnect_block(&mut self, block: &Block) -> Result<(), Error> {
+        let block_hash = block.header.calculate_hash();
+        if !self.indexed_blocks.contains(&block_hash) {
+            return Ok(());
+        }
+
+        append_records(
+            &self.path,
+            &[block_entry(ENTRY_BLOCK_DISCONNECTED, &block_hash)],
+        )?;
+        self.remove_block(&block_hash);
+
+        Ok(())
+    }
+
+    /// History of the script pubkey, empty if nothing paid to it
+    pub fn history(&self, script_pubkey: &[u8]) -> ScriptHistory {
+        self.histories
+            .get(&script_hash(script_pubkey))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn apply(&mut self, kind: u8, script_hash: [u8; 32], entry: HistoryEntry) {
+        if kind == ENTRY_FUNDING {
+            self.funded_outputs
+                .insert(entry.outpoint.clone(), (script_hash, entry.value));
+        }
+
+        let history = self.histories.entry(script_hash).or_default();
+        match kind {
+            ENTRY_FUNDING => history.funded.push(entry),
+            _ => history.spent.push(entry),
+        }
+    }
+
+    fn remove_block(&mut self, block_hash: &[u8]) {
+        self.indexed_blocks.remove(block_hash);
+
+        for history in self.histories.values_mut() {
+            for entry in &history.funded {
+                if entry.block_hash == block_hash {
+                    self.funded_outputs.remove(&entry.outpoint);
+                }
+            }
+            history
+                .funded
+                .retain(|entry| entry.block_hash != block_hash);
+            history.spent.retain(|entry| entry.block_hash != block_hash);
+        }
+        self.histories
+            .retain(|_, history| !history.funded.is_empty() || !history.spent.is_empty());
+    }
+}
+
+fn serialize_entry(kind: u8, script_hash: &[u8; 32], entry: &HistoryEntry) -> Vec<u8> {
+    let mut data = Vec::with_capacity(ENTRY_SIZE);
+    data.push(kind);
+    data.extend_from_slice(script_hash);
+    data.extend_from_slice(&entry.outpoint.txid);
+    data.extend_from_slice(&entry.outpoint.index.to_le_bytes());
+    data.extend_from_slice(&entry.value.to_le_bytes());
+    data.extend_from_slice(&entry.txid);
+    data.extend_from_slice(&entry.block_hash);
+    data
+}
+
+fn block_entry(kind: u8, block_hash: &[u8]) -> Vec<u8> {
+    let entry = HistoryEntry {
+        outpoint: OutPoint::new([0; 32], 0),
+        value: 0,
+        txid: [0; 32],
+        block_hash: block_hash.to_vec(),
+    };
+    serialize_entry(kind, &[0; 32], &entry)
+}
+
+fn parse_entry(data: &[u8]) -> (u8, [u8; 32], HistoryEntry) {
+    let mut script_hash = [0u8; 32];
+    script_hash.copy_from_slice(&data[1..33]);
+    let mut outpoint_txid = [0u8; 32];
+    outpoint_txid.copy_from_slice(&data[33..65]);
+    let mut txid = [0u8; 32];
+    txid.copy_from_slice(&data[77..109]);
+
+    let entry = HistoryEntry {
+        outpoint: OutPoint::new(
+            outpoint_txid,
+            u32::from_le_bytes(data[65..69].try_into().unwrap_or_default()),
+        ),
+        value: u64::from_le_bytes(data[69..77].try_into().unwrap_or_default()),
+        txid,
+        block_hash: data[109..141].to_vec(),
+    };
+
+    (data[0], script_hash, entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{block_header::BlockHeader, transaction::Transaction};
+    use crate::helpers::test_helpers;
+    use crate::storage::record_file::recover_data_dir;
+
+    fn transaction(previous_output: [u8; 36], outputs: &[(u64, u8)]) -> Transaction {
+        test_helpers::transaction(
+            vec![test_helpers::input(previous_output, vec![])],
+            outputs
+                .iter()
+                .map(|(value, script)| test_helpers::output(*value, vec![*script]))
+                .collect(),
+        )
+    }
+
+    fn outpoint_of(tx: &Transaction, index: u32) -> [u8; 36] {
+        let mut outpoint = [0u8; 36];
+        outpoint[..32].copy_from_slice(&tx.hash[..]);
+        outpoint[32..].copy_from_slice(&index.to_le_bytes());
+        outpoint
+    }
+
+    fn block(nonce: u32, txns: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader::new(1, vec![0; 32], vec![0; 32], 0, 0x207fffff, nonce),
+            txns.len(),
+            txns,
+        )
+    }
+
+    #[test]
+    pub fn test_history_and_balance_of_a_script() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_address_index_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let funding = transaction([0xff; 36], &[(5000, 0x51), (300, 0x52)]);
+        let spending = transaction(outpoint_of(&funding, 0), &[(4000, 0x52)]);
+        let first = block(1, vec![funding.clone()]);
+        let second = block(2, vec![spending.clone()]);
+
+        let mut address_index = AddressIndex::open(data_dir).unwrap();
+        address_index.index_block(&first).unwrap();
+        address_index.index_block(&second).unwrap();
+
+        let history = address_index.history(&[0x51]);
+        assert_eq!(history.funded.len(), 1);
+        assert_eq!(history.spent[0].txid, spending.hash.into_inner());
+        assert_eq!(history.balance(), 0);
+        assert_eq!(history.transactions().len(), 2);
+        assert_eq!(address_index.history(&[0x52]).balance(), 4300);
+
+        let reopened = AddressIndex::open(data_dir).unwrap();
+        assert_eq!(reopened.history(&[0x51]), history);
+
+        address_index.disconnect_block(&second).unwrap();
+        assert_eq!(address_index.history(&[0x51]).balance(), 5000);
+        assert_eq!(address_index.history(&[0x52]).balance(), 300);
+
+        let reopened = AddressIndex::open(data_dir).unwrap();
+        assert_eq!(reopened.history(&[0x51]).balance(), 5000);
+        assert!(reopened.history(&[0x53]).funded.is_empty());
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_an_incomplete_block_is_discarded() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_address_index_torn_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let funding = transaction([0xff; 36], &[(5000, 0x51)]);
+        let mut address_index = AddressIndex::open(data_dir).unwrap();
+        address_index.index_block(&block(1, vec![funding])).unwrap();
+
+        let path = Path::new(data_dir).join(ADDRESS_INDEX_FILE);
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() - ENTRY_SIZE]).unwrap();
+
+        assert_eq!(
+            AddressIndex::open(data_dir)
+                .unwrap()
+                .history(&[0x51])
+                .balance(),
+            0
+        );
+        recover_data_dir(data_dir).unwrap();
+        let mut reopened = AddressIndex::open(data_dir).unwrap();
+        assert_eq!(reopened.history(&[0x51]).balance(), 0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        let funding = transaction([0xff; 36], &[(5000, 0x51)]);
+        reopened.index_block(&block(1, vec![funding])).unwrap();
+        let reopened = AddressIndex::open(data_dir).unwrap();
+        assert_eq!(reopened.history(&[0x51]).balance(), 5000);
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::Error;
+
+use crate::{
+    components::{
+        block::Block, block_header::BlockHeader, header_chain::HeaderChain,
+        transaction::Transaction,
+    },
+    configuration::chain_params::chain_params,
+};
+
+use super::{block_store::BlockStore, header_store::read_headers, tx_index::TxIndex};
+
+/// Maximum amount of headers answered to a getheaders message
+pub const MAX_HEADERS_RESULTS: usize = 2000;
+
+/// #TDA BlockIndex
+/// Index of the stored blocks by hash and by height of the best chain. Only the headers are
+/// kept in memory, the blocks are read from the block store when they are asked.
+/// The transactions are found through the transaction index when it is enabled
+#[derive(Debug)]
+pub struct BlockIndex {
+    header_chain: HeaderChain,
+    block_store: BlockStore,
+    tx_index: Option<TxIndex>,
+}
+
+impl BlockIndex {
+    pub fn new(header_chain: HeaderChain, block_store: BlockStore) -> Self {
+        BlockIndex {
+            header_chain,
+            block_store,
+            tx_index: None,
+        }
+    }
+
+    /// Opens the transaction index of the data directory and adds the stored blocks of the
+    /// best chain it is missing. Returns the amount of blocks that were indexed
+    pub fn enable_tx_index(&mut self, data_dir: &str) -> Result<usize, Error> {
+        let mut tx_index = TxIndex::open(data_dir)?;
+        let indexed = tx_index.sync(&self.header_chain, &self.block_store)?;
+        self.tx_index = Some(tx_index);
+        Ok(indexed)
+    }
+
+    /// Loads the headers and opens the block store of the data directory
+    pub fn load(data_dir: &str) -> Result<BlockIndex, Error> {
+        let mut header_chain = HeaderChain::new(chain_params().clone());
+        for header in read_headers(data_dir)? {
+            let _ = header_chain.add_header(header);
+        }
+
+        Ok(BlockIndex::new(header_chain, BlockStore::open(data_dir)?))
+    }
+
+    pub fn best_height(&self) -> u32 {
+        self.header_chain.best_height()
+    }
+
+    /// Amount of blocks in the store
+    pub fn stored_blocks(&self) -> usize {
+        self.block_store.len()
+    }
+
+    /// Returns the height of the block if it is part of the best chain
+    pub fn height_of(&self, hash: &[u8]) -> Option<u32> {
+        if self.header_chain.is_in_best_chain(hash) {
+            self.header_chain.height_of(hash)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the block with the hash from the store, if it was stored
+    pub fn block_by_hash(&self, hash: &[u8]) -> Result<Option<Block>, Error> {
+        self.block_store.read_block(hash)
+    }
+
+    /// Reads the block of the best chain at the height from the store, if it was stored
+    pub fn block_at_height(&self, height: u32) -> Result<Option<Block>, Error> {
+        match self.header_chain.hash_at(height) {
+            Some(hash) => self.block_store.read_block(hash),
+            None => Ok(None),
+        }
+    }
+
+    /// Headers of the best chain after the first hash of the locator that is in it, or after the
+    /// genesis if there is none. They end at the stop hash or after the maximum of a headers message
+    pub fn headers_after_locator(
+        &self,
+        locator_hashes: &[Vec<u8>],
+        hash_stop: &[u8],
+    ) -> Vec<BlockHeader> {
+        let start = locator_hashes
+            .iter()
+            .find_map(|hash| self.height_of(hash))
+            .unwrap_or(0);
+
+        let mut headers = Vec::new();
+        for height in start + 1..=self.best_height() {
+            let header = match self.header_chain.header_at(height) {
+                Some(header) => header,
+                None => break,
+            };
+            headers.push(header.clone());
+
+            if headers.len() == MAX_HEADERS_RESULTS || header.calculate_hash() == hash_stop {
+                break;
+            }
+        }
+
+        headers
+    }
+
+    /// Looks for the transaction in the stored blocks. With the transaction index only its block
+    /// is read, otherwise the blocks of the best chain are read one by one
+    pub fn find_transaction(&self, hash: &[u8]) -> Result<Option<Transaction>, Error> {
+        if let Some(tx_index) = &self.tx_index {
+            let location = match tx_index.get(hash) {
+                Some(location) => location,
+                None => return Ok(None),
+            };
+            return Ok(self
+                .block_store
+                .read_block(&location.block_hash)?
+                .and_then(|block| block.txns.into_iter().nth(location.position as usize)));
+        }
+
+        for height in 1..=self.best_height() {
+            if let Some(block) = self.block_at_height(height)? {
+                if let Some(tx) = block.txns.into_iter().find(|tx| tx.hash[..] == *hash) {
+                    return Ok(Some(tx));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::configuration::chain_params::{ChainParams, Network};
+    use crate::helpers::test_helpers;
+
+    fn child(parent: &BlockHeader) -> BlockHeader {
+        test_helpers::mine(BlockHeader::new(
+            1,
+            parent.calculate_hash(),
+            vec![1; 32],
+            parent.timestamp + 600,
+            0x207fffff,
+            1,
+        ))
+    }
+
+    #[test]
+    pub fn test_blocks_are_found_by_hash_and_by_height() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_block_index_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let params = ChainParams::new(Network::Regtest);
+        let mut header_chain = HeaderChain::new(params.clone());
+        let first = child(&params.genesis_header);
+        let second = child(&first);
+        let third = child(&second);
+        for header in [&first, &second, &third] {
+            header_chain.add_header(header.clone()).unwrap();
+        }
+
+        let mut block_store = BlockStore::open(data_dir).unwrap();
+        let block = Block::new(second.clone(), 0, vec![]);
+        block_store.store_block(&block).unwrap();
+        let index = BlockIndex::new(header_chain, block_store);
+
+        assert_eq!(index.height_of(&second.calculate_hash()), Some(2));
+        let read = index.block_at_height(2).unwrap().unwrap();
+        assert_eq!(read.header.calculate_hash(), second.calculate_hash());
+        assert!(index
+            .block_by_hash(&second.calculate_hash())
+            .unwrap()
+            .is_some());
+        assert!(index.block_at_height(1).unwrap().is_none());
+        assert!(index.block_at_height(4).unwrap().is_none());
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_transactions_are_found_with_and_without_the_tx_index() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_block_index_tx_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let params = ChainParams::new(Network::Regtest);
+        let mut header_chain = HeaderChain::new(params.clone());
+        let mut tx = test_helpers::transaction(vec![], vec![]);
+        tx.lock_time = 7;
+        tx.hash = tx.compute_txid();
+        let header = child(&params.genesis_header);
+        header_chain.add_header(header.clone()).unwrap();
+
+        let mut block_store = BlockStore::open(data_dir).unwrap();
+        block_store
+            .store_block(&Block::new(header, 1, vec![tx.clone()]))
+            .unwrap();
+        let mut index = BlockIndex::new(header_chain, block_store);
+
+        let found = index.find_transaction(&tx.hash[..]).unwrap().unwrap();
+        assert_eq!(found.lock_time, 7);
+
+        assert_eq!(index.enable_tx_index(data_dir).unwrap(), 1);
+        let found = index.find_transaction(&tx.hash[..]).unwrap().unwrap();
+        assert_eq!(found.compute_txid(), tx.hash);
+        assert!(index.find_transaction(&[0; 32]).unwrap().is_none());
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_headers_start_after_the_locator_and_end_at_the_stop_hash() {
+        let params = ChainParams::new(Network::Regtest);
+        let mut header_chain = HeaderChain::new(params.clone());
+        let first = child(&params.genesis_header);
+        let second = child(&first);
+        let third = child(&second);
+        for header in [&first, &second, &third] {
+            header_chain.add_header(header.clone()).unwrap();
+        }
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_block_index_headers_test");
+        let index = BlockIndex::new(
+            header_chain,
+            BlockStore::open(data_dir.to_str().unwrap()).unwrap(),
+        );
+
+        let hashes = |headers: Vec<BlockHeader>| -> Vec<Vec<u8>> {
+            headers
+                .iter()
+                .map(|header| header.calculate_hash())
+                .collect()
+        };
+
+        assert_eq!(
+            hashes(index.headers_after_locator(&[vec![7; 32]], &[0; 32])),
+            hashes(vec![first.clone(), second.clone(), third.clone()])
+        );
+        assert_eq!(
+            hashes(index.headers_after_locator(
+                &[vec![7; 32], first.calculate_hash()],
+                &second.calculate_hash()
+            )),
+            hashes(vec![second])
+        );
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
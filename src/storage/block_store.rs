@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    components::block::Block, testnet_protocol::messages::network_message::deserialize_block,
+};
+
+use super::record_file::{
+    append_records, read_record_at, read_records, recover_records, rewrite_records, truncate_file,
+    RECORD_HEADER_SIZE,
+};
+
+/// Name of the file, inside the data directory, where the blocks are appended
+pub const BLOCKS_FILE: &str = "blocks.dat";
+
+/// Name of the file, inside the data directory, with the position of each stored block
+pub const BLOCK_INDEX_FILE: &str = "blocks.idx";
+
+/// Size of an entry of the index: the hash, the offset and the length of the block
+const INDEX_ENTRY_SIZE: usize = 44;
+
+/// Position of a block in the blocks file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockLocation {
+    pub offset: u64,
+    pub length: u32,
+}
+
+/// #TDA BlockStore
+/// Append only store of the blocks in their wire serialization, so the witnesses are kept.
+/// Every block is written in a checksummed record, and the index file maps the hash of the
+/// block to the offset of that record. A block is stored once its index entry is on disk
+#[derive(Debug)]
+pub struct BlockStore {
+    blocks_path: PathBuf,
+    index_path: PathBuf,
+    locations: HashMap<Vec<u8>, BlockLocation>,
+    hashes: Vec<Vec<u8>>,
+}
+
+impl BlockStore {
+    /// Opens the store of the data directory, reading its index. If there are no files yet
+    /// the store is empty. The files are not changed, so the store can be opened while the node
+    /// appends blocks: the entries that were not completely written and the ones that point past
+    /// the end of the blocks file are skipped
+    pub fn open(data_dir: &str) -> Result<BlockStore, Error> {
+        let mut store = BlockStore::empty(data_dir)?;
+
+        // The blocks are appended before their entries, so the blocks of the entries read are
+        // inside the size read after them
+        let entries = read_records(&store.index_path)?;
+        let blocks_size = file_size(&store.blocks_path)?;
+        for entry in entries {
+            store.add_entry(&entry.payload, blocks_size);
+        }
+
+        Ok(store)
+    }
+
+    /// Discards what a crash left half written in the store of the data directory: the torn
+    /// entries of the index, the entries that point past the end of the blocks file and the blocks
+    /// after the last indexed one. It must run before the store is opened, while nothing else
+    /// reads or writes it
+    pub fn recover(data_dir: &str) -> Result<(), Error> {
+        let mut store = BlockStore::empty(data_dir)?;
+
+        let entries = recover_records(&store.index_path)?;
+        let blocks_size = file_size(&store.blocks_path)?;
+
+        let mut valid_entries = Vec::with_capacity(entries.len());
+        let mut blocks_end = 0;
+        for entry in &entries {
+            if let Some(end) = store.add_entry(&entry.payload, blocks_size) {
+                blocks_end = blocks_end.max(end);
+                valid_entries.push(entry.payload.clone());
+            }
+        }
+
+        // The discarded entries would point to the blocks appended after recovering
+        if valid_entries.len() < entries.len() {
+            println!(
+                "Se descartan {} entradas del indice de bloques",
+                entries.len() - valid_entries.len()
+            );
+            rewrite_records(&store.index_path, &valid_entries)?;
+        }
+        if blocks_size > blocks_end {
+            println!(
+                "Se descartan {} bytes de bloques sin indexar",
+                blocks_size - blocks_end
+            );
+            truncate_file(&store.blocks_path, blocks_end)?;
+        }
+
+        Ok(())
+    }
+
+    fn empty(data_dir: &str) -> Result<BlockStore, Error> {
+        fs::create_dir_all(data_dir)?;
+
+        Ok(BlockStore {
+            blocks_path: Path::new(data_dir).join(BLOCKS_FILE),
+            index_path: Path::new(data_dir).join(BLOCK_INDEX_FILE),
+            locations: HashMap::new(),
+            hashes: Vec::new(),
+        })
+    }
+
+    /// Adds the block of the entry of the index if the entry is valid, its block is inside the
+    /// blocks file and it was not stored before. Returns the end of the block in the file
+    fn add_entry(&mut self, entry: &[u8], blocks_size: u64) -> Option<u64> {
+        let (hash, location) = parse_index_entry(entry)?;
+
+        let end = location.offset + RECORD_HEADER_SIZE + location.length as u64;
+        if end > blocks_size || self.locations.contains_key(&hash) {
+            return None;
+        }
+        self.locations.insert(hash.clone(), location);
+        self.hashes.push(hash);
+
+        Some(end)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.locations.contains_key(hash)
+    }
+
+    /// Hashes of the stored blocks, in the order they were stored
+    #[allow(dead_code)]
+    pub fn hashes(&self) -> &[Vec<u8>] {
+        &self.hashes
+    }
+
+    #[allow(dead_code)]
+    pub fn location(&self, hash: &[u8]) -> Option<BlockLocation> {
+        self.locations.get(hash).copied()
+    }
+
+    /// Appends the block to the blocks file and then its position to the index, waiting for each
+    /// to be on disk. Returns false if the block was already stored
+    pub fn store_block(&mut self, block: &Block) -> Result<bool, Error> {
+        let hash = block.header.calculate_hash();
+        if self.contains(&hash) {
+            return Ok(false);
+        }
+
+        let data = block.serialize();
+        let length = u32::try_from(data.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "Block too big to be stored"))?;
+        let offset = append_records(&self.blocks_path, &[data])?;
+
+        let mut entry = Vec::with_capacity(INDEX_ENTRY_SIZE);
+        entry.extend_from_slice(&hash);
+        entry.extend_from_slice(&offset.to_le_bytes());
+        entry.extend_from_slice(&length.to_le_bytes());
+        append_records(&self.index_path, &[entry])?;
+
+        self.locations
+            .insert(hash.clone(), BlockLocation { offset, length });
+        self.hashes.push(hash);
+
+        Ok(true)
+    }
+
+    /// Reads the block with the hash from the blocks file, if it was stored
+    pub fn read_block(&self, hash: &[u8]) -> Result<Option<Block>, Error> {
+        let location = match self.locations.get(hash) {
+            Some(location) => *location,
+            None => return Ok(None),
+        };
+
+        let mut blocks_file = File::open(&self.blocks_path)?;
+        read_block_at(&mut blocks_file, location).map(Some)
+    }
+
+    /// Reads every stored block, in the order they were stored
+    #[allow(dead_code)]
+    pub fn read_blocks(&self) -> Result<Vec<Block>, Error> {
+        if self.hashes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut blocks_file = File::open(&self.blocks_path)?;
+        self.hashes
+            .iter()
+            .map(|hash| read_block_at(&mut blocks_file, self.locations[hash]))
+            .collect()
+    }
+}
+
+/// Size of the file, zero if it does not exist
+fn file_size(path: &Path) -> Result<u64, Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the hash and the location of the block of an entry of the index
+fn parse_index_entry(entry: &[u8]) -> Option<(Vec<u8>, BlockLocation)> {
+    if entry.len() != INDEX_ENTRY_SIZE {
+        return None;
+    }
+
+    let location = BlockLocation {
+        offset: u64::from_le_bytes(entry[32..40].try_into().ok()?),
+        length: u32::from_le_bytes(entry[40..44].try_into().ok()?),
+    };
+    Some((entry[..32].to_vec(), location))
+}
+
+/// Reads the record of the location, checking its checksum and length before parsing the block
+fn read_block_at(blocks_file: &mut File, location: BlockLocation) -> Result<Block, Error> {
+    let data = read_record_at(blocks_file, location.offset)?;
+    if data.len() != location.length as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "The block record does not match the index",
+        ));
+    }
+
+    deserialize_block(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::block_header::BlockHeader;
+    use crate::helpers::test_helpers;
+
+    fn block(nonce: u32, witness: Vec<Vec<u8>>) -> Block {
+        let mut input = test_helpers::input([nonce as u8; 36], vec![0x51]);
+        input.sequence = 0xfffffffe;
+        input.witness = witness;
+        let mut tx =
+            test_helpers::transaction(vec![input], vec![test_helpers::output(5000, vec![0x51])]);
+        tx.version = 2;
+        tx.hash = tx.compute_txid();
+        let mut txid = tx.hash.to_vec();
+        txid.reverse();
+        tx.txid = txid;
+
+        Block::new(
+            BlockHeader::new(1, vec![0; 32], vec![0; 32], 0, 0x207fffff, nonce),
+            1,
+            vec![tx],
+        )
+    }
+
+    #[test]
+    pub fn test_blocks_are_the_same_after_storing_and_reopening() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_block_store_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        let with_witness = block(1, vec![vec![], vec![1, 2, 3]]);
+        let without_witness = block(2, vec![]);
+
+        let mut store = BlockStore::open(data_dir).unwrap();
+        assert!(store.store_block(&with_witness).unwrap());
+        assert!(store.store_block(&without_witness).unwrap());
+        assert!(!store.store_block(&with_witness).unwrap());
+
+        let store = BlockStore::open(data_dir).unwrap();
+        assert_eq!(store.len(), 2);
+
+        let read = store
+            .read_block(&with_witness.header.calculate_hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.serialize(), with_witness.serialize());
+        assert_eq!(read.txns[0].inputs[0].witness, vec![vec![], vec![1, 2, 3]]);
+        assert_eq!(read.txns[0].txid, with_witness.txns[0].txid);
+
+        let blocks = store.read_blocks().unwrap();
+        assert_eq!(blocks[1].serialize(), without_witness.serialize());
+        assert!(store.read_block(&[0; 32]).unwrap().is_none());
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_blocks_written_without_their_index_entry_are_discarded() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_block_store_recovery_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        let first = block(1, vec![]);
+        let second = block(2, vec![]);
+
+        let mut store = BlockStore::open(data_dir).unwrap();
+        store.store_block(&first).unwrap();
+        let blocks_path = Path::new(data_dir).join(BLOCKS_FILE);
+        let stored_size = fs::metadata(&blocks_path).unwrap().len();
+
+        // The process dies after appending the block and before appending its index entry
+        append_records(&blocks_path, &[second.serialize()]).unwrap();
+
+        // Opening the store does not change it, the blocks are discarded by the recovery
+        let store = BlockStore::open(data_dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert!(fs::metadata(&blocks_path).unwrap().len() > stored_size);
+
+        BlockStore::recover(data_dir).unwrap();
+        let mut store = BlockStore::open(data_dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(fs::metadata(&blocks_path).unwrap().len(), stored_size);
+
+        assert!(store.store_block(&second).unwrap());
+        let store = BlockStore::open(data_dir).unwrap();
+        let read = store
+            .read_block(&second.header.calculate_hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.serialize(), second.serialize());
+
+        // A corrupted block is an error instead of a different block
+        let mut data = fs::read(&blocks_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&blocks_path, data).unwrap();
+        assert!(store.read_block(&second.header.calculate_hash()).is_err());
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    components::{chainstate::Chainstate, utxo_struct::Utxo, wallet::Wallet},
+    testnet_protocol::messages::{
+        message_parsers::parse_transaction, network_message::PayloadReader,
+    },
+};
+
+use super::record_file::{read_records, rewrite_records};
+
+/// Name of the file, inside the data directory, with the last saved chainstate
+pub const CHAINSTATE_FILE: &str = "chainstate.dat";
+
+/// Name of the file, inside the data directory, with the utxos and the history of the wallets
+pub const WALLETS_FILE: &str = "wallets.dat";
+
+/// Saves the chainstate in a checksummed record that replaces the file atomically, so the file
+/// always has a complete chainstate at some tip
+pub fn save_chainstate(data_dir: &str, chainstate: &Chainstate) -> Result<(), Error> {
+    fs::create_dir_all(data_dir)?;
+    rewrite_records(
+        &Path::new(data_dir).join(CHAINSTATE_FILE),
+        &[chainstate.serialize()],
+    )
+}
+
+/// Reads the chainstate saved in the data directory, if one was saved
+pub fn load_chainstate(data_dir: &str) -> Result<Option<Chainstate>, Error> {
+    match read_records(&Path::new(data_dir).join(CHAINSTATE_FILE))?.first() {
+        Some(record) => Chainstate::deserialize(&record.payload).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Saves the utxos and the history of the wallets after the tip of the chainstate they were
+/// updated with. The tip is the commit marker: the wallets are only restored on top of a
+/// chainstate with the same tip. The keys of the wallets are not saved
+pub fn save_wallets(
+    data_dir: &str,
+    tip_hash: &[u8],
+    wallets: &HashMap<String, Wallet>,
+) -> Result<(), Error> {
+    let mut data = tip_hash.to_vec();
+    data.extend_from_slice(&(wallets.len() as u32).to_le_bytes());
+    for wallet in wallets.values() {
+        serialize_string(&mut data, &wallet.address);
+        serialize_utxos(&mut data, &wallet.utxo_set.utxos);
+        serialize_utxos(&mut data, &wallet.utxos_vueltos);
+
+        data.extend_from_slice(&(wallet.transactions_history.len() as u32).to_le_bytes());
+        for transaction in &wallet.transactions_history {
+            let bytes = transaction.serialize();
+            data.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            data.extend(bytes);
+        }
+    }
+
+    fs::create_dir_all(data_dir)?;
+    rewrite_records(&Path::new(data_dir).join(WALLETS_FILE), &[data])
+}
+
+/// Restores the utxos and the history of the wallet from the ones saved with the tip. Returns false
+/// if the wallet was not saved, or was saved with another tip, as when the process died between
+/// saving the chainstate and the wallets
+pub fn restore_wallet(data_dir: &str, tip_hash: &[u8], wallet: &mut Wallet) -> Result<bool, Error> {
+    let records = read_records(&Path::new(data_dir).join(WALLETS_FILE))?;
+    let data = match records.first() {
+        Some(record) => &record.payload,
+        None => return Ok(false),
+    };
+
+    let mut reader = PayloadReader::new(data);
+    if reader.read_bytes(32)? != tip_hash {
+        return Ok(false);
+    }
+
+    for _ in 0..reader.read_u32()? {
+        let address = deserialize_string(&mut reader)?;
+        let utxos = deserialize_utxos(&mut reader, wallet)?;
+        let utxos_vueltos = deserialize_utxos(&mut reader, wallet)?;
+
+        let mut transactions_history = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let length = reader.read_u32()? as usize;
+            let bytes = reader.read_bytes(length)?.to_vec();
+            transactions_history.push(parse_transaction(bytes, &mut 0)?);
+        }
+
+        if address == wallet.address {
+            wallet.utxo_set.utxos = utxos;
+            wallet.utxos_vueltos = utxos_vueltos;
+            wallet.transactions_history = transactions_history;
+            wallet.calculate_balance();
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn serialize_string(data: &mut Vec<u8>, string: &str) {
+    data.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    data.extend_from_slice(string.as_bytes());
+}
+
+fn deserialize_string(reader: &mut PayloadReader) -> Result<String, Error> {
+    let length = reader.read_u32()? as usize;
+    String::from_utf8(reader.read_bytes(length)?.to_vec())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn serialize_utxos(data: &mut Vec<u8>, utxos: &[Utxo]) {
+    data.extend_from_slice(&(utxos.len() as u32).to_le_bytes());
+    for utxo in utxos {
+        serialize_string(data, &utxo.txid);
+        data.extend_from_slice(&utxo.index.to_le_bytes());
+        data.extend_from_slice(&utxo.value.to_le_bytes());
+    }
+}
+
+/// Reads the utxos, which belong to the key of the wallet
+fn deserialize_utxos(reader: &mut PayloadReader, wallet: &Wallet) -> Result<Vec<Utxo>, Error> {
+    let mut utxos = Vec::new();
+    for _ in 0..reader.read_u32()? {
+        utxos.push(Utxo {
+            txid: deserialize_string(reader)?,
+            index: reader.read_u32()?,
+            value: reader.read_u64()?,
+            pubkey: wallet.public_key,
+        });
+    }
+
+    Ok(utxos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{
+        block::Block, block_header::BlockHeader, chainstate::OutPoint, transaction::Transaction,
+    };
+    use crate::helpers::test_helpers;
+    use bitcoin_hashes::Hash;
+
+    fn coinbase(height: u8) -> Transaction {
+        let mut previous_output = [0u8; 36];
+        previous_output[32..].copy_from_slice(&[0xff; 4]);
+        test_helpers::transaction(
+            vec![test_helpers::input(previous_output, vec![0x01, height])],
+            vec![test_helpers::output(5000, vec![0x51])],
+        )
+    }
+
+    fn block(prev_block_hash: Vec<u8>, txns: Vec<Transaction>) -> Block {
+        Block::new(
+            BlockHeader::new(1, prev_block_hash, vec![0; 32], 0, 0x207fffff, 0),
+            txns.len(),
+            txns,
+        )
+    }
+
+    #[test]
+    pub fn test_saved_chainstate_resumes_from_its_tip() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_chainstate_store_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        assert!(load_chainstate(data_dir).unwrap().is_none());
+
+        let first = block(vec![0; 32], vec![coinbase(1)]);
+        let outpoint = OutPoint::new(first.txns[0].hash.into_inner(), 0);
+        let spend = test_helpers::transaction(
+            vec![test_helpers::input(
+                [&outpoint.txid[..], &[0; 4]].concat().try_into().unwrap(),
+                vec![0x51],
+            )],
+            vec![test_helpers::output(3000, vec![0x51])],
+        );
+        let second = block(
+            first.header.calculate_hash(),
+            vec![coinbase(2), spend.clone()],
+        );
+
+        let mut chainstate = Chainstate::new();
+        chainstate.connect_block(&first, 1001).unwrap();
+        chainstate.connect_block(&second, 1002).unwrap();
+        save_chainstate(data_dir, &chainstate).unwrap();
+
+        let mut loaded = load_chainstate(data_dir).unwrap().unwrap();
+        assert_eq!(loaded.tip_hash(), chainstate.tip_hash());
+        assert_eq!(loaded.tip_height(), 1002);
+        assert_eq!(loaded.len(), chainstate.len());
+        assert!(loaded.is_unspent(&OutPoint::new(spend.hash.into_inner(), 0)));
+
+        // The undo data is saved, so the tip can still be disconnected
+        loaded.disconnect_block(&second).unwrap();
+        assert!(loaded.is_unspent(&outpoint));
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_wallet_is_only_restored_with_the_tip_it_was_saved_with() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_wallet_store_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+
+        let mut wallet = Wallet::new();
+        wallet.address = "mnJvq7mbGiPNNhUne4FAqq27Q8xZrAsVun".to_owned();
+        wallet.utxo_set.utxos.push(Utxo {
+            txid: "ab".repeat(32),
+            index: 1,
+            value: 700,
+            pubkey: wallet.public_key,
+        });
+        wallet.transactions_history.push(coinbase(1));
+        let mut wallets = HashMap::new();
+        wallets.insert(wallet.address.clone(), wallet.clone());
+        save_wallets(data_dir, &[1; 32], &wallets).unwrap();
+
+        let mut restored =
+            Wallet::new_from_existing(&wallet.private_key, &wallet.public_key, &wallet.address);
+        assert!(!restore_wallet(data_dir, &[2; 32], &mut restored).unwrap());
+        assert!(restored.utxo_set.utxos.is_empty());
+
+        assert!(restore_wallet(data_dir, &[1; 32], &mut restored).unwrap());
+        assert_eq!(restored.balance, 700);
+        assert_eq!(restored.utxo_set.utxos[0].index, 1);
+        assert_eq!(
+            restored.transactions_history[0].hash,
+            wallet.transactions_history[0].hash
+        );
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
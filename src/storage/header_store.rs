@@ -0,0 +1,79 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    components::block_header::BlockHeader,
+    testnet_protocol::messages::message_parsers::parse_block_header,
+};
+
+use super::record_file::{append_records, read_records};
+
+/// Name of the file, inside the data directory, where the headers are appended
+pub const HEADERS_FILE: &str = "headers.dat";
+
+/// Size of a header in the wire serialization
+const HEADER_SIZE: usize = 80;
+
+/// Appends the headers to the headers file, each in a checksummed record with its 80 bytes
+/// serialization. Once it returns the headers are on disk
+pub fn append_headers(data_dir: &str, headers: &[BlockHeader]) -> Result<(), Error> {
+    fs::create_dir_all(data_dir)?;
+
+    let records: Vec<Vec<u8>> = headers.iter().map(|header| header.serialize()).collect();
+    append_records(&Path::new(data_dir).join(HEADERS_FILE), &records)?;
+    Ok(())
+}
+
+/// Reads the headers stored in the data directory, in the order they were stored.
+/// If there is no file yet there are no headers. A last header that was not completely written
+/// is skipped, so the chain resumes from the last complete one. The file is not changed
+pub fn read_headers(data_dir: &str) -> Result<Vec<BlockHeader>, Error> {
+    read_records(&Path::new(data_dir).join(HEADERS_FILE))?
+        .iter()
+        .map(|record| {
+            if record.payload.len() != HEADER_SIZE {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid header record"));
+            }
+            parse_block_header(&record.payload)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::record_file::{encode_record, recover_data_dir};
+
+    #[test]
+    pub fn test_headers_are_the_same_after_storing_them() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_header_store_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        let first = BlockHeader::new(1, vec![0; 32], vec![1; 32], 10, 0x207fffff, 2);
+        let second = BlockHeader::new(2, first.calculate_hash(), vec![3; 32], 20, 0x207fffff, 4);
+
+        assert!(read_headers(data_dir).unwrap().is_empty());
+        append_headers(data_dir, std::slice::from_ref(&first)).unwrap();
+        append_headers(data_dir, std::slice::from_ref(&second)).unwrap();
+
+        let headers = read_headers(data_dir).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].serialize(), first.serialize());
+        assert_eq!(headers[1].serialize(), second.serialize());
+
+        // A header torn by a crash is skipped, and once the recovery discards it the next one is
+        // appended after the first two
+        let path = Path::new(data_dir).join(HEADERS_FILE);
+        let mut data = fs::read(&path).unwrap();
+        data.extend_from_slice(&encode_record(&first.serialize())[..40]);
+        fs::write(&path, data).unwrap();
+        assert_eq!(read_headers(data_dir).unwrap().len(), 2);
+        recover_data_dir(data_dir).unwrap();
+        append_headers(data_dir, std::slice::from_ref(&first)).unwrap();
+        assert_eq!(read_headers(data_dir).unwrap().len(), 3);
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use bitcoin_hashes::{sha256d, Hash};
+
+use crate::configuration::chain_params::{chain_params, ChainParams, Network};
+
+use super::{
+    address_index::ADDRESS_INDEX_FILE, block_store::BlockStore, header_store::HEADERS_FILE,
+    tx_index::TX_INDEX_FILE,
+};
+
+/// Size of the magic, the length and the checksum written before the payload of each record
+pub const RECORD_HEADER_SIZE: u64 = 12;
+
+/// Payload of a record of a file and the offset where the record starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub offset: u64,
+    pub payload: Vec<u8>,
+}
+
+/// First four bytes of the double sha256 of the payload, as in the messages of the protocol
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(payload).into_inner();
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Writes the magic of the network, the length and the checksum before the payload
+pub fn encode_record(payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(payload.len() + RECORD_HEADER_SIZE as usize);
+    record.extend_from_slice(&chain_params().magic.to_le_bytes());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&checksum(payload));
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Reads the record that starts at the data, if it is complete and its checksum matches.
+/// Returns the payload and the size of the record
+fn decode_record(data: &[u8]) -> Option<(&[u8], usize)> {
+    let header = data.get(..RECORD_HEADER_SIZE as usize)?;
+    if u32::from_le_bytes(header[..4].try_into().ok()?) != chain_params().magic {
+        return None;
+    }
+    let length = u32::from_le_bytes(header[4..8].try_into().ok()?) as usize;
+    let payload = data.get(RECORD_HEADER_SIZE as usize..RECORD_HEADER_SIZE as usize + length)?;
+    if checksum(payload) != header[8..12] {
+        return None;
+    }
+
+    Some((payload, RECORD_HEADER_SIZE as usize + length))
+}
+
+/// Returns true if the data is the start of a record that was not completely written: its header
+/// or the payload of the length it declares go past the end of the data
+fn is_incomplete_record(data: &[u8]) -> bool {
+    if let Some(magic) = data.get(..4) {
+        if magic != chain_params().magic.to_le_bytes() {
+            return false;
+        }
+    }
+
+    match data.get(4..8) {
+        Some(length) if data.len() >= RECORD_HEADER_SIZE as usize => {
+            let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+            RECORD_HEADER_SIZE + length as u64 > data.len() as u64
+        }
+        _ => true,
+    }
+}
+
+/// Returns the network whose magic starts the data, if it is not the network of the node
+fn other_network(data: &[u8]) -> Option<Network> {
+    let magic = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    [
+        Network::Mainnet,
+        Network::Testnet3,
+        Network::Signet,
+        Network::Regtest,
+    ]
+    .into_iter()
+    .filter(|network| *network != chain_params().network)
+    .find(|network| ChainParams::new(*network).magic == magic)
+}
+
+/// Appends the records to the file and waits until they are on disk, so once it returns they
+/// survive a crash. Returns the offset of the first record
+pub fn append_records(path: &Path, payloads: &[Vec<u8>]) -> Result<u64, Error> {
+    let data: Vec<u8> = payloads
+        .iter()
+        .flat_map(|payload| encode_record(payload))
+        .collect();
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let offset = file.metadata()?.len();
+    file.write_all(&data)?;
+    file.sync_data()?;
+
+    Ok(offset)
+}
+
+/// Reads the complete records of the file without changing it, so it can be read while another
+/// thread appends to it. Only the last record can be incomplete, as it is while it is appended or
+/// when the process died writing it. Returns the records and the length of the file up to the
+/// last complete one. If there is no file there are no records. A corrupted record and a file of
+/// another network are errors
+fn read_valid_records(path: &Path) -> Result<(Vec<Record>, u64, u64), Error> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((Vec::new(), 0, 0)),
+        Err(e) => return Err(e),
+    };
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        if let Some((payload, size)) = decode_record(&data[offset..]) {
+            records.push(Record {
+                offset: offset as u64,
+                payload: payload.to_vec(),
+            });
+            offset += size;
+            continue;
+        }
+
+        if let Some(network) = other_network(&data[offset..]) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has records of the network {:?}",
+                    path.display(),
+                    network
+                ),
+            ));
+        }
+        if is_incomplete_record(&data[offset..]) {
+            break;
+        }
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} has a corrupted record at {}", path.display(), offset),
+        ));
+    }
+
+    Ok((records, offset as u64, data.len() as u64))
+}
+
+/// Reads the complete records of the file, skipping the last one if it is incomplete, as it is
+/// while it is being appended. The file is not changed
+pub fn read_records(path: &Path) -> Result<Vec<Record>, Error> {
+    read_valid_records(path).map(|(records, _, _)| records)
+}
+
+/// Reads the records of the file and truncates it after the last complete one, discarding the
+/// record that was torn when the process died while writing it, so the next records are appended
+/// after the valid ones. It must only run while nothing else reads or writes the file.
+/// A corrupted record and a file of another network are errors, and the file is not truncated
+pub fn recover_records(path: &Path) -> Result<Vec<Record>, Error> {
+    let (records, valid_length, length) = read_valid_records(path)?;
+
+    if valid_length < length {
+        println!(
+            "Se descartan {} bytes incompletos de {}",
+            length - valid_length,
+            path.display()
+        );
+        truncate_file(path, valid_length)?;
+    }
+
+    Ok(records)
+}
+
+/// Discards what a crash left half written in the files of the data directory. It runs once when
+/// the process starts, before the files are opened, so the stores are opened read only afterwards
+/// and the records are appended after the valid ones
+pub fn recover_data_dir(data_dir: &str) -> Result<(), Error> {
+    for file in [HEADERS_FILE, TX_INDEX_FILE, ADDRESS_INDEX_FILE] {
+        recover_records(&Path::new(data_dir).join(file))?;
+    }
+    BlockStore::recover(data_dir)
+}
+
+/// Reads the payload of the record at the offset of the file, checking its checksum
+pub fn read_record_at(file: &mut File, offset: u64) -> Result<Vec<u8>, Error> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+
+    let mut record = header.to_vec();
+    record.resize(RECORD_HEADER_SIZE as usize + length, 0);
+    file.read_exact(&mut record[RECORD_HEADER_SIZE as usize..])?;
+
+    match decode_record(&record) {
+        Some((payload, _)) => Ok(payload.to_vec()),
+        None => Err(Error::new(ErrorKind::InvalidData, "Corrupted record")),
+    }
+}
+
+/// Replaces the file with one with only the records, writing it aside and renaming it, so a crash
+/// leaves either the old file or the new one
+pub fn rewrite_records(path: &Path, payloads: &[Vec<u8>]) -> Result<(), Error> {
+    let data: Vec<u8> = payloads
+        .iter()
+        .flat_map(|payload| encode_record(payload))
+        .collect();
+
+    write_atomically(path, &data)
+}
+
+/// Writes the data to a temporary file next to the path and renames it to the path. The directory
+/// is synced after the rename, so the new file is the one found after a crash
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let temporary_path = path.with_extension("tmp");
+
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(temporary_path, path)?;
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()
+}
+
+/// Cuts the file at the length, discarding what was written after it
+pub fn truncate_file(path: &Path, length: u64) -> Result<(), Error> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(length)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_torn_records_are_truncated_and_corrupted_ones_are_errors() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_record_file_test");
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(&data_dir).unwrap();
+        let path = data_dir.join("records.dat");
+
+        append_records(&path, &[vec![1, 2, 3], vec![4]]).unwrap();
+        let offset = append_records(&path, &[vec![5, 6]]).unwrap();
+        assert_eq!(offset, 2 * RECORD_HEADER_SIZE + 4);
+
+        // The last record is torn, as if the process died while writing it
+        let length = fs::metadata(&path).unwrap().len();
+        truncate_file(&path, length - 1).unwrap();
+
+        let records = recover_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].payload, vec![4]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), offset);
+
+        let offset = append_records(&path, &[vec![7, 8]]).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(read_record_at(&mut file, offset).unwrap(), vec![7, 8]);
+
+        // A changed byte of the payload breaks the checksum, and the records after it are kept
+        let mut data = fs::read(&path).unwrap();
+        data[RECORD_HEADER_SIZE as usize] ^= 1;
+        fs::write(&path, &data).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        assert!(read_record_at(&mut file, 0).is_err());
+        assert_eq!(read_record_at(&mut file, offset).unwrap(), vec![7, 8]);
+        assert_eq!(
+            recover_records(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_records(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // The last record is also corrupted, not torn, when it is complete
+        data[RECORD_HEADER_SIZE as usize] ^= 1;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, &data).unwrap();
+        assert!(recover_records(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), data);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    pub fn test_records_of_another_network_are_not_truncated() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_record_file_network_test");
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(&data_dir).unwrap();
+        let path = data_dir.join("records.dat");
+
+        let network = match chain_params().network {
+            Network::Mainnet => Network::Testnet3,
+            _ => Network::Mainnet,
+        };
+        let mut record = ChainParams::new(network).magic.to_le_bytes().to_vec();
+        record.extend_from_slice(&1u32.to_le_bytes());
+        record.extend_from_slice(&checksum(&[7]));
+        record.push(7);
+        fs::write(&path, &record).unwrap();
+
+        assert_eq!(
+            recover_records(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(fs::read(&path).unwrap(), record);
+        let _ = fs::remove_dir_all(&data_dir);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::components::{block::Block, header_chain::HeaderChain};
+
+use super::{
+    block_store::BlockStore,
+    record_file::{append_records, read_records},
+};
+
+/// Name of the file, inside the data directory, where the transaction index is appended
+pub const TX_INDEX_FILE: &str = "txindex.dat";
+
+/// Size of a hash in the records of the index
+const HASH_SIZE: usize = 32;
+
+/// Record of a connected block, with its hash and its txids
+const RECORD_BLOCK_CONNECTED: u8 = 0;
+/// Record that removes the transactions of a block that left the best chain
+const RECORD_BLOCK_DISCONNECTED: u8 = 1;
+
+/// Block of a confirmed transaction and its position among the transactions of the block
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxLocation {
+    pub block_hash: Vec<u8>,
+    pub position: u32,
+}
+
+/// #TDA TxIndex
+/// Optional index from the txid of every transaction of the blocks of the best chain to its block.
+/// Each connected block appends a checksummed record with its hash and its txids in order,
+/// so a block is indexed completely or not at all. A block that leaves the best chain appends
+/// a record with its hash that removes its transactions. A transaction in the blocks of two
+/// branches keeps both locations, so it is still found when one of them is removed
+#[derive(Debug)]
+pub struct TxIndex {
+    path: PathBuf,
+    locations: HashMap<Vec<u8>, Vec<TxLocation>>,
+    indexed_blocks: HashSet<Vec<u8>>,
+}
+
+impl TxIndex {
+    /// Opens the index of the data directory. If there is no file yet the index is empty.
+    /// A block whose record was torn by a crash is skipped and indexed again by the next sync.
+    /// The file is not changed, so the index can be opened while the node appends to it
+    pub fn open(data_dir: &str) -> Result<TxIndex, Error> {
+        fs::create_dir_all(data_dir)?;
+
+        let mut tx_index = TxIndex {
+            path: Path::new(data_dir).join(TX_INDEX_FILE),
+            locations: HashMap::new(),
+            indexed_blocks: HashSet::new(),
+        };
+
+        for record in read_records(&tx_index.path)? {
+            let (kind, hashes) = match record.payload.split_first() {
+                Some((kind, hashes)) if !hashes.is_empty() && hashes.len() % HASH_SIZE == 0 => {
+                    (*kind, hashes)
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Invalid tx index record",
+                    ))
+                }
+            };
+
+            let mut hashes = hashes.chunks_exact(HASH_SIZE);
+            let block_hash = hashes.next().unwrap_or_default().to_vec();
+            match kind {
+                RECORD_BLOCK_CONNECTED => tx_index.apply(block_hash, hashes),
+                RECORD_BLOCK_DISCONNECTED => tx_index.remove_block(&block_hash),
+                _ => {}
+            }
+        }
+
+        Ok(tx_index)
+    }
+
+    /// Deletes the index of the data directory and builds it again from the stored blocks
+    /// of the best chain
+    #[allow(dead_code)]
+    pub fn rebuild(
+        data_dir: &str,
+        header_chain: &HeaderChain,
+        block_store: &BlockStore,
+    ) -> Result<TxIndex, Error> {
+        match fs::remove_file(Path::new(data_dir).join(TX_INDEX_FILE)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let mut tx_index = TxIndex::open(data_dir)?;
+        tx_index.sync(header_chain, block_store)?;
+        Ok(tx_index)
+    }
+
+    /// Removes the indexed blocks that are not in the best chain and indexes the stored blocks of
+    /// the best chain that are not in the index yet, in height order, as when the index is enabled
+    /// after the blocks were downloaded. Returns the amount of blocks indexed
+    pub fn sync(
+        &mut self,
+        header_chain: &HeaderChain,
+        block_store: &BlockStore,
+    ) -> Result<usize, Error> {
+        let stale_blocks: Vec<Vec<u8>> = self
+            .indexed_blocks
+            .iter()
+            .filter(|hash| !header_chain.is_in_best_chain(hash))
+            .cloned()
+            .collect();
+        for hash in stale_blocks {
+            self.disconnect_block_hash(&hash)?;
+        }
+
+        let mut indexed = 0;
+        for height in 1..=header_chain.best_height() {
+            let hash = match header_chain.hash_at(height) {
+                Some(hash) => hash,
+                None => break,
+            };
+            if self.indexed_blocks.contains(hash) {
+                continue;
+            }
+            if let Some(block) = block_store.read_block(hash)? {
+                self.index_block(&block)?;
+                indexed += 1;
+            }
+        }
+
+        Ok(indexed)
+    }
+
+    /// Appends the transactions of the block to the index, if it was not indexed before
+    pub fn index_block(&mut self, block: &Block) -> Result<(), Error> {
+        let block_hash = block.header.calculate_hash();
+        if self.indexed_blocks.contains(&block_hash) {
+            return Ok(());
+        }
+
+        let mut record = Vec::with_capacity((block.txns.len() + 1) * HASH_SIZE + 1);
+        record.push(RECORD_BLOCK_CONNECTED);
+        record.extend_from_slice(&block_hash);
+        for tx in &block.txns {
+            record.extend_from_slice(&tx.hash[..]);
+        }
+        append_records(&self.path, &[record])?;
+
+        self.apply(block_hash, block.txns.iter().map(|tx| &tx.hash[..]));
+
+        Ok(())
+    }
+
+    /// Removes the transactions of a block that left the best chain
+    pub fn disconnect_block(&mut self, block: &Block) -> Result<(), Error> {
+        self.disconnect_block_hash(&block.header.calculate_hash())
+    }
+
+    fn disconnect_block_hash(&mut self, block_hash: &[u8]) -> Result<(), Error> {
+        if !self.indexed_blocks.contains(block_hash) {
+            return Ok(());
+        }
+
+        let mut record = Vec::with_capacity(HASH_SIZE + 1);
+        record.push(RECORD_BLOCK_DISCONNECTED);
+        record.extend_from_slice(block_hash);
+        append_records(&self.path, &[record])?;
+        self.remove_block(block_hash);
+
+        Ok(())
+    }
+
+    /// Returns where the transaction with the txid, in internal byte order, was confirmed
+    pub fn get(&self, txid: &[u8]) -> Option<&TxLocation> {
+        self.locations
+            .get(txid)
+            .and_then(|locations| locations.last())
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    fn apply<'a>(&mut self, block_hash: Vec<u8>, txids: impl Iterator<Item = &'a [u8]>) {
+        for (position, txid) in txids.enumerate() {
+            self.locations
+                .entry(txid.to_vec())
+                .or_default()
+                .push(TxLocation {
+                    block_hash: block_hash.clone(),
+                    position: position as u32,
+                });
+        }
+        self.indexed_blocks.insert(block_hash);
+    }
+
+    fn remove_block(&mut self, block_hash: &[u8]) {
+        self.indexed_blocks.remove(block_hash);
+        for locations in self.locations.values_mut() {
+            locations.retain(|location| location.block_hash != block_hash);
+        }
+        self.locations.retain(|_, locations| !locations.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{block_header::BlockHeader, transaction::Transaction};
+    use crate::configuration::chain_params::{ChainParams, Network};
+    use crate::helpers::test_helpers;
+    use crate::storage::record_file::recover_data_dir;
+
+    fn transaction(previous_output: u8) -> Transaction {
+        test_helpers::transaction(
+            vec![test_helpers::input([previous_output; 36], vec![0x51])],
+            vec![test_helpers::output(5000, vec![0x51])],
+        )
+    }
+
+    fn child(parent: &BlockHeader, nonce: u32, txns: Vec<Transaction>) -> Block {
+        Block::new(
+            test_helpers::mine(BlockHeader::new(
+                1,
+                parent.calculate_hash(),
+                vec![0; 32],
+                parent.timestamp + 600,
+                0x207fffff,
+                nonce,
+            )),
+            txns.len(),
+            txns,
+        )
+    }
+
+    #[test]
+    pub fn test_index_is_built_incrementally_and_rebuilt_from_the_store() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_tx_index_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        let params = ChainParams::new(Network::Regtest);
+        let first = child(
+            &params.genesis_header,
+            1,
+            vec![transaction(1), transaction(2)],
+        );
+        let second = child(&first.header, 2, vec![transaction(3)]);
+        let mut header_chain = HeaderChain::new(params);
+        header_chain.add_header(first.header.clone()).unwrap();
+        header_chain.add_header(second.header.clone()).unwrap();
+        let mut block_store = BlockStore::open(data_dir).unwrap();
+        block_store.store_block(&first).unwrap();
+        block_store.store_block(&second).unwrap();
+
+        let mut tx_index = TxIndex::open(data_dir).unwrap();
+        tx_index.index_block(&first).unwrap();
+        tx_index.index_block(&first).unwrap();
+        assert_eq!(tx_index.len(), 2);
+
+        let tx_index = TxIndex::open(data_dir).unwrap();
+        assert_eq!(
+            tx_index.get(&first.txns[1].hash[..]),
+            Some(&TxLocation {
+                block_hash: first.header.calculate_hash(),
+                position: 1,
+            })
+        );
+        assert!(tx_index.get(&second.txns[0].hash[..]).is_none());
+
+        let mut tx_index = TxIndex::open(data_dir).unwrap();
+        assert_eq!(tx_index.sync(&header_chain, &block_store).unwrap(), 1);
+        assert_eq!(
+            tx_index.get(&second.txns[0].hash[..]).unwrap().block_hash,
+            second.header.calculate_hash()
+        );
+
+        // The record of the second block is torn, so it is indexed again
+        let path = Path::new(data_dir).join(TX_INDEX_FILE);
+        let length = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(length - 10)
+            .unwrap();
+        assert_eq!(TxIndex::open(data_dir).unwrap().len(), 2);
+        recover_data_dir(data_dir).unwrap();
+        let mut tx_index = TxIndex::open(data_dir).unwrap();
+        assert_eq!(tx_index.len(), 2);
+        assert_eq!(tx_index.sync(&header_chain, &block_store).unwrap(), 1);
+
+        let tx_index = TxIndex::rebuild(data_dir, &header_chain, &block_store).unwrap();
+        assert_eq!(tx_index.len(), 3);
+        let _ = fs::remove_dir_all(data_dir);
+    }
+
+    #[test]
+    pub fn test_disconnected_blocks_leave_the_index() {
+        let data_dir = std::env::temp_dir().join("tp_bitcoin_tx_index_reorg_test");
+        let data_dir = data_dir.to_str().unwrap();
+        let _ = fs::remove_dir_all(data_dir);
+        let params = ChainParams::new(Network::Regtest);
+        let shared = transaction(1);
+        let old = child(
+            &params.genesis_header,
+            1,
+            vec![shared.clone(), transaction(2)],
+        );
+        let new_1 = child(
+            &params.genesis_header,
+            2,
+            vec![transaction(3), shared.clone()],
+        );
+        let new_2 = child(&new_1.header, 2, vec![transaction(4)]);
+        let mut header_chain = HeaderChain::new(params);
+        let mut block_store = BlockStore::open(data_dir).unwrap();
+        for block in [&old, &new_1, &new_2] {
+            header_chain.add_header(block.header.clone()).unwrap();
+            block_store.store_block(block).unwrap();
+        }
+
+        let mut tx_index = TxIndex::open(data_dir).unwrap();
+        tx_index.index_block(&old).unwrap();
+        tx_index.disconnect_block(&old).unwrap();
+        tx_index.index_block(&new_1).unwrap();
+        assert!(tx_index.get(&old.txns[1].hash[..]).is_none());
+        assert_eq!(
+            tx_index.get(&shared.hash[..]),
+            Some(&TxLocation {
+                block_hash: new_1.header.calculate_hash(),
+                position: 1,
+            })
+        );
+
+        // The records are replayed in order when the index is opened again
+        let tx_index = TxIndex::open(data_dir).unwrap();
+        assert!(tx_index.get(&old.txns[1].hash[..]).is_none());
+        assert_eq!(tx_index.len(), 2);
+
+        // A block indexed before it left the best chain is removed by the sync
+        let mut tx_index = TxIndex::open(data_dir).unwrap();
+        tx_index.index_block(&old).unwrap();
+        assert_eq!(tx_index.sync(&header_chain, &block_store).unwrap(), 1);
+        assert!(tx_index.get(&old.txns[1].hash[..]).is_none());
+        assert_eq!(
+            tx_index.get(&shared.hash[..]).unwrap().block_hash,
+            new_1.header.calculate_hash()
+        );
+        assert_eq!(tx_index.len(), 3);
+        let _ = fs::remove_dir_all(data_dir);
+    }
+}
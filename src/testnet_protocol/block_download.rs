@@ -1,293 +1,742 @@
 use std::{
-    io::{Error, ErrorKind, Read},
-    net::{SocketAddr, TcpStream},
-    str::FromStr,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet},
+    io::{Error, ErrorKind},
+    net::TcpStream,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
 use crate::{
-    components::{block::Block, block_header::BlockHeader, transaction::Transaction},
-    connection::connection_protocol::set_tcp_stream_vec,
+    components::{
+        block::Block,
+        block_header::BlockHeader,
+        chainstate::{Chainstate, MAX_REORG_DEPTH},
+        header_chain::HeaderChain,
+        wallet::{rollback_wallet, update_wallet, Wallet},
+    },
+    configuration::{
+        chain_params::chain_params,
+        config_helper::{get_blocks_start_time, get_data_dir, is_txindex_enabled},
+    },
+    connection::peer_liveness::{read_peer_message, write_peer_message, PeerTracker},
     interface::interfaz_grafica::{ChannelData, DownloadData},
-    logger::{
-        log_printer::{log_block, log_block_header},
-        logger_impl::Logger,
+    storage::{
+        address_index::AddressIndex,
+        block_store::BlockStore,
+        chainstate_store::{save_chainstate, save_wallets},
+        header_store::{append_headers, read_headers},
+        tx_index::TxIndex,
     },
     testnet_protocol::{
+        block_scheduler::{BlockScheduler, BLOCK_DOWNLOAD_TIMEOUT},
         header_download::header_download,
         messages::{
-            message_builders::build_get_data_message,
-            message_parsers::{has_consecutive_zeros, parse_block, parse_transactions},
+            message_builders::{
+                build_get_data_blocks_message, build_get_data_message, build_get_headers_message,
+            },
             message_senders::write_and_read_get_data_message,
+            network_message::{Inventory, NetworkMessage, MSG_BLOCK, MSG_WITNESS_FLAG},
         },
     },
 };
 
+/// Time a peer without blocks to download waits before asking the scheduler again
+const IDLE_PEER_WAIT: Duration = Duration::from_millis(200);
+
+/// Number of connected blocks between two saves of the chainstate and the wallets, which are also
+/// saved when the tip of the best chain is connected
+const NODE_STATE_SAVE_INTERVAL: u32 = 1000;
+
+/// Downloads the headers and the blocks of the best chain from the peers, and then keeps
+/// following the blocks they announce until every peer disconnects
 pub fn initial_block_download(
-    address: Vec<String>,
+    tcp_stream_vec: Vec<TcpStream>,
     node_sender: Arc<Mutex<gtk::glib::Sender<ChannelData>>>,
+    chainstate: Arc<Mutex<Chainstate>>,
+    wallets: Arc<Mutex<HashMap<String, Wallet>>>,
+    address_index: Option<Arc<Mutex<AddressIndex>>>,
 ) -> Result<(), Error> {
-    let tcp_stream_vec: Vec<TcpStream> = set_tcp_stream_vec(address.to_vec()).unwrap();
-
-    let lista_seed_hashes: Vec<String> = vec![
-        "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943".to_string(),
-        "0000000000003739446e6bf168c07f87ee93a26ade070e8dfdae51ab20f33fa9".to_string(),
-    ];
+    let peer_tracker = PeerTracker::new();
+    for socket in &tcp_stream_vec {
+        peer_tracker.start_keepalive(socket)?;
+    }
 
     let vec_tcp_localhost: Vec<TcpStream> = vec![tcp_stream_vec[0].try_clone().unwrap()];
 
     let node_sender_copy = Arc::clone(&node_sender);
 
-    let lista_headers: Vec<BlockHeader> =
-        header_download(&vec_tcp_localhost, lista_seed_hashes, node_sender_copy).unwrap();
-
-    let logger_header = Logger::new("./logs", "headers_client")
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get logger"))
-        .unwrap();
+    let data_dir = get_data_dir();
+    let header_chain = Arc::new(Mutex::new(load_stored_header_chain(&data_dir)));
+    let stored_tip = header_chain.lock().unwrap().best_tip().hash.clone();
+
+    let lista_headers: Vec<BlockHeader> = header_download(
+        &vec_tcp_localhost,
+        node_sender_copy,
+        &peer_tracker,
+        &header_chain,
+    )
+    .unwrap();
+
+    if lista_headers.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "No peer sent a header chain with the minimum chainwork",
+        ));
+    }
 
     println!("Cantidad de headers ---> {}", lista_headers.len());
-    println!(
-        "headers ---> {:?}",
-        lista_headers[lista_headers.len() - 1].prev_block_hash
-    );
 
-    for header in lista_headers.clone() {
-        log_block_header(Some(&logger_header), &header)?;
-    }
+    // Only the headers that were not stored in a previous run are saved
+    let header_chain_blocked = header_chain.lock().unwrap();
+    let fork_height = header_chain_blocked.fork_height(&stored_tip).unwrap_or(0);
+    let new_headers: Vec<BlockHeader> = (fork_height + 1..=header_chain_blocked.best_height())
+        .filter_map(|height| header_chain_blocked.header_at(height).cloned())
+        .collect();
+    drop(header_chain_blocked);
+    append_headers(&data_dir, &new_headers)?;
 
     thread::sleep(Duration::from_secs(15));
 
-    let mut headers_blocks: Vec<BlockHeader> = Vec::new();
-
-    for header in lista_headers {
-        if header.timestamp >= 1682380800 && header.timestamp <= 1688342400 {
-            headers_blocks.push(header);
+    // The blocks are downloaded after the tip of the saved chainstate. Without one, from the first
+    // one of the start time, so they are consecutive
+    let saved_tip_height = saved_chainstate_height(&chainstate, &header_chain.lock().unwrap());
+    let first_block = match saved_tip_height {
+        Some(height) => (height as usize).min(lista_headers.len()),
+        None => {
+            let start_time = get_blocks_start_time();
+            let first_block = lista_headers
+                .iter()
+                .position(|header| header.timestamp >= start_time)
+                .unwrap_or(lista_headers.len());
+            if first_block > 0 {
+                println!(
+                    "Se descargan los bloques desde {}: el chainstate es parcial y no se validan \
+                     las entradas, las comisiones, los scripts ni los lock times de los bloques",
+                    start_time
+                );
+            }
+            first_block
         }
-    }
+    };
+    let headers_blocks: Vec<BlockHeader> = lista_headers[first_block..].to_vec();
 
     println!(
         "Longitud de lista de headers que se van a pedir los blocks --->{}",
         headers_blocks.len()
     );
 
-    let logger_block = Logger::new("./logs", "blocks_client")
-        .map_err(|_| Error::new(ErrorKind::Other, "Failed to get logger"))?;
-
-    let vec_tcp_copy: Vec<TcpStream> = vec![
-        tcp_stream_vec[0].try_clone().unwrap(),
-        tcp_stream_vec[1].try_clone().unwrap(),
-        tcp_stream_vec[2].try_clone().unwrap(),
-        tcp_stream_vec[3].try_clone().unwrap(),
-    ];
+    let mut block_store = BlockStore::open(&data_dir)?;
+    println!(
+        "Bloques guardados de ejecuciones anteriores ---> {}",
+        block_store.len()
+    );
 
-    let lista_blocks: Vec<Block> =
-        block_download(vec_tcp_copy, headers_blocks, node_sender).unwrap();
+    let mut tx_index = match is_txindex_enabled() {
+        true => Some(TxIndex::open(&data_dir)?),
+        false => None,
+    };
+    if let Some(tx_index) = tx_index.as_mut() {
+        let indexed = tx_index.sync(&header_chain.lock().unwrap(), &block_store)?;
+        println!(
+            "Bloques agregados al indice de transacciones ---> {}",
+            indexed
+        );
+    }
+    if let Some(address_index) = &address_index {
+        let indexed = address_index
+            .lock()
+            .unwrap()
+            .sync(&header_chain.lock().unwrap(), &block_store)?;
+        println!(
+            "Bloques agregados al indice de direcciones ---> {}",
+            indexed
+        );
+    }
 
-    println!(
-        "Longitud blocks descargados del servidor ---> {}",
-        lista_blocks.len()
+    let (block_sender, block_receiver) = channel::<Block>();
+
+    let announcement_sockets: Vec<TcpStream> = tcp_stream_vec
+        .iter()
+        .filter_map(|socket| socket.try_clone().ok())
+        .collect();
+    let chainstate_copy = Arc::clone(&chainstate);
+    let header_chain_copy = Arc::clone(&header_chain);
+    let block_store_thread = thread::spawn(move || {
+        let mut recent_blocks: HashMap<Vec<u8>, Block> = HashMap::new();
+        for block in block_receiver {
+            if let Err(e) = block_store.store_block(&block) {
+                println!("No se pudo guardar el bloque: {}", e);
+            }
+            let hash = block.header.calculate_hash();
+            connect_downloaded_block(
+                &chainstate,
+                &header_chain_copy,
+                &mut recent_blocks,
+                &wallets,
+                &mut tx_index,
+                &address_index,
+                block,
+            );
+
+            if is_node_state_save_point(&header_chain_copy.lock().unwrap(), &hash) {
+                if let Err(e) = save_node_state(&data_dir, &chainstate, &wallets) {
+                    println!("No se pudo guardar el estado del nodo: {}", e);
+                }
+            }
+        }
+    });
+
+    let result = block_download(
+        tcp_stream_vec,
+        headers_blocks,
+        node_sender,
+        &peer_tracker,
+        &header_chain,
+        block_sender.clone(),
     );
 
-    for block in lista_blocks {
-        log_block(Some(&logger_block), &block)?;
+    match &result {
+        Ok(()) => follow_announcements(
+            announcement_sockets,
+            &peer_tracker,
+            &header_chain,
+            &chainstate_copy,
+            block_sender,
+        ),
+        Err(_) => drop(block_sender),
     }
 
+    if block_store_thread.join().is_err() {
+        println!("Hubo un error guardando los blocks");
+    }
+    result?;
+
     thread::sleep(Duration::from_secs(15));
 
     Ok(())
 }
 
-pub fn block_download(
-    sockets: Vec<TcpStream>,
-    lista_headers: Vec<BlockHeader>,
-    node_sender: Arc<Mutex<gtk::glib::Sender<ChannelData>>>,
-) -> Result<Vec<Block>, Error> {
-    let len = lista_headers.len();
-    let half_len = len / 2;
-    let remainder_len = (len - half_len) / 3;
+/// Moves the chainstate to the downloaded block, disconnecting the blocks of the old branch if the
+/// best chain changed, and notifies the wallets and the indexes that are enabled.
+/// The last blocks are kept in case they must be disconnected
+fn connect_downloaded_block(
+    chainstate: &Mutex<Chainstate>,
+    header_chain: &Mutex<HeaderChain>,
+    recent_blocks: &mut HashMap<Vec<u8>, Block>,
+    wallets: &Mutex<HashMap<String, Wallet>>,
+    tx_index: &mut Option<TxIndex>,
+    address_index: &Option<Arc<Mutex<AddressIndex>>>,
+    block: Block,
+) {
+    let header_chain = header_chain.lock().unwrap();
+    let hash = block.header.calculate_hash();
+    let height = match header_chain.height_of(&hash) {
+        Some(height) => height,
+        None => return,
+    };
+    recent_blocks.insert(hash.clone(), block);
 
-    let first_slice = lista_headers[0..half_len].to_vec();
-    let second_slice = lista_headers[half_len..half_len + remainder_len].to_vec();
-    let third_slice =
-        lista_headers[half_len + remainder_len..half_len + 2 * remainder_len].to_vec();
-    let fourth_slice = lista_headers[half_len + 2 * remainder_len..].to_vec();
+    let result =
+        chainstate
+            .lock()
+            .unwrap()
+            .activate_best_chain(&header_chain, recent_blocks, &hash);
 
-    let divided_vecs = vec![first_slice, second_slice, third_slice, fourth_slice];
+    match result {
+        Ok(update) => {
+            if !update.disconnected.is_empty() {
+                println!(
+                    "Reorganizacion: se desconectaron {} bloques",
+                    update.disconnected.len()
+                );
+            }
 
-    let mut handles: Vec<JoinHandle<Vec<Block>>> = vec![];
+            let mut wallets = wallets.lock().unwrap();
+            for wallet in wallets.values_mut() {
+                for (block, undo) in &update.disconnected {
+                    rollback_wallet(wallet, block, undo);
+                }
+                for block in &update.connected {
+                    update_wallet(wallet, block.clone());
+                }
+            }
+            drop(wallets);
 
-    for i in 0..sockets.len() {
-        let socket: TcpStream = sockets[i].try_clone().unwrap();
+            if let Some(tx_index) = tx_index.as_mut() {
+                for (block, _) in &update.disconnected {
+                    if let Err(e) = tx_index.disconnect_block(block) {
+                        println!("No se pudo desindexar el bloque: {}", e);
+                    }
+                }
+                for block in &update.connected {
+                    if let Err(e) = tx_index.index_block(block) {
+                        println!("No se pudo indexar el bloque: {}", e);
+                    }
+                }
+            }
 
-        let lista_headers_copia = divided_vecs[i].clone();
+            if let Some(address_index) = address_index {
+                let mut address_index = address_index.lock().unwrap();
+                for (block, _) in &update.disconnected {
+                    if let Err(e) = address_index.disconnect_block(block) {
+                        println!("No se pudo desindexar el bloque: {}", e);
+                    }
+                }
+                for block in &update.connected {
+                    if let Err(e) = address_index.index_block(block) {
+                        println!("No se pudo indexar el bloque: {}", e);
+                    }
+                }
+            }
+        }
+        Err(error) => println!("No se pudo conectar el bloque {}: {:?}", height, error),
+    }
 
-        let node_sender_copy = Arc::clone(&node_sender);
+    recent_blocks.retain(|hash, _| {
+        header_chain
+            .height_of(hash)
+            .is_some_and(|block_height| block_height as usize + MAX_REORG_DEPTH > height as usize)
+    });
+}
 
-        let handle = thread::spawn(move || {
-            let mut lista_blocks: Vec<Block> = vec![];
+/// Height of the tip of the chainstate saved by a previous run, if it is in the best chain.
+/// A chainstate of another branch is discarded, as its blocks may not be stored
+fn saved_chainstate_height(
+    chainstate: &Mutex<Chainstate>,
+    header_chain: &HeaderChain,
+) -> Option<u32> {
+    let mut chainstate = chainstate.lock().unwrap();
+    let tip_hash = chainstate.tip_hash()?.clone();
+
+    if header_chain.is_in_best_chain(&tip_hash) {
+        let height = header_chain.height_of(&tip_hash)?;
+        println!(
+            "Se retoma la descarga de bloques desde la altura {}",
+            height
+        );
+        return Some(height);
+    }
 
-            for header in lista_headers_copia.clone() {
-                let block_total = get_block_by_hash(&header.prev_block_hash, &socket);
+    println!("El chainstate guardado no esta en la mejor cadena, se descarta");
+    *chainstate = Chainstate::new();
+    None
+}
 
-                match block_total {
-                    Ok(block) => {
-                        lista_blocks.push(block.clone());
-                    }
-                    Err(_header) => {}
-                }
+/// The node state is saved every some blocks and when the tip of the best chain is connected
+fn is_node_state_save_point(header_chain: &HeaderChain, hash: &[u8]) -> bool {
+    match header_chain.height_of(hash) {
+        Some(height) => {
+            height % NODE_STATE_SAVE_INTERVAL == 0 || header_chain.best_tip().hash == hash
+        }
+        None => false,
+    }
+}
 
-                let node_sender_blocked = node_sender_copy.lock().unwrap();
+/// Saves the chainstate and then the wallets, marked with the tip of that chainstate. Both are
+/// locked meanwhile, so the wallets saved are the ones updated up to the tip
+fn save_node_state(
+    data_dir: &str,
+    chainstate: &Mutex<Chainstate>,
+    wallets: &Mutex<HashMap<String, Wallet>>,
+) -> Result<(), Error> {
+    let wallets = wallets.lock().unwrap();
+    let chainstate = chainstate.lock().unwrap();
 
-                if socket.peer_addr().unwrap() == SocketAddr::from_str("127.0.0.1:18333").unwrap() {
-                    let _ =
-                        node_sender_blocked.send(ChannelData::DownloadDataBlocks(DownloadData {
-                            total_data: lista_headers_copia.len() as f64,
-                            received_data: lista_blocks.len() as f64,
-                        }));
-                }
+    save_chainstate(data_dir, &chainstate)?;
+    match chainstate.tip_hash() {
+        Some(tip_hash) => save_wallets(data_dir, tip_hash, &wallets),
+        None => Ok(()),
+    }
+}
 
-                drop(node_sender_blocked);
+/// Loads the header chain saved by the previous runs, so the download resumes from its tip
+fn load_stored_header_chain(data_dir: &str) -> HeaderChain {
+    let mut header_chain = HeaderChain::new(chain_params().clone());
+
+    match read_headers(data_dir) {
+        Ok(headers) => {
+            for header in headers {
+                let _ = header_chain.add_header(header);
+            }
+        }
+        Err(e) => println!("No se pudieron leer los headers guardados: {}", e),
+    }
+
+    println!(
+        "Headers guardados de ejecuciones anteriores ---> {}",
+        header_chain.best_height()
+    );
+
+    header_chain
+}
+
+/// Downloads the blocks of the headers from every peer, keeping a window of blocks in flight
+/// per peer. The blocks are sent through the block sender in height order.
+/// The headers the peers announce meanwhile are added to the header chain
+pub fn block_download(
+    sockets: Vec<TcpStream>,
+    lista_headers: Vec<BlockHeader>,
+    node_sender: Arc<Mutex<gtk::glib::Sender<ChannelData>>>,
+    tracker: &PeerTracker,
+    header_chain: &Arc<Mutex<HeaderChain>>,
+    block_sender: Sender<Block>,
+) -> Result<(), Error> {
+    let total_blocks = lista_headers.len();
+    let hashes: Vec<Vec<u8>> = lista_headers
+        .iter()
+        .map(|header| header.calculate_hash())
+        .collect();
+
+    let scheduler = Arc::new(Mutex::new(BlockScheduler::new(hashes)));
+
+    let mut handles: Vec<JoinHandle<()>> = vec![];
+
+    for socket in sockets {
+        let scheduler = Arc::clone(&scheduler);
+        let node_sender = Arc::clone(&node_sender);
+        let tracker = tracker.clone();
+        let header_chain = Arc::clone(header_chain);
+        let block_sender = block_sender.clone();
+
+        let handle = thread::spawn(move || {
+            if let Err(e) = download_blocks_from_peer(
+                &socket,
+                &scheduler,
+                &tracker,
+                &header_chain,
+                &block_sender,
+                &node_sender,
+                total_blocks,
+            ) {
+                println!("Error en descarga de blocks de un peer: {}", e);
             }
-            lista_blocks
         });
 
         handles.push(handle);
     }
 
-    let mut lista_blocks_total: Vec<Block> = Vec::new();
-
-    let mut i = 0;
-    let total_blocks_ignorados = 0;
     for handle in handles {
-        match handle.join() {
-            Ok(blocks) => {
-                println!(
-                    "Cantidad de bloques ignorados -> {}",
-                    divided_vecs[i].len() - blocks.len()
-                );
-                i += 1;
-                lista_blocks_total.extend(blocks);
-            }
-            Err(_) => println!("Hubo un error en descarga de blocks"),
+        if handle.join().is_err() {
+            println!("Hubo un error en descarga de blocks");
         }
     }
 
+    let scheduler_blocked = scheduler.lock().unwrap();
     println!(
-        "CANTIDAD TOTAL DE BLOCKS IGNORADOS ---> {}",
-        total_blocks_ignorados
-    );
-    println!(
-        "CANTIDAD DE BLOQUES A IMPRIMIR ---> {}",
-        lista_blocks_total.len()
+        "CANTIDAD DE BLOCKS DESCARGADOS ---> {} de {}",
+        scheduler_blocked.delivered(),
+        total_blocks
     );
-    println!("CANTIDAD DE SUBLISTAS RESUELTAS ---> {}", i);
 
-    Ok(lista_blocks_total)
+    if !scheduler_blocked.is_finished() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "No peer could deliver every block",
+        ));
+    }
+
+    Ok(())
 }
 
-pub fn get_block_by_hash(
-    prev_block_hash: &[u8],
-    mut socket: &TcpStream,
-) -> Result<Block, BlockHeader> {
-    let get_data_message = build_get_data_message(prev_block_hash).unwrap();
-
-    let tuple_get_data_response = match write_and_read_get_data_message(&get_data_message, socket) {
-        Ok(bytes_read_result) => bytes_read_result,
-        Err(_header) => {
-            return Err(BlockHeader::new(
-                0,
-                (0_u32).to_le_bytes().to_vec(),
-                (0_u32).to_le_bytes().to_vec(),
-                0,
-                0,
-                0,
-            ));
+/// Requests blocks to the peer while the scheduler has blocks for it, until every block
+/// was delivered or the peer disconnects
+fn download_blocks_from_peer(
+    socket: &TcpStream,
+    scheduler: &Mutex<BlockScheduler>,
+    tracker: &PeerTracker,
+    header_chain: &Mutex<HeaderChain>,
+    block_sender: &Sender<Block>,
+    node_sender: &Mutex<gtk::glib::Sender<ChannelData>>,
+    total_blocks: usize,
+) -> Result<(), Error> {
+    let peer = socket.peer_addr()?;
+    socket.set_read_timeout(Some(BLOCK_DOWNLOAD_TIMEOUT))?;
+
+    loop {
+        let mut scheduler_blocked = scheduler.lock().unwrap();
+        if scheduler_blocked.is_finished() || scheduler_blocked.is_stuck(&tracker.connected_peers())
+        {
+            break;
         }
-    };
 
-    let payload = tuple_get_data_response.0;
-    let long_buffer = tuple_get_data_response.1.len();
+        for slow_peer in scheduler_blocked.reassign_stale_requests(BLOCK_DOWNLOAD_TIMEOUT) {
+            println!(
+                "El peer {} es lento, se piden sus bloques a otro",
+                slow_peer
+            );
+        }
 
-    println!(
-        " ---------- LONGITUD DEL PAYLOAD EN EL HEDER DEL MESSAGE ---------- {}",
-        payload
-    );
-    println!(
-        " ---------- LONGITUD DEL BUFFER EN EL QUE ESTA EL PAYLOAD ---------- {}",
-        long_buffer
-    );
+        let requests = scheduler_blocked.next_requests(peer);
+        let in_flight = scheduler_blocked.in_flight_of(peer);
+        drop(scheduler_blocked);
 
-    println!(
-        " ---------- SE RECIBIO EL HEADER DEL MENSAJE BLOCK, PASO A LEER EL PAYLOAD ---------- "
-    );
+        if !requests.is_empty() {
+            if let Err(e) = write_peer_message(socket, &build_get_data_blocks_message(&requests)) {
+                scheduler.lock().unwrap().peer_disconnected(peer);
+                return Err(e);
+            }
+        }
+
+        if in_flight == 0 {
+            // Other peers are downloading the last blocks, wait in case they are reassigned
+            thread::sleep(IDLE_PEER_WAIT);
+            continue;
+        }
 
-    println!("Lectura post recibir HEADER de BLOCK message");
+        match read_peer_message(socket, tracker) {
+            Ok(NetworkMessage::Block(block)) => {
+                // A block whose transactions do not match its header, like a mutated one, has the
+                // hash of the honest block, so it is asked again to other peers
+                if !is_consistent_block(&block) {
+                    scheduler.lock().unwrap().peer_disconnected(peer);
+                    tracker.remove(&peer);
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Peer sent an invalid block",
+                    ));
+                }
 
-    let mut response_buffer: Vec<u8> = Vec::new();
+                let mut scheduler_blocked = scheduler.lock().unwrap();
+                scheduler_blocked.block_received(block);
 
-    if long_buffer > 24 {
-        response_buffer.extend_from_slice(&tuple_get_data_response.1[24..]);
-    }
+                // The lock is kept while sending so the blocks leave in height order
+                for block in scheduler_blocked.take_ready_blocks() {
+                    let _ = block_sender.send(block);
+                }
 
-    while response_buffer.len() < payload.try_into().unwrap() {
-        //
-        let mut response_buffer_aux = vec![0; 500000];
-        let read_size_payload_block_message = socket.read(&mut response_buffer_aux).unwrap();
-        response_buffer.extend_from_slice(&response_buffer_aux[..read_size_payload_block_message]);
+                let _ = node_sender
+                    .lock()
+                    .unwrap()
+                    .send(ChannelData::DownloadDataBlocks(DownloadData {
+                        total_data: total_blocks as f64,
+                        received_data: scheduler_blocked.delivered() as f64,
+                    }));
+            }
+            Ok(NetworkMessage::NotFound(inventory)) => {
+                let hashes: Vec<Vec<u8>> = inventory.into_iter().map(|entry| entry.hash).collect();
+                scheduler.lock().unwrap().blocks_not_found(peer, &hashes);
+            }
+            Ok(NetworkMessage::Headers(headers)) => {
+                add_announced_headers(header_chain, &headers);
+            }
+            Ok(NetworkMessage::Inv(inventory)) if announces_block(&inventory) => {
+                if let Err(e) = write_get_headers(socket, header_chain) {
+                    scheduler.lock().unwrap().peer_disconnected(peer);
+                    return Err(e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                scheduler.lock().unwrap().peer_disconnected(peer);
+                return Err(e);
+            }
+        }
     }
 
-    let mut block_header = parse_block(response_buffer.clone());
+    socket.set_read_timeout(None)?;
+    Ok(())
+}
 
-    let mut response_buffer_aux = &response_buffer[..payload as usize];
-    let mut tiene_ceros = has_consecutive_zeros(response_buffer_aux);
+/// Returns true if the header of the block is valid and matches its transactions
+fn is_consistent_block(block: &Block) -> bool {
+    block.header.is_valid() && matches!(block.is_valid(), Ok(true))
+}
 
-    let mut errores = 0;
+/// Returns true if some entry of the inventory is a block
+fn announces_block(inventory: &[Inventory]) -> bool {
+    inventory
+        .iter()
+        .any(|entry| entry.inv_type & !MSG_WITNESS_FLAG == MSG_BLOCK)
+}
 
-    while !block_header.is_valid() || tiene_ceros {
-        errores += 1;
+/// Asks the peer for the headers that follow the best chain of the header chain
+fn write_get_headers(socket: &TcpStream, header_chain: &Mutex<HeaderChain>) -> Result<(), Error> {
+    let locator = header_chain.lock().unwrap().block_locator();
+    write_peer_message(socket, &build_get_headers_message(locator)?)
+}
 
-        let get_data_message = build_get_data_message(prev_block_hash).unwrap();
+/// Adds the headers a peer announced to the header chain and stores the ones that were not known.
+/// Stops at the first header that is invalid or does not connect to the chain.
+/// Returns the amount of headers added
+fn add_announced_headers(header_chain: &Mutex<HeaderChain>, headers: &[BlockHeader]) -> usize {
+    let mut header_chain_blocked = header_chain.lock().unwrap();
+    let mut new_headers = vec![];
 
-        match write_and_read_get_data_message(&get_data_message, socket) {
-            Ok(_) => {}
-            Err(_header) => {
-                return Err(BlockHeader::new(
-                    0,
-                    (0_u32).to_le_bytes().to_vec(),
-                    (0_u32).to_le_bytes().to_vec(),
-                    0,
-                    0,
-                    0,
-                ));
+    for header in headers {
+        if !header.is_valid() {
+            println!("El peer anuncio un header invalido");
+            break;
+        }
+        if header_chain_blocked.contains(&header.calculate_hash()) {
+            continue;
+        }
+        match header_chain_blocked.add_header(header.clone()) {
+            Ok(_) => new_headers.push(header.clone()),
+            Err(e) => {
+                println!("El peer anuncio un header rechazado por la cadena: {:?}", e);
+                break;
             }
         }
+    }
 
-        println!("parseando header mensaje block --->");
+    // The lock is kept while storing so the headers are stored after their parents
+    if let Err(e) = append_headers(&get_data_dir(), &new_headers) {
+        println!("No se pudieron guardar los headers anunciados: {}", e);
+    }
 
-        println!("Lectura post recibir header de BLOCK message");
+    new_headers.len()
+}
 
-        response_buffer = vec![0; 500000];
-        socket.read_exact(&mut response_buffer).unwrap();
+/// Follows the blocks the peers announce after the download, one thread per peer, until every
+/// peer disconnects. The peers are asked to announce the new blocks with headers
+fn follow_announcements(
+    sockets: Vec<TcpStream>,
+    tracker: &PeerTracker,
+    header_chain: &Arc<Mutex<HeaderChain>>,
+    chainstate: &Arc<Mutex<Chainstate>>,
+    block_sender: Sender<Block>,
+) {
+    let mut handles: Vec<JoinHandle<()>> = vec![];
+
+    for socket in sockets {
+        let tracker = tracker.clone();
+        let header_chain = Arc::clone(header_chain);
+        let chainstate = Arc::clone(chainstate);
+        let block_sender = block_sender.clone();
 
-        response_buffer_aux = &response_buffer[..payload as usize];
-        tiene_ceros = has_consecutive_zeros(response_buffer_aux);
+        let handle = thread::spawn(move || {
+            if let Err(e) = follow_peer_announcements(
+                &socket,
+                &tracker,
+                &header_chain,
+                &chainstate,
+                &block_sender,
+            ) {
+                println!("Se dejan de seguir los anuncios de un peer: {}", e);
+            }
+        });
 
-        block_header = parse_block(response_buffer.clone());
+        handles.push(handle);
+    }
 
-        if errores >= 6 {
-            break;
+    for handle in handles {
+        if handle.join().is_err() {
+            println!("Hubo un error siguiendo los anuncios de bloques");
+        }
+    }
+}
+
+/// Reads the messages of the peer: the blocks announced with an inv are asked with a getheaders,
+/// the headers are added to the header chain, and the blocks of the best chain the chainstate
+/// does not have are requested and sent through the block sender to be connected
+fn follow_peer_announcements(
+    socket: &TcpStream,
+    tracker: &PeerTracker,
+    header_chain: &Mutex<HeaderChain>,
+    chainstate: &Mutex<Chainstate>,
+    block_sender: &Sender<Block>,
+) -> Result<(), Error> {
+    let mut requested: HashSet<Vec<u8>> = HashSet::new();
+
+    write_peer_message(socket, &NetworkMessage::SendHeaders.to_bytes())?;
+    write_get_headers(socket, header_chain)?;
+    request_missing_blocks(socket, header_chain, chainstate, &mut requested)?;
+
+    loop {
+        match read_peer_message(socket, tracker)? {
+            NetworkMessage::Inv(inventory) if announces_block(&inventory) => {
+                write_get_headers(socket, header_chain)?;
+            }
+            NetworkMessage::Headers(headers)
+                if add_announced_headers(header_chain, &headers) > 0 =>
+            {
+                request_missing_blocks(socket, header_chain, chainstate, &mut requested)?;
+            }
+            NetworkMessage::Block(block) => {
+                if !is_consistent_block(&block) {
+                    tracker.remove(&socket.peer_addr()?);
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Peer sent an invalid block",
+                    ));
+                }
+                let _ = block_sender.send(block);
+            }
+            NetworkMessage::NotFound(inventory) => {
+                for entry in inventory {
+                    requested.remove(&entry.hash);
+                }
+            }
+            _ => {}
         }
     }
+}
 
-    if errores >= 6 {
-        return Err(block_header);
+/// Requests the blocks of the best chain after the fork with the tip of the chainstate that were
+/// not requested yet, so the chainstate can move to the best tip, even when it is on another branch
+fn request_missing_blocks(
+    socket: &TcpStream,
+    header_chain: &Mutex<HeaderChain>,
+    chainstate: &Mutex<Chainstate>,
+    requested: &mut HashSet<Vec<u8>>,
+) -> Result<(), Error> {
+    let tip_hash = match chainstate.lock().unwrap().tip_hash() {
+        Some(tip_hash) => tip_hash.clone(),
+        None => return Ok(()),
+    };
+
+    let header_chain = header_chain.lock().unwrap();
+    let fork_height = match header_chain.fork_height(&tip_hash) {
+        Some(fork_height) => fork_height,
+        None => return Ok(()),
+    };
+
+    let hashes: Vec<Vec<u8>> = (fork_height + 1..=header_chain.best_height())
+        .filter_map(|height| header_chain.hash_at(height).cloned())
+        .filter(|hash| !requested.contains(hash))
+        .collect();
+    drop(header_chain);
+
+    if hashes.is_empty() {
+        return Ok(());
     }
+    write_peer_message(socket, &build_get_data_blocks_message(&hashes))?;
+    requested.extend(hashes);
+
+    Ok(())
+}
 
-    let transactions: Vec<Transaction> = parse_transactions(response_buffer).unwrap();
+pub fn get_block_by_hash(
+    prev_block_hash: &[u8],
+    socket: &TcpStream,
+    tracker: &PeerTracker,
+) -> Result<Block, BlockHeader> {
+    let empty_header = BlockHeader::new(
+        0,
+        (0_u32).to_le_bytes().to_vec(),
+        (0_u32).to_le_bytes().to_vec(),
+        0,
+        0,
+        0,
+    );
 
-    let block = Block::new(block_header, transactions.len(), transactions);
+    let get_data_message = match build_get_data_message(prev_block_hash) {
+        Ok(message) => message,
+        Err(_) => return Err(empty_header),
+    };
+
+    let block = match write_and_read_get_data_message(&get_data_message, socket, tracker) {
+        Ok(block) => block,
+        Err(_) => return Err(empty_header),
+    };
+
+    if !block.header.is_valid() {
+        return Err(block.header);
+    }
 
     Ok(block)
 }
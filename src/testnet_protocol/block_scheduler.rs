@@ -0,0 +1,317 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::components::block::Block;
+
+/// Most blocks requested to the same peer without having been received
+pub const MAX_BLOCKS_IN_FLIGHT_PER_PEER: usize = 16;
+
+/// Time a peer has to deliver a requested block before it is requested to another peer
+pub const BLOCK_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Represents a block requested to a peer and not received yet
+#[derive(Debug, Clone)]
+struct InFlightRequest {
+    peer: SocketAddr,
+    requested_at: Instant,
+}
+
+/// #TDA BlockScheduler
+/// Decides which blocks are requested to each peer. Keeps a window of blocks in flight
+/// per peer, requests again the blocks of the peers that are slow, disconnected or do not
+/// have them, and delivers the received blocks in height order
+#[derive(Debug)]
+pub struct BlockScheduler {
+    hashes: Vec<Vec<u8>>,
+    heights: HashMap<Vec<u8>, usize>,
+    pending: VecDeque<usize>,
+    in_flight: HashMap<usize, InFlightRequest>,
+    not_found: HashMap<usize, HashSet<SocketAddr>>,
+    received: BTreeMap<usize, Block>,
+    next_to_deliver: usize,
+}
+
+impl BlockScheduler {
+    /// Creates the scheduler of the blocks with the given hashes, sorted by height
+    pub fn new(hashes: Vec<Vec<u8>>) -> Self {
+        let heights = hashes
+            .iter()
+            .enumerate()
+            .map(|(index, hash)| (hash.clone(), index))
+            .collect();
+
+        BlockScheduler {
+            pending: (0..hashes.len()).collect(),
+            hashes,
+            heights,
+            in_flight: HashMap::new(),
+            not_found: HashMap::new(),
+            received: BTreeMap::new(),
+            next_to_deliver: 0,
+        }
+    }
+
+    /// Returns the hashes of the blocks to request to the peer, filling its window of blocks in flight
+    pub fn next_requests(&mut self, peer: SocketAddr) -> Vec<Vec<u8>> {
+        let in_flight = self.in_flight_of(peer);
+        let mut requests = vec![];
+        let mut skipped = vec![];
+
+        while in_flight + requests.len() < MAX_BLOCKS_IN_FLIGHT_PER_PEER {
+            let index = match self.pending.pop_front() {
+                Some(index) => index,
+                None => break,
+            };
+
+            let peer_does_not_have_it = self
+                .not_found
+                .get(&index)
+                .is_some_and(|peers| peers.contains(&peer));
+
+            if peer_does_not_have_it {
+                skipped.push(index);
+                continue;
+            }
+
+            self.in_flight.insert(
+                index,
+                InFlightRequest {
+                    peer,
+                    requested_at: Instant::now(),
+                },
+            );
+            requests.push(self.hashes[index].clone());
+        }
+
+        for index in skipped.into_iter().rev() {
+            self.pending.push_front(index);
+        }
+
+        requests
+    }
+
+    /// Amount of blocks requested to the peer and not received yet
+    pub fn in_flight_of(&self, peer: SocketAddr) -> usize {
+        self.in_flight
+            .values()
+            .filter(|request| request.peer == peer)
+            .count()
+    }
+
+    /// Registers a received block. Returns false if the block was not requested or
+    /// was already received from another peer
+    pub fn block_received(&mut self, block: Block) -> bool {
+        let index = match self.heights.get(&block.header.calculate_hash()) {
+            Some(index) => *index,
+            None => return false,
+        };
+
+        if index < self.next_to_deliver || self.received.contains_key(&index) {
+            return false;
+        }
+
+        // A block requested again after a timeout can still arrive from the slow peer
+        if self.in_flight.remove(&index).is_none() {
+            self.pending.retain(|pending| *pending != index);
+        }
+        self.received.insert(index, block);
+        true
+    }
+
+    /// Registers the blocks the peer answered it does not have, so they are requested to another peer
+    pub fn blocks_not_found(&mut self, peer: SocketAddr, hashes: &[Vec<u8>]) {
+        for hash in hashes {
+            if let Some(index) = self.heights.get(hash).copied() {
+                if self
+                    .in_flight
+                    .get(&index)
+                    .is_some_and(|request| request.peer == peer)
+                {
+                    self.in_flight.remove(&index);
+                    self.not_found.entry(index).or_default().insert(peer);
+                    self.pending.push_front(index);
+                }
+            }
+        }
+    }
+
+    /// Requests again the blocks in flight of a peer that disconnected
+    pub fn peer_disconnected(&mut self, peer: SocketAddr) {
+        let indexes: Vec<usize> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| request.peer == peer)
+            .map(|(index, _)| *index)
+            .collect();
+
+        self.requeue(indexes);
+    }
+
+    /// Requests again the blocks that were not delivered in the given time.
+    /// Returns the peers that were too slow
+    pub fn reassign_stale_requests(&mut self, timeout: Duration) -> Vec<SocketAddr> {
+        let stale: Vec<(usize, SocketAddr)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| request.requested_at.elapsed() > timeout)
+            .map(|(index, request)| (*index, request.peer))
+            .collect();
+
+        let mut slow_peers: Vec<SocketAddr> = stale.iter().map(|(_, peer)| *peer).collect();
+        slow_peers.sort();
+        slow_peers.dedup();
+
+        self.requeue(stale.into_iter().map(|(index, _)| index).collect());
+        slow_peers
+    }
+
+    fn requeue(&mut self, mut indexes: Vec<usize>) {
+        indexes.sort();
+        for index in indexes.into_iter().rev() {
+            self.in_flight.remove(&index);
+            self.pending.push_front(index);
+        }
+    }
+
+    /// Returns the received blocks that follow the last delivered one, in height order
+    pub fn take_ready_blocks(&mut self) -> Vec<Block> {
+        let mut blocks = vec![];
+
+        while let Some(block) = self.received.remove(&self.next_to_deliver) {
+            blocks.push(block);
+            self.next_to_deliver += 1;
+        }
+
+        blocks
+    }
+
+    /// Amount of blocks already delivered
+    pub fn delivered(&self) -> usize {
+        self.next_to_deliver
+    }
+
+    /// Returns true when every block was delivered
+    pub fn is_finished(&self) -> bool {
+        self.next_to_deliver == self.hashes.len()
+    }
+
+    /// Returns true if no peer can deliver the pending blocks
+    pub fn is_stuck(&self, peers: &[SocketAddr]) -> bool {
+        self.in_flight.is_empty()
+            && self.pending.iter().all(|index| {
+                self.not_found
+                    .get(index)
+                    .is_some_and(|refused| peers.iter().all(|peer| refused.contains(peer)))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::block_header::BlockHeader;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn blocks(amount: u32) -> Vec<Block> {
+        (0..amount)
+            .map(|nonce| {
+                let header = BlockHeader::new(1, vec![1; 32], vec![1; 32], 0, 0x207fffff, nonce);
+                Block::new(header, 0, vec![])
+            })
+            .collect()
+    }
+
+    fn scheduler(blocks: &[Block]) -> BlockScheduler {
+        BlockScheduler::new(
+            blocks
+                .iter()
+                .map(|block| block.header.calculate_hash())
+                .collect(),
+        )
+    }
+
+    #[test]
+    pub fn test_each_peer_gets_a_window_of_requests() {
+        let blocks = blocks(40);
+        let mut scheduler = scheduler(&blocks);
+
+        let first = scheduler.next_requests(peer(1));
+        let second = scheduler.next_requests(peer(2));
+
+        assert_eq!(first.len(), MAX_BLOCKS_IN_FLIGHT_PER_PEER);
+        assert_eq!(first[0], blocks[0].header.calculate_hash());
+        assert_eq!(second[0], blocks[16].header.calculate_hash());
+        assert!(scheduler.next_requests(peer(1)).is_empty());
+    }
+
+    #[test]
+    pub fn test_blocks_are_delivered_in_height_order() {
+        let blocks = blocks(3);
+        let mut scheduler = scheduler(&blocks);
+        scheduler.next_requests(peer(1));
+
+        assert!(scheduler.block_received(blocks[2].clone()));
+        assert!(scheduler.block_received(blocks[1].clone()));
+        assert!(scheduler.take_ready_blocks().is_empty());
+
+        assert!(scheduler.block_received(blocks[0].clone()));
+        let delivered: Vec<u32> = scheduler
+            .take_ready_blocks()
+            .iter()
+            .map(|block| block.header.nonce)
+            .collect();
+
+        assert_eq!(delivered, vec![0, 1, 2]);
+        assert!(scheduler.is_finished());
+    }
+
+    #[test]
+    pub fn test_requests_of_a_disconnected_peer_are_reassigned() {
+        let blocks = blocks(20);
+        let mut scheduler = scheduler(&blocks);
+        scheduler.next_requests(peer(1));
+        scheduler.next_requests(peer(2));
+
+        scheduler.peer_disconnected(peer(1));
+
+        let requests = scheduler.next_requests(peer(3));
+        assert_eq!(requests[0], blocks[0].header.calculate_hash());
+        assert_eq!(scheduler.in_flight_of(peer(1)), 0);
+    }
+
+    #[test]
+    pub fn test_not_found_blocks_are_requested_to_other_peers() {
+        let blocks = blocks(1);
+        let mut scheduler = scheduler(&blocks);
+        let requests = scheduler.next_requests(peer(1));
+
+        scheduler.blocks_not_found(peer(1), &requests);
+
+        assert!(scheduler.next_requests(peer(1)).is_empty());
+        assert!(scheduler.is_stuck(&[peer(1)]));
+        assert_eq!(scheduler.next_requests(peer(2)), requests);
+    }
+
+    #[test]
+    pub fn test_stale_requests_are_reassigned() {
+        let blocks = blocks(2);
+        let mut scheduler = scheduler(&blocks);
+        scheduler.next_requests(peer(1));
+
+        assert!(scheduler
+            .reassign_stale_requests(Duration::from_secs(60))
+            .is_empty());
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(
+            scheduler.reassign_stale_requests(Duration::ZERO),
+            vec![peer(1)]
+        );
+        assert_eq!(scheduler.next_requests(peer(2)).len(), 2);
+    }
+}
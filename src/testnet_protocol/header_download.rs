@@ -1,12 +1,9 @@
-use crate::components::block_header::BlockHeader;
-use crate::helpers::auxiliar_functions::{
-    bytes_to_hex, reverse_hash, string_to_reversed_bytes, u8_to_hex_string,
-};
+use crate::components::{block_header::BlockHeader, header_chain::HeaderChain};
+use crate::connection::peer_liveness::PeerTracker;
+use crate::helpers::auxiliar_functions::{bytes_to_hex, reverse_hash};
 use crate::interface::interfaz_grafica::{ChannelData, DownloadData};
 use crate::testnet_protocol::messages::message_builders::build_get_headers_message;
-use crate::testnet_protocol::messages::message_parsers::parse_block_header;
 use crate::testnet_protocol::messages::message_senders::write_and_read_get_headers_message;
-use std::collections::HashSet;
 
 use std::io::Error;
 use std::net::TcpStream;
@@ -14,87 +11,91 @@ use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
-/// Creates the get headers response from the block with its parse function
+/// Most headers a peer answers in a headers message, fewer means we reached its tip
+const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+/// Downloads the headers from the peers and adds them to the header chain.
+/// Each socket asks for the headers that follow the block locator of the chain until the
+/// peer answers fewer than 2000 headers, which means it reached its tip. The headers of a
+/// peer are validated on a copy of the chain and only added to the shared chain once they
+/// have the minimum chainwork. Returns the headers of the best chain
 pub fn header_download(
     sockets: &Vec<TcpStream>,
-    lista_seed_hashes: Vec<String>,
     node_sender: Arc<Mutex<gtk::glib::Sender<ChannelData>>>,
+    tracker: &PeerTracker,
+    header_chain: &Arc<Mutex<HeaderChain>>,
 ) -> Result<Vec<BlockHeader>, Error> {
     // vector con los handlers de cada thread
-    // JoinHandle<Vec<BlockHeader>>
-    let mut handles: Vec<JoinHandle<Vec<BlockHeader>>> = vec![];
+    let mut handles: Vec<JoinHandle<()>> = vec![];
 
-    for i in 0..sockets.len() {
+    for socket in sockets {
         // se spawnea un hilo por cada socket que haya
 
-        let hash_init: String = lista_seed_hashes[i].clone();
-        let hash_stop: String = lista_seed_hashes[i + 1].clone();
-        let mut socket: TcpStream = sockets[i].try_clone().unwrap();
+        let mut socket: TcpStream = socket.try_clone().unwrap();
 
         let node_sender_copy = Arc::clone(&node_sender);
+        let tracker = tracker.clone();
+        let header_chain = Arc::clone(header_chain);
 
         let handle = thread::spawn(move || {
-            let mut prox_hash = string_to_reversed_bytes(hash_init);
-
-            let mut set_hashes_descargados: HashSet<Vec<u8>> = HashSet::new();
-
-            let mut firt_iterarion = true;
-            let mut header_list: Vec<BlockHeader> = vec![];
-
-            while !set_hashes_descargados.contains(&string_to_reversed_bytes(hash_stop.clone())) {
-                let get_headers_msg = build_get_headers_message(prox_hash.clone()).unwrap();
-
-                let response_buffer: Vec<u8> =
-                    write_and_read_get_headers_message(&get_headers_msg, &mut socket).unwrap();
-
-                let mut cursor = 3;
-
-                for _i in 0..=1999 {
-                    if response_buffer.is_empty() {
+            let mut peer_chain = header_chain.lock().unwrap().clone();
+            let mut committed_height = peer_chain.best_height();
+
+            let mut prox_hash = peer_chain.best_tip().hash.clone();
+            let mut finished = false;
+
+            while !finished {
+                let get_headers_msg =
+                    build_get_headers_message(peer_chain.block_locator()).unwrap();
+
+                let headers: Vec<BlockHeader> = match write_and_read_get_headers_message(
+                    &get_headers_msg,
+                    &mut socket,
+                    &tracker,
+                ) {
+                    Ok(headers) => headers,
+                    Err(_) => break,
+                };
+
+                finished = headers.len() < MAX_HEADERS_PER_MESSAGE;
+                let previous_tip = peer_chain.best_tip().hash.clone();
+
+                for header in headers {
+                    if !header.is_valid() {
+                        println!("El peer envio un header invalido, se deja de descargar");
+                        finished = true;
                         break;
                     }
 
-                    let header: BlockHeader =
-                        parse_block_header(&response_buffer[cursor..cursor + 81]).unwrap();
-
-                    if header.is_valid() {
-                        if !set_hashes_descargados.contains(&header.prev_block_hash) {
-                            header_list.push(header.clone());
+                    match peer_chain.add_header(header) {
+                        Ok(hash) => prox_hash = hash,
+                        Err(e) => {
+                            println!("El peer envio un header rechazado por la cadena: {:?}", e);
+                            finished = true;
+                            break;
                         }
-                    } else {
-                        break;
-                    }
-
-                    if !firt_iterarion
-                        && u8_to_hex_string(&header.prev_block_hash)
-                            == *"000000000933EA01AD0EE984209779BAAEC3CED90FA3F408719526F8D77F4943"
-                    {
-                        break;
-                    }
-
-                    firt_iterarion = false;
-
-                    if !set_hashes_descargados.contains(&header.prev_block_hash)
-                        && header.is_valid()
-                    {
-                        set_hashes_descargados.insert(header.prev_block_hash.clone());
                     }
+                }
 
-                    prox_hash = header.prev_block_hash.clone();
+                // Headers that do not extend the best chain would be asked again forever
+                if peer_chain.best_tip().hash == previous_tip {
+                    finished = true;
+                }
 
-                    cursor += 81;
+                if peer_chain.has_minimum_chainwork() {
+                    committed_height =
+                        commit_peer_headers(&peer_chain, &header_chain, committed_height);
                 }
 
-                println!(
-                    "CANTIDAD DE HEADERS DESCARGADOS ---> {}",
-                    set_hashes_descargados.len()
-                );
+                let cantidad_headers = peer_chain.best_height();
+
+                println!("CANTIDAD DE HEADERS DESCARGADOS ---> {}", cantidad_headers);
 
                 let node_sender_blocked = node_sender_copy.lock().unwrap();
 
                 let _ = node_sender_blocked.send(ChannelData::DownloadData(DownloadData {
                     total_data: 2400000.0,
-                    received_data: set_hashes_descargados.len() as f64,
+                    received_data: cantidad_headers as f64,
                 }));
 
                 drop(node_sender_blocked);
@@ -105,20 +106,44 @@ pub fn header_download(
                 );
             }
 
-            header_list
+            if !peer_chain.has_minimum_chainwork() {
+                println!("Los headers del peer no tienen el trabajo minimo, se descartan");
+            }
         });
 
         handles.push(handle);
     }
 
-    let mut lista_headers_total: Vec<BlockHeader> = vec![];
-
     for handle in handles {
-        match handle.join() {
-            Ok(headers) => lista_headers_total.extend(headers),
-            Err(_) => println!("Hubo un error en descarga de headers"),
+        if handle.join().is_err() {
+            println!("Hubo un error en descarga de headers");
+        }
+    }
+
+    let header_chain_blocked = header_chain.lock().unwrap();
+
+    Ok(header_chain_blocked.best_chain_headers())
+}
+
+/// Adds to the shared chain the headers of the best chain of the peer above the given height,
+/// and returns the height up to which they were added
+fn commit_peer_headers(
+    peer_chain: &HeaderChain,
+    header_chain: &Mutex<HeaderChain>,
+    committed_height: u32,
+) -> u32 {
+    let mut header_chain_blocked = header_chain.lock().unwrap();
+
+    for height in committed_height + 1..=peer_chain.best_height() {
+        let header = match peer_chain.header_at(height) {
+            Some(header) => header.clone(),
+            None => return height - 1,
+        };
+
+        if header_chain_blocked.add_header(header).is_err() {
+            return height - 1;
         }
     }
 
-    Ok(lista_headers_total)
+    peer_chain.best_height()
 }
@@ -0,0 +1,171 @@
+use bitcoin_hashes::{sha256d, Hash};
+use std::io::{Error, ErrorKind, Read};
+
+use crate::configuration::chain_params::chain_params;
+
+/// Size of the header that precedes every P2P message
+pub const HEADER_SIZE: usize = 24;
+
+/// Biggest payload we accept from a peer (same limit as Bitcoin Core)
+pub const MAX_PAYLOAD_SIZE: u32 = 32 * 1024 * 1024;
+
+/// Represents a message read from the wire, already separated from the stream
+/// and with its checksum verified
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawMessage {
+    pub command: String,
+    pub payload: Vec<u8>,
+}
+
+/// Calculates the checksum of a payload: first 4 bytes of its double sha256
+pub fn payload_checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = sha256d::Hash::hash(payload);
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&hash[0..4]);
+    checksum
+}
+
+/// Decodes the 24 bytes header and returns the command, the payload length and the checksum
+pub fn decode_header(header: &[u8; HEADER_SIZE]) -> Result<(String, u32, [u8; 4]), Error> {
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    if magic != chain_params().magic {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid magic bytes: {:#010x}", magic),
+        ));
+    }
+
+    let command = String::from_utf8_lossy(&header[4..16])
+        .trim_end_matches('\x00')
+        .to_string();
+
+    let length = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Payload of {} bytes exceeds the maximum size", length),
+        ));
+    }
+
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&header[20..24]);
+
+    Ok((command, length, checksum))
+}
+
+/// Reads exactly one message from the stream: the header, and then the amount of bytes
+/// it announces as payload. Fails if the magic or the checksum do not match
+pub fn read_message<R: Read>(reader: &mut R) -> Result<RawMessage, Error> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+
+    let (command, length, checksum) = decode_header(&header)?;
+
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+
+    if payload_checksum(&payload) != checksum {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid checksum in {} message", command),
+        ));
+    }
+
+    Ok(RawMessage { command, payload })
+}
+
+/// Builds the whole message (header + payload) ready to be sent
+pub fn encode_message(command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut command_bytes = [0; 12];
+    for (i, byte) in command.bytes().take(12).enumerate() {
+        command_bytes[i] = byte;
+    }
+
+    let mut message: Vec<u8> = Vec::with_capacity(HEADER_SIZE + payload.len());
+    message.extend_from_slice(&chain_params().magic.to_le_bytes());
+    message.extend_from_slice(&command_bytes);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&payload_checksum(payload));
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Reads messages until one with the wanted command arrives, discarding the rest.
+/// Returns an error if a notfound arrives instead, because the peer will not send it
+pub fn read_until_command<R: Read>(reader: &mut R, command: &str) -> Result<RawMessage, Error> {
+    loop {
+        let message = read_message(reader)?;
+
+        if message.command == command {
+            return Ok(message);
+        }
+
+        if message.command == "notfound" {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Peer answered notfound while waiting for {}", command),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn test_read_message_returns_the_same_message_that_was_encoded() {
+        let bytes = encode_message("ping", &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut cursor = Cursor::new(bytes);
+
+        let message = read_message(&mut cursor).unwrap();
+
+        assert_eq!(message.command, "ping");
+        assert_eq!(message.payload, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    pub fn test_read_message_separates_two_messages_received_together() {
+        let mut bytes = encode_message("verack", &[]);
+        bytes.extend_from_slice(&encode_message("block", &vec![7; 150000]));
+        let mut cursor = Cursor::new(bytes);
+
+        let first = read_message(&mut cursor).unwrap();
+        let second = read_message(&mut cursor).unwrap();
+
+        assert_eq!(first.command, "verack");
+        assert!(first.payload.is_empty());
+        assert_eq!(second.command, "block");
+        assert_eq!(second.payload.len(), 150000);
+    }
+
+    #[test]
+    pub fn test_read_message_fails_with_invalid_checksum() {
+        let mut bytes = encode_message("tx", &[1, 2, 3]);
+        let last = bytes.len() - 1;
+        bytes[last] = 9;
+
+        assert!(read_message(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    pub fn test_read_message_fails_with_invalid_magic() {
+        let mut bytes = encode_message("tx", &[1, 2, 3]);
+        bytes[0] = 0;
+
+        assert!(read_message(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    pub fn test_read_until_command_skips_other_messages() {
+        let mut bytes = encode_message("sendheaders", &[]);
+        bytes.extend_from_slice(&encode_message("headers", &[0]));
+        let mut cursor = Cursor::new(bytes);
+
+        let message = read_until_command(&mut cursor, "headers").unwrap();
+
+        assert_eq!(message.command, "headers");
+        assert_eq!(message.payload, vec![0]);
+    }
+}
@@ -3,8 +3,7 @@ use crate::{
         block_header::BlockHeader,
         transaction::{Transaction, TransactionInput, TransactionOutput},
     },
-    helpers::auxiliar_functions::{get_flag_value, read_var_int, u8_to_hex_string},
-    logger::logger_impl::Logger,
+    helpers::auxiliar_functions::{get_flag_value, read_var_int},
 };
 use bitcoin_hashes::{sha256d, Hash};
 use std::io::{Error, ErrorKind};
@@ -15,24 +14,6 @@ fn sha256d(buffer: &[u8]) -> sha256d::Hash {
     sha256d::Hash::hash(buffer)
 }
 
-pub fn has_consecutive_zeros(arr: &[u8]) -> bool {
-    let mut count_zeros = 0;
-
-    for &num in arr {
-        if num == 0 {
-            count_zeros += 1;
-        } else {
-            count_zeros = 0;
-        }
-
-        if count_zeros == 50 {
-            return true;
-        }
-    }
-
-    false
-}
-
 pub fn parse_tx_out(
     tx_out_count: Result<(u64, usize), &str>,
     response_buffer: Vec<u8>,
@@ -91,24 +72,12 @@ pub fn parse_tx_in(
     for _ in 0..tx_in_count_value {
         let mut previous_output: [u8; 36] = [0; 36];
 
-        let prev_block_hash = &response_buffer[offset..offset + 32];
-
         previous_output[..32].copy_from_slice(&response_buffer[offset..offset + 32]);
         offset += 32;
 
-        let bytes_index = &response_buffer[offset..offset + 4];
-        let bytes_array_index: [u8; 4] = bytes_index
-            .try_into()
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
-        let index = u32::from_le_bytes(bytes_array_index);
-
         previous_output[32..].copy_from_slice(&response_buffer[offset..offset + 4]);
         offset += 4;
 
-        if index == 4294967295 && prev_block_hash.iter().all(|&x| x == 0) {
-            println!("    THIS is a coinbase transaction!\n");
-        }
-
         let script_length = read_var_int(&response_buffer[offset..]);
 
         offset += script_length
@@ -137,6 +106,7 @@ pub fn parse_tx_in(
             previous_output,
             script: sig_script.to_vec(),
             sequence,
+            witness: Vec::new(),
         };
 
         tx_in_list.push(tx_in);
@@ -144,81 +114,31 @@ pub fn parse_tx_in(
     Ok((tx_in_list, offset))
 }
 
-pub fn get_witness(response_buffer: Vec<u8>, mut offset: usize) -> Result<usize, Error> {
-    let witness_count = read_var_int(&response_buffer[offset..]);
+/// Reads the witness stack of an input. Returns the items and the offset after them
+pub fn get_witness(
+    response_buffer: &[u8],
+    mut offset: usize,
+) -> Result<(Vec<Vec<u8>>, usize), Error> {
+    let (witness_count, size) = read_var_int(response_buffer.get(offset..).unwrap_or_default())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    offset += size;
 
-    offset += witness_count
-        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
-        .1;
+    let mut witness = Vec::new();
 
-    for _ in 0..witness_count
-        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
-        .0
-    {
-        let witness_length = read_var_int(&response_buffer[offset..]);
+    for _ in 0..witness_count {
+        let (item_length, size) = read_var_int(response_buffer.get(offset..).unwrap_or_default())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        offset += size;
 
-        offset += witness_length
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
-            .1;
+        let item = response_buffer
+            .get(offset..offset + item_length as usize)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Witness item too short"))?;
+        offset += item_length as usize;
 
-        offset += witness_length
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
-            .0 as usize;
+        witness.push(item.to_vec());
     }
 
-    Ok(offset)
-}
-
-pub fn parse_block(response_buffer: Vec<u8>) -> BlockHeader {
-    println!("Parseando el PAYLOAD DE BLOCK ---> ");
-    let version = u32::from_le_bytes([
-        response_buffer[0],
-        response_buffer[1],
-        response_buffer[2],
-        response_buffer[3],
-    ]);
-    println!("    BLOCK Version --->  {}", version);
-
-    let prev_block_hash = &response_buffer[4..36];
-    let merkle_root = &response_buffer[36..68];
-    println!(
-        "    BLOCK Previous block hash: {:?}",
-        u8_to_hex_string(prev_block_hash)
-    );
-    println!("    BLOCK Merkle root: {:?}", u8_to_hex_string(merkle_root));
-
-    let timestamp = u32::from_le_bytes([
-        response_buffer[68],
-        response_buffer[69],
-        response_buffer[70],
-        response_buffer[71],
-    ]);
-    println!("    BLOCK Timestamp --->  {}", timestamp);
-
-    let bits = u32::from_le_bytes([
-        response_buffer[72],
-        response_buffer[73],
-        response_buffer[74],
-        response_buffer[75],
-    ]);
-    println!("    BLOCK Bits --->  {}", bits);
-
-    let nonce = u32::from_le_bytes([
-        response_buffer[76],
-        response_buffer[77],
-        response_buffer[78],
-        response_buffer[79],
-    ]);
-    println!("    BLOCK nonce --->  {}", nonce);
-
-    BlockHeader::new(
-        version,
-        prev_block_hash.to_vec(),
-        merkle_root.to_vec(),
-        timestamp,
-        bits,
-        nonce,
-    )
+    Ok((witness, offset))
 }
 
 pub fn parse_transaction(
@@ -231,14 +151,11 @@ pub fn parse_transaction(
         .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
     let transaction_version = u32::from_le_bytes(bytes_array);
 
-    let initial_offset = *offset;
-
     *offset += 4;
 
     let transaction_flag = get_flag_value(&response_buffer[*offset..]);
 
     if transaction_flag {
-        println!("    Tiene witness, +2 en offset");
         *offset += 2;
     }
 
@@ -248,7 +165,7 @@ pub fn parse_transaction(
         .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
         .1;
 
-    let (tx_in_list, _offset) = parse_tx_in(tx_in_count, response_buffer.clone(), *offset)
+    let (mut tx_in_list, _offset) = parse_tx_in(tx_in_count, response_buffer.clone(), *offset)
         .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
 
     *offset = _offset;
@@ -264,7 +181,11 @@ pub fn parse_transaction(
     *offset = _offset;
 
     if transaction_flag {
-        let _ = get_witness(response_buffer.clone(), *offset);
+        for input in tx_in_list.iter_mut() {
+            let (witness, _offset) = get_witness(&response_buffer, *offset)?;
+            input.witness = witness;
+            *offset = _offset;
+        }
     }
 
     let bytes_locktime = &response_buffer[*offset..*offset + 4];
@@ -275,13 +196,8 @@ pub fn parse_transaction(
 
     *offset += 4;
 
-    let raw_hash = sha256d(&response_buffer[initial_offset..*offset]);
-    let mut id = raw_hash.to_vec();
-    id.reverse(); // reverse the bytes to get the transaction id
-
-    println!("    TRANSACTION HASH --->  {} \n", raw_hash);
-    let transaction = Transaction {
-        hash: raw_hash,
+    let mut transaction = Transaction {
+        hash: sha256d(&[]), // temporary hash
         version: transaction_version,
         tx_in_count: tx_in_count
             .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
@@ -292,20 +208,22 @@ pub fn parse_transaction(
             .0 as u32,
         outputs: tx_out_list,
         lock_time,
-        txid: id,
+        txid: vec![],
     };
 
+    // The txid does not cover the witness, so it is hashed from the serialization without it
+    let raw_hash = transaction.compute_txid();
+    let mut id = raw_hash.to_vec();
+    id.reverse(); // reverse the bytes to get the transaction id
+
+    transaction.hash = raw_hash;
+    transaction.txid = id;
+
     Ok(transaction)
 }
 
 pub fn parse_transactions(response_buffer: Vec<u8>) -> Result<Vec<Transaction>, Error> {
     let tnx_count: Result<(u64, usize), &str> = read_var_int(&response_buffer[80..]);
-    println!(
-        "    CANTIDAD DE TRANSACCIONES RECIBIDAS {} ",
-        tnx_count
-            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
-            .0
-    );
 
     let mut transactions = Vec::<Transaction>::new();
 
@@ -327,34 +245,6 @@ pub fn parse_transactions(response_buffer: Vec<u8>) -> Result<Vec<Transaction>,
     Ok(transactions)
 }
 
-/// Parses the header version message
-pub fn parse_message(buffer: &[u8], logger: Option<&Logger>) -> Result<(String, u32), Error> {
-    if let Some(logger) = logger {
-        logger.log("")?;
-    }
-
-    let magic = u32::from_le_bytes(
-        buffer[..4]
-            .try_into()
-            .map_err(|e| Error::new(InvalidData, e))?,
-    );
-    let command = String::from_utf8_lossy(&buffer[4..16]);
-    let length = u32::from_le_bytes(
-        buffer[16..20]
-            .try_into()
-            .map_err(|e| Error::new(InvalidData, e))?,
-    );
-    let checksum = &buffer[20..24];
-
-    println!("Message:");
-    println!("    Magic: {}", magic);
-    println!("    COMMAND: {}", command.trim_end_matches('\x00'));
-    println!("    PAYLOAD LENGHT: {}", length);
-    println!("    Checksum: {:?}", checksum);
-
-    Ok((command.trim_end_matches('\x00').to_string(), length))
-}
-
 /// Parses the block header so the data is seen properly
 pub fn parse_block_header(block_header: &[u8]) -> Result<BlockHeader, Error> {
     let version = u32::from_le_bytes(
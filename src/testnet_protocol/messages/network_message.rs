@@ -0,0 +1,591 @@
+use std::io::{Error, ErrorKind, Read};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::{
+    components::{block::Block, block_header::BlockHeader, transaction::Transaction},
+    helpers::auxiliar_functions::{read_var_int, serialize_var_int},
+    testnet_protocol::messages::{
+        message_codec::{encode_message, read_message, RawMessage},
+        message_parsers::{parse_block_header, parse_transaction, parse_transactions},
+    },
+};
+
+/// Inventory type of a transaction
+pub const MSG_TX: u32 = 1;
+/// Inventory type of a block
+pub const MSG_BLOCK: u32 = 2;
+/// Flag of the inventory types that ask for the witness data too (BIP144)
+pub const MSG_WITNESS_FLAG: u32 = 1 << 30;
+/// Inventory type of a transaction with its witness data
+pub const MSG_WITNESS_TX: u32 = MSG_TX | MSG_WITNESS_FLAG;
+/// Inventory type of a block with the witness data of its transactions
+pub const MSG_WITNESS_BLOCK: u32 = MSG_BLOCK | MSG_WITNESS_FLAG;
+
+/// #ENUM NetworkMessage
+/// Represents every message of the protocol that the node knows how to send or receive
+#[derive(Debug, Clone)]
+pub enum NetworkMessage {
+    Version(VersionMessage),
+    Verack,
+    Ping(u64),
+    Pong(u64),
+    Inv(Vec<Inventory>),
+    GetData(Vec<Inventory>),
+    NotFound(Vec<Inventory>),
+    GetHeaders(GetHeadersMessage),
+    Headers(Vec<BlockHeader>),
+    Block(Block),
+    Tx(Transaction),
+    Addr(Vec<NetworkAddress>),
+    GetAddr,
+    SendHeaders,
+    Unknown(RawMessage),
+}
+
+/// Payload of the version message
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionMessage {
+    pub version: i32,
+    pub services: u64,
+    pub timestamp: i64,
+    pub receiver: NetworkAddress,
+    pub sender: NetworkAddress,
+    pub nonce: u64,
+    pub user_agent: String,
+    pub start_height: i32,
+    pub relay: bool,
+}
+
+/// Address of a node, as sent in version and addr messages.
+/// The time is only serialized inside addr messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkAddress {
+    pub time: u32,
+    pub services: u64,
+    pub ip: Ipv6Addr,
+    pub port: u16,
+}
+
+/// Entry of the inv, getdata and notfound messages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inventory {
+    pub inv_type: u32,
+    pub hash: Vec<u8>,
+}
+
+/// Payload of the getheaders message
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetHeadersMessage {
+    pub version: u32,
+    pub locator_hashes: Vec<Vec<u8>>,
+    pub hash_stop: Vec<u8>,
+}
+
+impl NetworkAddress {
+    /// Creates the address of an IPv4 node
+    pub fn from_ipv4(ip: Ipv4Addr, port: u16, services: u64) -> Self {
+        NetworkAddress {
+            time: 0,
+            services,
+            ip: ip.to_ipv6_mapped(),
+            port,
+        }
+    }
+
+    fn serialize(&self, with_time: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if with_time {
+            bytes.extend_from_slice(&self.time.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.services.to_le_bytes());
+        bytes.extend_from_slice(&self.ip.octets());
+        bytes.extend_from_slice(&self.port.to_be_bytes());
+        bytes
+    }
+
+    fn deserialize(reader: &mut PayloadReader, with_time: bool) -> Result<Self, Error> {
+        let time = if with_time { reader.read_u32()? } else { 0 };
+        let services = reader.read_u64()?;
+        let mut octets = [0; 16];
+        octets.copy_from_slice(reader.read_bytes(16)?);
+        let port = u16::from_be_bytes([reader.read_u8()?, reader.read_u8()?]);
+
+        Ok(NetworkAddress {
+            time,
+            services,
+            ip: Ipv6Addr::from(octets),
+            port,
+        })
+    }
+}
+
+impl Inventory {
+    pub fn new(inv_type: u32, hash: Vec<u8>) -> Self {
+        Inventory { inv_type, hash }
+    }
+}
+
+impl NetworkMessage {
+    /// Returns the command that identifies the message in the header
+    pub fn command(&self) -> &str {
+        match self {
+            NetworkMessage::Version(_) => "version",
+            NetworkMessage::Verack => "verack",
+            NetworkMessage::Ping(_) => "ping",
+            NetworkMessage::Pong(_) => "pong",
+            NetworkMessage::Inv(_) => "inv",
+            NetworkMessage::GetData(_) => "getdata",
+            NetworkMessage::NotFound(_) => "notfound",
+            NetworkMessage::GetHeaders(_) => "getheaders",
+            NetworkMessage::Headers(_) => "headers",
+            NetworkMessage::Block(_) => "block",
+            NetworkMessage::Tx(_) => "tx",
+            NetworkMessage::Addr(_) => "addr",
+            NetworkMessage::GetAddr => "getaddr",
+            NetworkMessage::SendHeaders => "sendheaders",
+            NetworkMessage::Unknown(raw) => &raw.command,
+        }
+    }
+
+    /// Serializes only the payload of the message
+    pub fn serialize_payload(&self) -> Vec<u8> {
+        match self {
+            NetworkMessage::Version(version) => serialize_version(version),
+            NetworkMessage::Verack | NetworkMessage::GetAddr | NetworkMessage::SendHeaders => {
+                Vec::new()
+            }
+            NetworkMessage::Ping(nonce) | NetworkMessage::Pong(nonce) => {
+                nonce.to_le_bytes().to_vec()
+            }
+            NetworkMessage::Inv(inventory)
+            | NetworkMessage::GetData(inventory)
+            | NetworkMessage::NotFound(inventory) => serialize_inventory(inventory),
+            NetworkMessage::GetHeaders(get_headers) => serialize_get_headers(get_headers),
+            NetworkMessage::Headers(headers) => {
+                let mut payload = serialize_var_int(headers.len() as u64);
+                for header in headers {
+                    payload.extend_from_slice(&header.serialize());
+                    payload.push(0x00);
+                }
+                payload
+            }
+            NetworkMessage::Block(block) => block.serialize(),
+            NetworkMessage::Tx(tx) => tx.serialize(),
+            NetworkMessage::Addr(addresses) => {
+                let mut payload = serialize_var_int(addresses.len() as u64);
+                for address in addresses {
+                    payload.extend_from_slice(&address.serialize(true));
+                }
+                payload
+            }
+            NetworkMessage::Unknown(raw) => raw.payload.clone(),
+        }
+    }
+
+    /// Serializes the whole message (header + payload) ready to be written in a socket
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_message(self.command(), &self.serialize_payload())
+    }
+
+    /// Builds the typed message from a message read from the wire
+    pub fn deserialize(raw: RawMessage) -> Result<NetworkMessage, Error> {
+        let mut reader = PayloadReader::new(&raw.payload);
+
+        let message = match raw.command.as_str() {
+            "version" => NetworkMessage::Version(deserialize_version(&mut reader)?),
+            "verack" => NetworkMessage::Verack,
+            "ping" => NetworkMessage::Ping(reader.read_u64()?),
+            "pong" => NetworkMessage::Pong(reader.read_u64()?),
+            "inv" => NetworkMessage::Inv(deserialize_inventory(&mut reader)?),
+            "getdata" => NetworkMessage::GetData(deserialize_inventory(&mut reader)?),
+            "notfound" => NetworkMessage::NotFound(deserialize_inventory(&mut reader)?),
+            "getheaders" => NetworkMessage::GetHeaders(deserialize_get_headers(&mut reader)?),
+            "headers" => NetworkMessage::Headers(deserialize_headers(&mut reader)?),
+            "block" => NetworkMessage::Block(deserialize_block(raw.payload)?),
+            "tx" => NetworkMessage::Tx(parse_transaction(raw.payload, &mut 0)?),
+            "addr" => {
+                let count = reader.read_var_int()?;
+                let mut addresses = Vec::new();
+                for _ in 0..count {
+                    addresses.push(NetworkAddress::deserialize(&mut reader, true)?);
+                }
+                NetworkMessage::Addr(addresses)
+            }
+            "getaddr" => NetworkMessage::GetAddr,
+            "sendheaders" => NetworkMessage::SendHeaders,
+            _ => NetworkMessage::Unknown(raw),
+        };
+
+        Ok(message)
+    }
+}
+
+/// Reads the next message of the stream and returns it already typed
+pub fn read_network_message<R: Read>(reader: &mut R) -> Result<NetworkMessage, Error> {
+    NetworkMessage::deserialize(read_message(reader)?)
+}
+
+fn serialize_version(version: &VersionMessage) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&version.version.to_le_bytes());
+    payload.extend_from_slice(&version.services.to_le_bytes());
+    payload.extend_from_slice(&version.timestamp.to_le_bytes());
+    payload.extend_from_slice(&version.receiver.serialize(false));
+    payload.extend_from_slice(&version.sender.serialize(false));
+    payload.extend_from_slice(&version.nonce.to_le_bytes());
+    payload.extend_from_slice(&serialize_var_int(version.user_agent.len() as u64));
+    payload.extend_from_slice(version.user_agent.as_bytes());
+    payload.extend_from_slice(&version.start_height.to_le_bytes());
+    payload.push(version.relay as u8);
+    payload
+}
+
+fn deserialize_version(reader: &mut PayloadReader) -> Result<VersionMessage, Error> {
+    let version = reader.read_u32()? as i32;
+    let services = reader.read_u64()?;
+    let timestamp = reader.read_u64()? as i64;
+    let receiver = NetworkAddress::deserialize(reader, false)?;
+    let sender = NetworkAddress::deserialize(reader, false)?;
+    let nonce = reader.read_u64()?;
+    let user_agent_length = reader.read_var_int()? as usize;
+    let user_agent = String::from_utf8_lossy(reader.read_bytes(user_agent_length)?).to_string();
+    let start_height = reader.read_u32()? as i32;
+    // relay is optional in older versions of the protocol
+    let relay = reader.read_u8().map(|relay| relay != 0).unwrap_or(true);
+
+    Ok(VersionMessage {
+        version,
+        services,
+        timestamp,
+        receiver,
+        sender,
+        nonce,
+        user_agent,
+        start_height,
+        relay,
+    })
+}
+
+fn serialize_inventory(inventory: &[Inventory]) -> Vec<u8> {
+    let mut payload = serialize_var_int(inventory.len() as u64);
+    for entry in inventory {
+        payload.extend_from_slice(&entry.inv_type.to_le_bytes());
+        payload.extend_from_slice(&entry.hash);
+    }
+    payload
+}
+
+fn deserialize_inventory(reader: &mut PayloadReader) -> Result<Vec<Inventory>, Error> {
+    let count = reader.read_var_int()?;
+    let mut inventory = Vec::new();
+    for _ in 0..count {
+        let inv_type = reader.read_u32()?;
+        let hash = reader.read_bytes(32)?.to_vec();
+        inventory.push(Inventory { inv_type, hash });
+    }
+    Ok(inventory)
+}
+
+fn serialize_get_headers(get_headers: &GetHeadersMessage) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&get_headers.version.to_le_bytes());
+    payload.extend_from_slice(&serialize_var_int(get_headers.locator_hashes.len() as u64));
+    for hash in &get_headers.locator_hashes {
+        payload.extend_from_slice(hash);
+    }
+    payload.extend_from_slice(&get_headers.hash_stop);
+    payload
+}
+
+fn deserialize_get_headers(reader: &mut PayloadReader) -> Result<GetHeadersMessage, Error> {
+    let version = reader.read_u32()?;
+    let count = reader.read_var_int()?;
+    let mut locator_hashes = Vec::new();
+    for _ in 0..count {
+        locator_hashes.push(reader.read_bytes(32)?.to_vec());
+    }
+    let hash_stop = reader.read_bytes(32)?.to_vec();
+
+    Ok(GetHeadersMessage {
+        version,
+        locator_hashes,
+        hash_stop,
+    })
+}
+
+fn deserialize_headers(reader: &mut PayloadReader) -> Result<Vec<BlockHeader>, Error> {
+    let count = reader.read_var_int()?;
+    let mut headers = Vec::new();
+    for _ in 0..count {
+        headers.push(parse_block_header(reader.read_bytes(80)?)?);
+        // transaction count, always 0 in a headers message
+        reader.read_var_int()?;
+    }
+    Ok(headers)
+}
+
+/// Parses a block in its wire serialization, the payload of the block message
+pub fn deserialize_block(payload: Vec<u8>) -> Result<Block, Error> {
+    if payload.len() < 81 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Block message too short",
+        ));
+    }
+
+    let header = parse_block_header(&payload[..80])?;
+    let txns = parse_transactions(payload)?;
+
+    Ok(Block::new(header, txns.len(), txns))
+}
+
+/// Cursor over a payload that fails instead of panicking when the data ends early
+pub struct PayloadReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        PayloadReader { data, offset: 0 }
+    }
+
+    pub fn read_bytes(&mut self, amount: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + amount)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Payload too short"))?;
+        self.offset += amount;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_var_int(&mut self) -> Result<u64, Error> {
+        let (value, size) = read_var_int(&self.data[self.offset.min(self.data.len())..])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.offset += size;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::transaction::{TransactionInput, TransactionOutput};
+    use bitcoin_hashes::Hash;
+    use std::io::Cursor;
+
+    fn round_trip(message: &NetworkMessage) -> NetworkMessage {
+        let mut cursor = Cursor::new(message.to_bytes());
+        read_network_message(&mut cursor).unwrap()
+    }
+
+    fn header() -> BlockHeader {
+        BlockHeader::new(
+            2,
+            vec![1; 32],
+            vec![2; 32],
+            1296688602,
+            0x1d00ffff,
+            414098458,
+        )
+    }
+
+    #[test]
+    pub fn test_version_message_round_trip() {
+        let version = VersionMessage {
+            version: 70015,
+            services: 1,
+            timestamp: 1688342400,
+            receiver: NetworkAddress::from_ipv4(Ipv4Addr::new(127, 0, 0, 1), 18333, 1),
+            sender: NetworkAddress::from_ipv4(Ipv4Addr::new(10, 0, 0, 2), 18333, 1),
+            nonce: 99,
+            user_agent: "/tp-bitcoin:0.1.0/".to_string(),
+            start_height: 2400000,
+            relay: true,
+        };
+
+        match round_trip(&NetworkMessage::Version(version.clone())) {
+            NetworkMessage::Version(parsed) => assert_eq!(parsed, version),
+            _ => panic!("Expected a version message"),
+        }
+    }
+
+    #[test]
+    pub fn test_ping_and_pong_round_trip() {
+        assert!(matches!(
+            round_trip(&NetworkMessage::Ping(1234)),
+            NetworkMessage::Ping(1234)
+        ));
+        assert!(matches!(
+            round_trip(&NetworkMessage::Pong(1234)),
+            NetworkMessage::Pong(1234)
+        ));
+    }
+
+    #[test]
+    pub fn test_inventory_messages_round_trip() {
+        let inventory = vec![
+            Inventory::new(MSG_BLOCK, vec![3; 32]),
+            Inventory::new(MSG_TX, vec![4; 32]),
+        ];
+
+        match round_trip(&NetworkMessage::GetData(inventory.clone())) {
+            NetworkMessage::GetData(parsed) => assert_eq!(parsed, inventory),
+            _ => panic!("Expected a getdata message"),
+        }
+        match round_trip(&NetworkMessage::NotFound(inventory.clone())) {
+            NetworkMessage::NotFound(parsed) => assert_eq!(parsed, inventory),
+            _ => panic!("Expected a notfound message"),
+        }
+    }
+
+    #[test]
+    pub fn test_getheaders_and_headers_round_trip() {
+        let get_headers = GetHeadersMessage {
+            version: 70015,
+            locator_hashes: vec![vec![5; 32], vec![6; 32]],
+            hash_stop: vec![0; 32],
+        };
+        match round_trip(&NetworkMessage::GetHeaders(get_headers.clone())) {
+            NetworkMessage::GetHeaders(parsed) => assert_eq!(parsed, get_headers),
+            _ => panic!("Expected a getheaders message"),
+        }
+
+        match round_trip(&NetworkMessage::Headers(vec![header(), header()])) {
+            NetworkMessage::Headers(parsed) => {
+                assert_eq!(parsed.len(), 2);
+                assert_eq!(parsed[0].serialize(), header().serialize());
+            }
+            _ => panic!("Expected a headers message"),
+        }
+    }
+
+    #[test]
+    pub fn test_block_round_trip() {
+        let tx = Transaction {
+            hash: bitcoin_hashes::sha256d::Hash::hash(&[0; 32]),
+            version: 1,
+            tx_in_count: 1,
+            inputs: vec![TransactionInput {
+                previous_output: [8; 36],
+                script: vec![0x51, 0x52],
+                sequence: 0xffffffff,
+                witness: vec![],
+            }],
+            tx_out_count: 1,
+            outputs: vec![TransactionOutput {
+                value: 5000,
+                script_pubkey: vec![0x76, 0xa9],
+            }],
+            lock_time: 0,
+            txid: vec![],
+        };
+        let block = Block::new(header(), 1, vec![tx.clone()]);
+
+        match round_trip(&NetworkMessage::Block(block.clone())) {
+            NetworkMessage::Block(parsed) => {
+                assert_eq!(parsed.txn_count, 1);
+                assert_eq!(parsed.serialize(), block.serialize());
+            }
+            _ => panic!("Expected a block message"),
+        }
+        match round_trip(&NetworkMessage::Tx(tx.clone())) {
+            NetworkMessage::Tx(parsed) => assert_eq!(parsed.serialize(), tx.serialize()),
+            _ => panic!("Expected a tx message"),
+        }
+    }
+
+    #[test]
+    pub fn test_segwit_transaction_round_trip() {
+        let tx = Transaction {
+            hash: bitcoin_hashes::sha256d::Hash::hash(&[0; 32]),
+            version: 2,
+            tx_in_count: 2,
+            inputs: vec![
+                TransactionInput {
+                    previous_output: [8; 36],
+                    script: vec![],
+                    sequence: 0xfffffffd,
+                    witness: vec![vec![0x30; 71], vec![0x02; 33]],
+                },
+                TransactionInput {
+                    previous_output: [9; 36],
+                    script: vec![0x51],
+                    sequence: 0xffffffff,
+                    witness: vec![],
+                },
+            ],
+            tx_out_count: 1,
+            outputs: vec![TransactionOutput {
+                value: 5000,
+                script_pubkey: vec![0x00, 0x14],
+            }],
+            lock_time: 7,
+            txid: vec![],
+        };
+
+        match round_trip(&NetworkMessage::Tx(tx.clone())) {
+            NetworkMessage::Tx(parsed) => {
+                assert_eq!(parsed.serialize(), tx.serialize());
+                assert_eq!(parsed.inputs[0].witness, tx.inputs[0].witness);
+                assert_eq!(parsed.lock_time, 7);
+                assert_eq!(parsed.hash, tx.compute_txid());
+            }
+            _ => panic!("Expected a tx message"),
+        }
+
+        // The txid does not change with the witness, the wtxid does
+        assert_eq!(tx.compute_txid(), tx.without_witness().compute_txid());
+        assert_eq!(tx.compute_txid(), tx.without_witness().compute_wtxid());
+        assert_ne!(tx.compute_txid(), tx.compute_wtxid());
+    }
+
+    #[test]
+    pub fn test_addr_round_trip() {
+        let mut address = NetworkAddress::from_ipv4(Ipv4Addr::new(1, 2, 3, 4), 18333, 9);
+        address.time = 1688342400;
+
+        match round_trip(&NetworkMessage::Addr(vec![address.clone()])) {
+            NetworkMessage::Addr(parsed) => assert_eq!(parsed, vec![address]),
+            _ => panic!("Expected an addr message"),
+        }
+    }
+
+    #[test]
+    pub fn test_unknown_command_is_kept_as_raw_message() {
+        let raw = RawMessage {
+            command: "feefilter".to_string(),
+            payload: vec![1, 0, 0, 0, 0, 0, 0, 0],
+        };
+
+        match round_trip(&NetworkMessage::Unknown(raw.clone())) {
+            NetworkMessage::Unknown(parsed) => assert_eq!(parsed, raw),
+            _ => panic!("Expected an unknown message"),
+        }
+    }
+
+    #[test]
+    pub fn test_truncated_payload_returns_error() {
+        let raw = RawMessage {
+            command: "getheaders".to_string(),
+            payload: vec![1, 0, 0, 0, 1, 7],
+        };
+
+        assert!(NetworkMessage::deserialize(raw).is_err());
+    }
+}
@@ -1,7 +1,9 @@
 pub mod messages {
     pub mod message_builders;
+    pub mod message_codec;
     pub mod message_parsers;
     pub mod message_senders;
+    pub mod network_message;
 }
 
 pub mod client_handlers {
@@ -11,5 +13,6 @@ pub mod client_handlers {
 }
 
 pub mod block_download;
+pub mod block_scheduler;
 pub mod broadcasting;
 pub mod header_download;